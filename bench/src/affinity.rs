@@ -0,0 +1,342 @@
+/// Placement of benchmark threads on CPUs.
+///
+/// The cost of the reclamation schemes is mostly the cost of moving cache lines between cores, so
+/// where the threads run matters a lot, and if we let the scheduler decide, it changes from run
+/// to run. A `Placement` decides which CPU each thread of a `ThreadBencher` is pinned to.
+///
+/// We read the topology from `/sys/devices/system/cpu`, restrict it to the CPUs we may run on
+/// with `sched_getaffinity`, and pin with `sched_setaffinity`, so pinning only works on Linux.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// How to place the threads of a benchmark. If there are more threads than CPUs in the
+/// placement, the threads wrap around.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placement {
+    /// Do not pin the threads.
+    None,
+    /// Fill one socket before the next, and all hardware threads of a core before the next core.
+    Compact,
+    /// Spread the threads over the sockets, and over the physical cores of each socket before
+    /// using their other hardware threads.
+    Scatter,
+    /// One thread on each physical core, filling one socket before the next.
+    PhysicalCores,
+    /// Pin thread `i` to the `i`th CPU of the list.
+    Cpus(Vec<usize>),
+}
+
+/// A logical CPU, as the kernel sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cpu {
+    pub id: usize,
+    /// The socket.
+    pub package: usize,
+    /// The physical core, which is only unique within a socket.
+    pub core: usize,
+}
+
+lazy_static! {
+    static ref DEFAULT_PLACEMENT: Mutex<Placement> = Mutex::new(Placement::None);
+}
+
+/// Set the placement used by `ThreadBencher::new`.
+pub fn set_default_placement(placement: Placement) {
+    *DEFAULT_PLACEMENT.lock().unwrap() = placement;
+}
+
+pub fn default_placement() -> Placement {
+    DEFAULT_PLACEMENT.lock().unwrap().clone()
+}
+
+/// Parse a list of numbers and ranges like `0,2,4-7`, where `a-b` is all numbers from `a` to `b`,
+/// as in `/sys/devices/system/cpu/online`. The runner also uses this for its thread counts.
+pub fn parse_list(s: &str) -> Result<Vec<usize>, String> {
+    let parse = |n: &str| {
+        n.trim().parse::<usize>().map_err(|_| format!("'{}' is not a number", n))
+    };
+    let mut list = vec![];
+    for part in s.trim().split(',') {
+        let mut ends = part.splitn(2, '-');
+        let low = parse(ends.next().unwrap())?;
+        let high = match ends.next() {
+            Some(high) => parse(high)?,
+            None => low,
+        };
+        if high < low {
+            return Err(format!("'{}' is an empty range", part));
+        }
+        list.extend(low..high + 1);
+    }
+    Ok(list)
+}
+
+fn read_number(path: &str) -> Option<usize> {
+    let mut s = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut s))
+        .ok()
+        .and_then(|_| s.trim().parse().ok())
+}
+
+/// The online CPUs of this machine that the calling thread may run on, as given by its affinity
+/// mask, which for instance `taskset` or a cgroup restricts. If we cannot read the topology, we
+/// return one CPU, so that all threads end up on CPU 0.
+///
+/// Since we use the mask of the calling thread, this must be called before it is pinned.
+pub fn topology() -> Vec<Cpu> {
+    let mut online = String::new();
+    let mut cpus = File::open("/sys/devices/system/cpu/online")
+        .and_then(|mut f| f.read_to_string(&mut online))
+        .map_err(|e| e.to_string())
+        .and_then(|_| parse_list(&online))
+        .unwrap_or(vec![0]);
+    if let Ok(allowed) = allowed_cpus() {
+        cpus.retain(|c| allowed.contains(c));
+    }
+    if cpus.is_empty() {
+        cpus.push(0);
+    }
+    cpus.into_iter()
+        .map(|id| {
+            let dir = format!("/sys/devices/system/cpu/cpu{}/topology", id);
+            Cpu {
+                id: id,
+                package: read_number(&format!("{}/physical_package_id", dir)).unwrap_or(0),
+                core: read_number(&format!("{}/core_id", dir)).unwrap_or(id),
+            }
+        })
+        .collect()
+}
+
+/// The CPUs of `topology` in the order we hand them out to threads.
+fn order(placement: &Placement, topology: &[Cpu]) -> Vec<usize> {
+    let mut cpus = topology.to_vec();
+    cpus.sort_by_key(|c| (c.package, c.core, c.id));
+    // The index of each CPU among the hardware threads of its core, since the first hardware
+    // thread of every core should be used before any second one.
+    let sibling = |c: &Cpu| {
+        cpus.iter()
+            .filter(|o| o.package == c.package && o.core == c.core && o.id < c.id)
+            .count()
+    };
+    match *placement {
+        Placement::None => vec![],
+        Placement::Compact => cpus.iter().map(|c| c.id).collect(),
+        Placement::PhysicalCores => {
+            cpus.iter().filter(|c| sibling(c) == 0).map(|c| c.id).collect()
+        }
+        Placement::Scatter => {
+            let mut packages = cpus.iter().map(|c| c.package).collect::<Vec<_>>();
+            packages.dedup();
+            let per_package = packages
+                .iter()
+                .map(|&p| {
+                    let mut cs = cpus.iter().filter(|c| c.package == p).collect::<Vec<_>>();
+                    cs.sort_by_key(|c| (sibling(c), c.core, c.id));
+                    cs
+                })
+                .collect::<Vec<_>>();
+            let longest = per_package.iter().map(|cs| cs.len()).max().unwrap_or(0);
+            (0..longest)
+                .flat_map(|i| per_package.iter().filter_map(move |cs| cs.get(i).map(|c| c.id)))
+                .collect()
+        }
+        Placement::Cpus(ref list) => list.clone(),
+    }
+}
+
+impl Placement {
+    /// The CPU of each of `n_threads` threads, or `None` if the threads should not be pinned.
+    /// Panics if the placement has CPUs that are not in `topology`.
+    pub fn cpus(&self, n_threads: usize) -> Vec<Option<usize>> {
+        let topology = topology();
+        if let Err(e) = self.check_in(&topology) {
+            panic!("{}", e);
+        }
+        self.cpus_in(n_threads, &topology)
+    }
+
+    /// Check that all CPUs of the placement are online, and that we may run on them, so that
+    /// `cpus` does not panic.
+    pub fn check(&self) -> Result<(), String> {
+        self.check_in(&topology())
+    }
+
+    fn check_in(&self, topology: &[Cpu]) -> Result<(), String> {
+        if let Placement::Cpus(ref list) = *self {
+            for cpu in list {
+                if !topology.iter().any(|c| c.id == *cpu) {
+                    return Err(format!(
+                        "CPU {} is not online, or not in the affinity mask of the process",
+                        cpu
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn cpus_in(&self, n_threads: usize, topology: &[Cpu]) -> Vec<Option<usize>> {
+        let order = order(self, topology);
+        (0..n_threads)
+            .map(|i| if order.is_empty() {
+                None
+            } else {
+                Some(order[i % order.len()])
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Placement {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Placement, Self::Err> {
+        Ok(match s {
+            "none" => Placement::None,
+            "compact" => Placement::Compact,
+            "scatter" => Placement::Scatter,
+            "physical" => Placement::PhysicalCores,
+            list => Placement::Cpus(parse_list(list).map_err(|e| {
+                format!(
+                    "{}; expected none, compact, scatter, physical or a list of CPUs",
+                    e
+                )
+            })?),
+        })
+    }
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Placement::None => write!(f, "none"),
+            Placement::Compact => write!(f, "compact"),
+            Placement::Scatter => write!(f, "scatter"),
+            Placement::PhysicalCores => write!(f, "physical"),
+            Placement::Cpus(ref list) => {
+                let list = list.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", list.join(","))
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const u64) -> i32;
+    fn sched_getaffinity(pid: i32, cpusetsize: usize, mask: *mut u64) -> i32;
+}
+
+/// The number of CPUs in the `cpu_set_t` of glibc.
+const CPU_SETSIZE: usize = 1024;
+
+/// The CPUs in the affinity mask of the calling thread.
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    let mut mask = [0u64; CPU_SETSIZE / 64];
+    // `pid` 0 is the calling thread.
+    let ret = unsafe { sched_getaffinity(0, ::std::mem::size_of_val(&mask), mask.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((0..CPU_SETSIZE).filter(|&cpu| mask[cpu / 64] & (1 << (cpu % 64)) != 0).collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    Err(io::Error::new(io::ErrorKind::Other, "affinity masks are only supported on Linux"))
+}
+
+/// Pin the calling thread to `cpu`.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    if cpu >= CPU_SETSIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "CPU number is too large"));
+    }
+    let mut mask = [0u64; CPU_SETSIZE / 64];
+    mask[cpu / 64] |= 1 << (cpu % 64);
+    // `pid` 0 is the calling thread.
+    let ret = unsafe { sched_setaffinity(0, ::std::mem::size_of_val(&mask), mask.as_ptr()) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "pinning is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Two sockets with two cores of two hardware threads each, numbered like Linux does on
+    /// Intel machines: the second hardware threads come after all the first ones.
+    fn two_sockets() -> Vec<Cpu> {
+        (0..8)
+            .map(|id| {
+                Cpu {
+                    id: id,
+                    package: (id / 2) % 2,
+                    core: id % 2,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn placements() {
+        let t = two_sockets();
+        let cpus = |p: Placement, n| {
+            p.cpus_in(n, &t).into_iter().map(|c| c.unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(cpus(Placement::Compact, 4), vec![0, 4, 1, 5]);
+        assert_eq!(cpus(Placement::PhysicalCores, 5), vec![0, 1, 2, 3, 0]);
+        assert_eq!(cpus(Placement::Scatter, 8), vec![0, 2, 1, 3, 4, 6, 5, 7]);
+        assert_eq!(cpus("1,6-7".parse().unwrap(), 4), vec![1, 6, 7, 1]);
+        assert_eq!(Placement::None.cpus_in(2, &t), vec![None, None]);
+    }
+
+    #[test]
+    fn check() {
+        let t = two_sockets();
+        assert_eq!(Placement::Compact.check_in(&t), Ok(()));
+        assert_eq!(Placement::Cpus(vec![0, 7]).check_in(&t), Ok(()));
+        assert!(Placement::Cpus(vec![0, 8]).check_in(&t).is_err());
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("scatter".parse(), Ok(Placement::Scatter));
+        assert_eq!("0-2,5".parse(), Ok(Placement::Cpus(vec![0, 1, 2, 5])));
+        assert!("sideways".parse::<Placement>().is_err());
+        assert!("7-4".parse::<Placement>().is_err());
+        assert!("0,".parse::<Placement>().is_err());
+        assert_eq!(Placement::Cpus(vec![0, 3]).to_string(), "0,3");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn allowed() {
+        let allowed = allowed_cpus().unwrap();
+        assert!(!allowed.is_empty());
+        assert!(topology().iter().all(|c| allowed.contains(&c.id)));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn pin() {
+        let cpu = topology()[0].id;
+        ::std::thread::spawn(move || pin_current_thread(cpu).unwrap())
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+/// A log-bucketed histogram, in the style of HdrHistogram.
+///
+/// Values are put in buckets by their highest set bit, and every bucket is split into
+/// `SUB_BUCKETS` linear sub-buckets, so the relative error of a value read back from the
+/// histogram is at most `1 / SUB_BUCKETS`. Values below `2 * SUB_BUCKETS` are exact. This lets
+/// us record latencies from nanoseconds to hours in a fixed amount of memory, without knowing the
+/// range in advance.
+
+use std::fmt::Write;
+
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// The largest shift is for values with the highest bit set, which is `63 - SUB_BUCKET_BITS`.
+const NUM_INDICES: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl ::std::fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("Histogram")
+            .field("count", &self.count)
+            .field("min", &self.min)
+            .field("max", &self.max)
+            .finish()
+    }
+}
+
+/// Returns the index of the bucket of `v`.
+fn index(v: u64) -> usize {
+    if v < 2 * SUB_BUCKETS as u64 {
+        return v as usize;
+    }
+    let shift = 63 - v.leading_zeros() - SUB_BUCKET_BITS;
+    let sub = (v >> shift) as usize;
+    (shift as usize + 1) * SUB_BUCKETS + sub - SUB_BUCKETS
+}
+
+/// Returns the smallest and the largest value in the bucket with index `i`.
+fn bounds(i: usize) -> (u64, u64) {
+    if i < 2 * SUB_BUCKETS {
+        return (i as u64, i as u64);
+    }
+    let shift = i / SUB_BUCKETS - 1;
+    let sub = (i % SUB_BUCKETS + SUB_BUCKETS) as u64;
+    let low = sub << shift;
+    (low, low + ((1u64 << shift) - 1))
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            counts: vec![0; NUM_INDICES],
+            count: 0,
+            sum: 0,
+            min: ::std::u64::MAX,
+            max: 0,
+        }
+    }
+
+    /// Make a histogram of all values in `values`.
+    pub fn from_values(values: &[u64]) -> Self {
+        let mut h = Self::new();
+        for &v in values {
+            h.record(v);
+        }
+        h
+    }
+
+    pub fn record(&mut self, v: u64) {
+        self.counts[index(v)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(v);
+        self.min = ::std::cmp::min(self.min, v);
+        self.max = ::std::cmp::max(self.max, v);
+    }
+
+    /// Add all values recorded in `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (c, o) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *c += *o;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = ::std::cmp::min(self.min, other.min);
+        self.max = ::std::cmp::max(self.max, other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The smallest recorded value. This is exact.
+    pub fn min(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.min }
+    }
+
+    /// The largest recorded value. This is exact.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.sum / self.count }
+    }
+
+    /// Returns the value below which `p` percent of the recorded values are. The value is the
+    /// largest value of its bucket, but never larger than the largest recorded value.
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((p / 100.0) * self.count as f64).ceil() as u64;
+        let rank = ::std::cmp::max(rank, 1);
+        let mut seen = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank {
+                return ::std::cmp::min(bounds(i).1, self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Returns the non-empty buckets as `(low, high, count)`, where all values in the bucket are
+    /// in `low..=high`.
+    pub fn buckets(&self) -> Vec<(u64, u64, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c > 0)
+            .map(|(i, &c)| {
+                let (low, high) = bounds(i);
+                (low, high, c)
+            })
+            .collect()
+    }
+
+    /// Export the histogram as one line per non-empty bucket, with the lowest value, the highest
+    /// value, the count, and the fraction of all values at or below the bucket, separated by
+    /// spaces. This can be plotted directly with gnuplot.
+    pub fn export(&self) -> String {
+        let mut s = String::new();
+        let mut seen = 0;
+        for (low, high, c) in self.buckets() {
+            seen += c;
+            writeln!(s, "{} {} {} {:.6}", low, high, c, seen as f64 / self.count as f64).unwrap();
+        }
+        s
+    }
+
+    /// A short summary of the percentiles.
+    pub fn report(&self) -> String {
+        format!(
+            "p50={} p90={} p99={} p99.9={} max={}",
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(99.9),
+            self.max()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn index_bounds() {
+        for &v in &[0, 1, 31, 63, 64, 65, 100, 1000, 123_456_789, ::std::u64::MAX] {
+            let (low, high) = bounds(index(v));
+            assert!(low <= v && v <= high, "{} not in {}..{}", v, low, high);
+            // The relative error is bounded by the number of sub-buckets.
+            assert!((high - low) <= low / SUB_BUCKETS as u64);
+        }
+        assert!(index(::std::u64::MAX) < NUM_INDICES);
+    }
+
+    #[test]
+    fn percentiles() {
+        let values = (1..10_001).collect::<Vec<u64>>();
+        let h = Histogram::from_values(&values);
+        assert_eq!(h.count(), 10_000);
+        assert_eq!(h.min(), 1);
+        assert_eq!(h.max(), 10_000);
+        for &(p, exact) in &[(50.0, 5_000), (90.0, 9_000), (99.0, 9_900), (99.9, 9_990)] {
+            let v = h.percentile(p);
+            assert!(v >= exact && v - exact <= exact / SUB_BUCKETS as u64);
+        }
+        assert_eq!(h.percentile(100.0), 10_000);
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = Histogram::from_values(&[1, 2, 3]);
+        let b = Histogram::from_values(&[1000, 2000]);
+        a.merge(&b);
+        assert_eq!(a.count(), 5);
+        assert_eq!(a.max(), 2000);
+        assert_eq!(a.min(), 1);
+        assert_eq!(a.export().lines().count(), 5);
+    }
+}
@@ -7,18 +7,84 @@
 /// problem.
 
 extern crate time;
+#[macro_use]
+extern crate lazy_static;
+
+pub mod affinity;
+pub mod histogram;
+pub mod output;
+pub use affinity::Placement;
+pub use histogram::Histogram;
+pub use output::Metadata;
 
 use std::str::FromStr;
 use std::sync::mpsc::{Sender, Receiver, channel};
-use std::sync::{Arc, Barrier};
+use std::sync::{Arc, Barrier, Mutex};
 use std::thread;
 
 const DEFAULT_NUM_SAMPLES: usize = 200;
+/// In throughput mode, the number of operations a thread runs between each time it reads the
+/// clock, so that reading the clock does not dominate short operations.
+const OPS_PER_CLOCK_READ: u64 = 32;
+
+/// What the samples of a `BenchStats` are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// The time in nanoseconds used on a fixed amount of work.
+    Nanos,
+    /// The number of operations per second done until a deadline.
+    OpsPerSec,
+}
+
+lazy_static! {
+    static ref DEFAULT_METRICS: Mutex<Vec<(String, fn() -> u64)>> = Mutex::new(vec![]);
+}
+
+/// Set the metrics every `ThreadBencher` reads after every sample, in addition to the ones given
+/// to `ThreadBencher::metric`. These are for values that do not depend on the state of the
+/// benchmark, such as the memory usage of the process.
+pub fn set_default_metrics(metrics: Vec<(String, fn() -> u64)>) {
+    *DEFAULT_METRICS.lock().unwrap() = metrics;
+}
+
+impl Unit {
+    pub fn suffix(&self) -> &'static str {
+        match *self {
+            Unit::Nanos => "ns/iter",
+            Unit::OpsPerSec => "ops/sec",
+        }
+    }
+
+    /// Is a larger sample a better result?
+    pub fn higher_is_better(&self) -> bool {
+        *self == Unit::OpsPerSec
+    }
+}
+
+impl FromStr for Unit {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Unit, Self::Err> {
+        match s {
+            "ns/iter" => Ok(Unit::Nanos),
+            "ops/sec" => Ok(Unit::OpsPerSec),
+            _ => Err(format!("unknown unit '{}'; expected ns/iter or ops/sec", s)),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct BenchStats {
     ident: BenchIdentifier,
     samples: Vec<u64>,
+    /// The time each thread used on its work, or the throughput of each thread, for each sample.
+    /// Empty if the benchmark is not threaded.
+    thread_samples: Vec<Vec<u64>>,
+    unit: Unit,
+    /// The latency of every operation, if we recorded it.
+    op_histogram: Option<Histogram>,
+    /// Named values read after every sample, such as the memory usage of the process. See
+    /// `ThreadBencher::metric`.
+    metrics: Vec<(String, Vec<u64>)>,
 }
 
 impl BenchStats {
@@ -31,6 +97,9 @@ impl BenchStats {
     pub fn threads(&self) -> usize {
         self.ident.threads
     }
+    pub fn unit(&self) -> Unit {
+        self.unit
+    }
 
     pub fn string(&self) -> String {
         self.ident.string()
@@ -111,16 +180,68 @@ impl BenchStats {
         self.samples.iter().filter(|&&s| s < avg).count() as u64
     }
 
+    /// The time, or throughput, of the fastest thread in each sample, averaged over all samples.
+    pub fn thread_min(&self) -> u64 {
+        self.thread_average(|ts| ts.iter().cloned().min().unwrap_or(0))
+    }
+
+    /// The time, or throughput, of the slowest thread in each sample, averaged over all samples.
+    pub fn thread_max(&self) -> u64 {
+        self.thread_average(|ts| ts.iter().cloned().max().unwrap_or(0))
+    }
+
+    /// The difference between the slowest and the fastest thread in each sample, in percent of
+    /// the largest value, averaged over all samples. This is 0 if all threads finish their work
+    /// at the same time, or have the same throughput, and close to 100 if one thread is starved.
+    pub fn thread_imbalance(&self) -> u64 {
+        self.thread_average(|ts| {
+            let min = ts.iter().cloned().min().unwrap_or(0);
+            let max = ts.iter().cloned().max().unwrap_or(0);
+            if max == 0 { 0 } else { (max - min) * 100 / max }
+        })
+    }
+
+    fn thread_average<F: Fn(&[u64]) -> u64>(&self, f: F) -> u64 {
+        if self.thread_samples.is_empty() {
+            return 0;
+        }
+        self.thread_samples.iter().map(|ts| f(ts)).sum::<u64>() / self.thread_samples.len() as u64
+    }
+
     pub fn report(&self) -> String {
-        format!(
-            "{} ns/iter (+/- {}) min={} max={} above={} below={}",
+        let mut s = format!(
+            "{} {} (+/- {}) min={} max={} above={} below={}",
             Self::fmt_thousands_sep(self.average()),
+            self.unit.suffix(),
             Self::fmt_thousands_sep(self.variance()),
             self.min(),
             self.max(),
             self.above_avg(),
             self.below_avg()
-        )
+        );
+        if !self.thread_samples.is_empty() {
+            s.push_str(&format!(
+                " thread_min={} thread_max={} imbalance={}%",
+                self.thread_min(),
+                self.thread_max(),
+                self.thread_imbalance()
+            ));
+        }
+        // Percentiles of the sample time are only interesting if there is no histogram of the
+        // single operations, since one sample is thousands of operations.
+        match self.op_histogram {
+            Some(ref h) => s.push_str(&format!(" op_latency: {}", h.report())),
+            None if self.unit == Unit::Nanos => {
+                s.push_str(&format!(" {}", self.sample_histogram().report()))
+            }
+            None => {}
+        }
+        for &(ref name, ref values) in self.metrics.iter() {
+            if let (Some(first), Some(last)) = (values.first(), values.last()) {
+                s.push_str(&format!(" {}={}->{}", name, first, last));
+            }
+        }
+        s
     }
 
     pub fn csv_header() -> String {
@@ -151,6 +272,50 @@ impl BenchStats {
         &self.samples
     }
 
+    /// Add the samples of `other`, which must be another run of the same benchmark, to these
+    /// statistics. This is how repeated runs of a benchmark end up as one result.
+    pub fn merge(&mut self, other: BenchStats) {
+        assert_eq!(self.string(), other.string());
+        assert_eq!(self.unit, other.unit);
+        self.samples.extend(other.samples);
+        self.thread_samples.extend(other.thread_samples);
+        self.op_histogram = match (self.op_histogram.take(), other.op_histogram) {
+            (Some(mut h), Some(o)) => {
+                h.merge(&o);
+                Some(h)
+            }
+            (h, None) => h,
+            (None, o) => o,
+        };
+        for (name, values) in other.metrics {
+            match self.metrics.iter().position(|&(ref n, _)| *n == name) {
+                Some(i) => self.metrics[i].1.extend(values),
+                None => self.metrics.push((name, values)),
+            }
+        }
+    }
+
+    /// The time each thread used in each sample, indexed by sample and then by thread.
+    pub fn thread_samples(&self) -> &[Vec<u64>] {
+        &self.thread_samples
+    }
+
+    /// A histogram of the samples.
+    pub fn sample_histogram(&self) -> Histogram {
+        Histogram::from_values(&self.samples)
+    }
+
+    /// A histogram of the latency of every operation, in nanoseconds, if the benchmark recorded
+    /// it. See `ThreadBencher::record_latency`.
+    pub fn op_histogram(&self) -> Option<&Histogram> {
+        self.op_histogram.as_ref()
+    }
+
+    /// The values of each metric, with one value for each sample.
+    pub fn metrics(&self) -> &[(String, Vec<u64>)] {
+        &self.metrics
+    }
+
     // This is borrowed from `test::Bencher` :)
     fn fmt_thousands_sep(mut n: u64) -> String {
         let sep = ',';
@@ -264,6 +429,10 @@ impl<S> Bencher<S> {
     pub fn into_stats(self, name: String) -> BenchStats {
         BenchStats {
             samples: self.samples,
+            thread_samples: vec![],
+            unit: Unit::Nanos,
+            op_histogram: None,
+            metrics: vec![],
             ident: BenchIdentifier::from_str(&name).unwrap(),
         }
     }
@@ -325,7 +494,12 @@ unsafe impl<S> Send for FunctionPtr<S> {}
 #[derive(Debug)]
 enum ThreadSignal<S> {
     Run(FunctionPtr<S>),
+    /// Call the function repeatedly for the given number of nanoseconds, and record the latency
+    /// of each call if the flag is set.
+    RunFor(FunctionPtr<S>, u64, bool),
     Done(u64),
+    /// The number of calls made, the time it took, and the latencies if we recorded them.
+    Ops(u64, u64, Option<Histogram>),
     End,
 }
 
@@ -352,6 +526,14 @@ where
 
 pub struct ThreadBencher<S, Sp: Spawner> {
     samples: Vec<u64>,
+    thread_samples: Vec<Vec<u64>>,
+    unit: Unit,
+    record_latency: bool,
+    op_histogram: Option<Histogram>,
+    default_metric_fns: Vec<(String, fn() -> u64)>,
+    metric_fns: Vec<(String, fn(&S) -> u64)>,
+    /// The values of the default metrics, followed by the values of the metrics in `metric_fns`.
+    metrics: Vec<(String, Vec<u64>)>,
     state: S,
     n: usize,
     threads: Vec<Sp>,
@@ -368,19 +550,39 @@ where
     Sp: Spawner,
     Sp::Return: Send + Default + 'static,
 {
+    /// Make a bencher with `n_threads` threads, placed as in `affinity::default_placement`.
     pub fn new(state: St, n_threads: usize) -> Self {
+        Self::with_placement(state, n_threads, &affinity::default_placement())
+    }
+
+    /// Make a bencher with `n_threads` threads, pinned to CPUs as given by `placement`. Panics if
+    /// a thread could not be pinned, since the results would not be comparable to other runs.
+    pub fn with_placement(state: St, n_threads: usize, placement: &Placement) -> Self {
+        let cpus = placement.cpus(n_threads);
         let mut senders = Vec::with_capacity(n_threads);
         let mut receivers = Vec::with_capacity(n_threads);
         let barrier = Arc::new(Barrier::new(n_threads + 1));
+        let (pinned_send, pinned_recv) = channel();
         // Start the threads, and give them channels for communication.
         let threads = (0..n_threads)
-            .map(|_thread_id| {
+            .map(|thread_id| {
+                let cpu = cpus[thread_id];
                 let (our_send, their_recv) = channel();
                 let (their_send, our_recv) = channel();
                 senders.push(our_send);
                 receivers.push(our_recv);
                 let barrier = barrier.clone();
+                let pinned = pinned_send.clone();
                 Sp::spawn(move || {
+                    // If we panicked here, the bencher would wait for us forever, so we send the
+                    // error to it, and it panics instead.
+                    let res = match cpu {
+                        Some(cpu) => affinity::pin_current_thread(cpu).map_err(|e| {
+                            format!("Failed to pin thread {} to CPU {}: {}", thread_id, cpu, e)
+                        }),
+                        None => Ok(()),
+                    };
+                    assert!(pinned.send(res).is_ok());
                     let recv = their_recv;
                     let send = their_send;
                     loop {
@@ -392,6 +594,32 @@ where
                                 let t1 = time::precise_time_ns();
                                 ThreadSignal::Done(t1 - t0)
                             }
+                            Ok(ThreadSignal::RunFor(ref mut f, duration, record_latency)) => {
+                                let mut hist = if record_latency {
+                                    Some(Histogram::new())
+                                } else {
+                                    None
+                                };
+                                barrier.wait();
+                                let t0 = time::precise_time_ns();
+                                let deadline = t0 + duration;
+                                let mut ops = 0;
+                                let mut t1 = t0;
+                                while t1 < deadline {
+                                    for _ in 0..OPS_PER_CLOCK_READ {
+                                        if let Some(ref mut h) = hist {
+                                            let s = time::precise_time_ns();
+                                            f.call();
+                                            h.record(time::precise_time_ns() - s);
+                                        } else {
+                                            f.call();
+                                        }
+                                    }
+                                    ops += OPS_PER_CLOCK_READ;
+                                    t1 = time::precise_time_ns();
+                                }
+                                ThreadSignal::Ops(ops, t1 - t0, hist)
+                            }
                             Ok(ThreadSignal::End) => {
                                 break;
                             }
@@ -404,9 +632,26 @@ where
                 })
             })
             .collect();
+        for _ in 0..n_threads {
+            if let Err(e) = pinned_recv.recv().unwrap() {
+                panic!("{}", e);
+            }
+        }
+        let default_metric_fns = DEFAULT_METRICS.lock().unwrap().clone();
+        let metrics = default_metric_fns
+            .iter()
+            .map(|&(ref name, _)| (name.clone(), vec![]))
+            .collect();
         Self {
             state,
             samples: vec![],
+            thread_samples: vec![],
+            unit: Unit::Nanos,
+            record_latency: false,
+            op_histogram: None,
+            default_metric_fns,
+            metric_fns: vec![],
+            metrics,
             n: DEFAULT_NUM_SAMPLES,
             threads,
             senders,
@@ -429,16 +674,17 @@ where
             // TODO: this is not good: we risk waiting for a long time in `barrier.wait`
             let t0 = time::precise_time_ns();
             self.barrier.wait();
+            let mut thread_times = Vec::with_capacity(self.receivers.len());
             for recv in self.receivers.iter() {
                 match recv.recv() {
-                    Ok(ThreadSignal::Done(_t)) => {
-                        // OK
-                    }
+                    Ok(ThreadSignal::Done(t)) => thread_times.push(t),
                     _ => panic!("Thread didn't return correctly"),
                 }
             }
             let t1 = time::precise_time_ns();
             self.samples.push(t1 - t0);
+            self.thread_samples.push(thread_times);
+            self.record_metrics();
         }
         for sender in &self.senders {
             assert!(sender.send(ThreadSignal::End).is_ok());
@@ -446,6 +692,59 @@ where
         (self.after)(&mut self.state);
     }
 
+    /// Start a throughput benchmark. All threads call the function given repeatedly until
+    /// `duration` has passed, and count the calls, so the function should do one operation. Each
+    /// sample is the total number of operations per second, and the number of operations per
+    /// second of each thread is in the thread samples.
+    ///
+    /// Since all threads run for the same time, one slow thread does not stretch the sample, as
+    /// it does in `thread_bench`.
+    pub fn throughput_bench(&mut self, f: fn(&St), duration: ::std::time::Duration) {
+        let duration = duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64;
+        let func_ptr = FunctionPtr::new(f, &self.state);
+        self.unit = Unit::OpsPerSec;
+        for _i in 0..self.n {
+            (self.before)(&mut self.state);
+            for sender in &self.senders {
+                let signal = ThreadSignal::RunFor(func_ptr.clone(), duration, self.record_latency);
+                assert!(sender.send(signal).is_ok());
+            }
+            self.barrier.wait();
+            let mut thread_ops = Vec::with_capacity(self.receivers.len());
+            for recv in self.receivers.iter() {
+                match recv.recv() {
+                    Ok(ThreadSignal::Ops(ops, t, hist)) => {
+                        thread_ops.push(ops * 1_000_000_000 / ::std::cmp::max(t, 1));
+                        if let Some(hist) = hist {
+                            self.op_histogram
+                                .get_or_insert_with(Histogram::new)
+                                .merge(&hist);
+                        }
+                    }
+                    _ => panic!("Thread didn't return correctly"),
+                }
+            }
+            self.samples.push(thread_ops.iter().sum());
+            self.thread_samples.push(thread_ops);
+            self.record_metrics();
+        }
+        for sender in &self.senders {
+            assert!(sender.send(ThreadSignal::End).is_ok());
+        }
+        (self.after)(&mut self.state);
+    }
+
+    /// Set the number of samples.
+    pub fn set_n(&mut self, n: usize) {
+        self.n = n;
+    }
+
+    /// Record the latency of every operation in `throughput_bench` in a histogram. This reads the
+    /// clock twice for every operation, which lowers the throughput of short operations.
+    pub fn record_latency(&mut self, record: bool) {
+        self.record_latency = record;
+    }
+
     pub fn before<F: 'static + Fn(&mut St)>(&mut self, f: F) {
         self.before = Box::new(f);
     }
@@ -454,10 +753,32 @@ where
         self.after = Box::new(f);
     }
 
+    /// Call `f` after every sample, and keep the values as the metric `name`.
+    pub fn metric(&mut self, name: &str, f: fn(&St) -> u64) {
+        self.metric_fns.push((name.to_string(), f));
+        self.metrics.push((name.to_string(), vec![]));
+    }
+
+    fn record_metrics(&mut self) {
+        let (defaults, metrics) = self.metrics.split_at_mut(self.default_metric_fns.len());
+        for (&(_, f), &mut (_, ref mut values)) in
+            self.default_metric_fns.iter().zip(defaults.iter_mut())
+        {
+            values.push(f());
+        }
+        for (&(_, f), &mut (_, ref mut values)) in self.metric_fns.iter().zip(metrics.iter_mut()) {
+            values.push(f(&self.state));
+        }
+    }
+
     pub fn into_stats(self, name: String) -> BenchStats {
         self.threads.into_iter().map(Spawner::join).count();
         BenchStats {
             samples: self.samples,
+            thread_samples: self.thread_samples,
+            unit: self.unit,
+            op_histogram: self.op_histogram,
+            metrics: self.metrics,
             ident: BenchIdentifier::from_str(&name).unwrap(),
         }
     }
@@ -494,6 +815,80 @@ mod test {
         b.bench(State { num: 0i32 }, |_| {});
     }
 
+    #[test]
+    fn thread_imbalance() {
+        let stats = BenchStats {
+            ident: BenchIdentifier::from_str("a::b::02").unwrap(),
+            samples: vec![100, 100],
+            thread_samples: vec![vec![50, 100], vec![100, 100]],
+            unit: Unit::Nanos,
+            op_histogram: None,
+            metrics: vec![],
+        };
+        assert_eq!(stats.thread_min(), 75);
+        assert_eq!(stats.thread_max(), 100);
+        assert_eq!(stats.thread_imbalance(), 25);
+    }
+
+    #[test]
+    fn throughput() {
+        struct State(::std::sync::atomic::AtomicUsize);
+
+        fn op(state: &State) {
+            state.0.fetch_add(1, ::std::sync::atomic::Ordering::Relaxed);
+        }
+
+        let state = State(::std::sync::atomic::AtomicUsize::new(0));
+        let mut b = ThreadBencher::<State, StdThread<()>>::new(state, 2);
+        b.set_n(3);
+        b.metric("calls", |s| s.0.load(::std::sync::atomic::Ordering::Relaxed) as u64);
+        b.throughput_bench(op, ::std::time::Duration::from_millis(10));
+        let stats = b.into_stats("a::b::02".to_string());
+        assert_eq!(stats.unit(), Unit::OpsPerSec);
+        let calls = &stats.metrics()[0].1;
+        assert_eq!(calls.len(), 3);
+        assert!(calls.windows(2).all(|w| w[0] < w[1]));
+        assert!(stats.op_histogram().is_none());
+        assert_eq!(stats.samples().len(), 3);
+        for (total, threads) in stats.samples().iter().zip(stats.thread_samples()) {
+            assert_eq!(threads.len(), 2);
+            assert_eq!(*total, threads.iter().sum::<u64>());
+            assert!(threads.iter().all(|&t| t > 0));
+        }
+    }
+
+    #[test]
+    fn op_latency() {
+        fn op(_: &()) {}
+
+        let mut b = ThreadBencher::<(), StdThread<()>>::new((), 2);
+        b.set_n(2);
+        b.record_latency(true);
+        b.throughput_bench(op, ::std::time::Duration::from_millis(10));
+        let stats = b.into_stats("a::b::02".to_string());
+        let hist = stats.op_histogram().unwrap();
+        // Threads run whole batches of operations, and record every one of them.
+        assert!(hist.count() > 0);
+        assert_eq!(hist.count() % OPS_PER_CLOCK_READ, 0);
+        assert!(hist.percentile(50.0) <= hist.max());
+    }
+
+    #[test]
+    fn merge() {
+        let run = |samples: Vec<u64>| BenchStats {
+            ident: "a::b::02".parse().unwrap(),
+            samples: samples,
+            thread_samples: vec![],
+            unit: Unit::Nanos,
+            op_histogram: Some(Histogram::from_values(&[1, 2])),
+            metrics: vec![],
+        };
+        let mut stats = run(vec![10, 20]);
+        stats.merge(run(vec![30]));
+        assert_eq!(stats.samples(), &[10, 20, 30]);
+        assert_eq!(stats.op_histogram().unwrap().count(), 4);
+    }
+
     #[test]
     fn threaded() {
         #[derive(Debug, Default, Clone)]
@@ -0,0 +1,232 @@
+/// Machine readable output of benchmark results.
+///
+/// The sample files of `benchmark-runner` and `BenchStats::csv` are made for humans and gnuplot.
+/// Here we write JSON and RFC 4180 CSV, where every result carries the metadata of the run, so
+/// that results from different machines and commits can be put in the same place and told apart.
+
+use std::fmt::Write;
+
+use BenchStats;
+
+/// Facts about the machine and build a set of results came from. This crate does not know
+/// anything about the build of the benchmarks, so it is up to the caller to fill this in.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub cpu_model: String,
+    pub cores: usize,
+    pub kernel: String,
+    pub git_commit: String,
+    /// The enabled cargo features, such as `hp-wait`.
+    pub features: Vec<String>,
+    /// How the threads were placed on CPUs, as in `Placement`'s `Display`.
+    pub placement: String,
+    /// The workload of the benchmarks that take one, in the runner's own format, or empty.
+    pub workload: String,
+    /// The numbers of threads the benchmarks were run with.
+    pub threads: Vec<usize>,
+    /// When the run started, in RFC 3339.
+    pub timestamp: String,
+}
+
+/// Escape `s` as a JSON string, with the quotes.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quote `s` as a CSV field, if it needs it.
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn json_list<I: Iterator<Item = String>>(items: I) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(", "))
+}
+
+impl Metadata {
+    fn json(&self) -> String {
+        format!(
+            "{{\"cpu_model\": {}, \"cores\": {}, \"kernel\": {}, \"git_commit\": {}, \
+             \"features\": {}, \"placement\": {}, \"workload\": {}, \"threads\": {}, \
+             \"timestamp\": {}}}",
+            json_string(&self.cpu_model),
+            self.cores,
+            json_string(&self.kernel),
+            json_string(&self.git_commit),
+            json_list(self.features.iter().map(|f| json_string(f))),
+            json_string(&self.placement),
+            json_string(&self.workload),
+            json_list(self.threads.iter().map(|t| t.to_string())),
+            json_string(&self.timestamp)
+        )
+    }
+}
+
+impl BenchStats {
+    fn json(&self) -> String {
+        let mut s = format!(
+            "{{\"variant\": {}, \"name\": {}, \"threads\": {}, \"unit\": {}, \
+             \"num_samples\": {}, \"average\": {}, \"stddev\": {}, \"min\": {}, \"max\": {}",
+            json_string(self.variant()),
+            json_string(self.name()),
+            self.threads(),
+            json_string(self.unit.suffix()),
+            self.samples.len(),
+            self.average(),
+            self.variance(),
+            self.min(),
+            self.max()
+        );
+        if !self.thread_samples.is_empty() {
+            write!(
+                s,
+                ", \"thread_min\": {}, \"thread_max\": {}, \"thread_imbalance\": {}",
+                self.thread_min(),
+                self.thread_max(),
+                self.thread_imbalance()
+            ).unwrap();
+        }
+        if let Some(ref h) = self.op_histogram {
+            write!(
+                s,
+                ", \"op_latency\": {{\"count\": {}, \"p50\": {}, \"p90\": {}, \"p99\": {}, \
+                 \"p99.9\": {}, \"max\": {}}}",
+                h.count(),
+                h.percentile(50.0),
+                h.percentile(90.0),
+                h.percentile(99.0),
+                h.percentile(99.9),
+                h.max()
+            ).unwrap();
+        }
+        if !self.metrics.is_empty() {
+            let metrics = self.metrics.iter().map(|&(ref name, ref values)| {
+                format!(
+                    "{}: {}",
+                    json_string(name),
+                    json_list(values.iter().map(|v| v.to_string()))
+                )
+            });
+            write!(s, ", \"metrics\": {{{}}}", metrics.collect::<Vec<_>>().join(", ")).unwrap();
+        }
+        write!(
+            s,
+            ", \"samples\": {}}}",
+            json_list(self.samples.iter().map(|s| s.to_string()))
+        ).unwrap();
+        s
+    }
+}
+
+/// Write `stats` as one JSON object, with the metadata in `"metadata"` and one object per
+/// benchmark, including all samples, in `"results"`.
+pub fn json(stats: &[BenchStats], meta: &Metadata) -> String {
+    let mut s = String::new();
+    writeln!(s, "{{").unwrap();
+    writeln!(s, "  \"metadata\": {},", meta.json()).unwrap();
+    writeln!(s, "  \"results\": [").unwrap();
+    for (i, stat) in stats.iter().enumerate() {
+        let comma = if i + 1 < stats.len() { "," } else { "" };
+        writeln!(s, "    {}{}", stat.json(), comma).unwrap();
+    }
+    writeln!(s, "  ]").unwrap();
+    writeln!(s, "}}").unwrap();
+    s
+}
+
+const CSV_HEADER: &'static str = "timestamp,git_commit,cpu_model,cores,kernel,features,\
+                                  placement,workload,variant,name,threads,unit,num_samples,\
+                                  average,stddev,min,max,thread_imbalance";
+
+/// Write `stats` as CSV, with a header and one row per benchmark. Every row has all the
+/// metadata, so that the rows of many runs can be concatenated. The features are separated by
+/// spaces, and `thread_imbalance` is empty if the benchmark is not threaded.
+pub fn csv(stats: &[BenchStats], meta: &Metadata) -> String {
+    let mut s = String::new();
+    writeln!(s, "{}", CSV_HEADER).unwrap();
+    for stat in stats {
+        let imbalance = if stat.thread_samples.is_empty() {
+            String::new()
+        } else {
+            stat.thread_imbalance().to_string()
+        };
+        let fields = [
+            csv_field(&meta.timestamp),
+            csv_field(&meta.git_commit),
+            csv_field(&meta.cpu_model),
+            meta.cores.to_string(),
+            csv_field(&meta.kernel),
+            csv_field(&meta.features.join(" ")),
+            csv_field(&meta.placement),
+            csv_field(&meta.workload),
+            csv_field(stat.variant()),
+            csv_field(stat.name()),
+            stat.threads().to_string(),
+            csv_field(stat.unit.suffix()),
+            stat.samples.len().to_string(),
+            stat.average().to_string(),
+            stat.variance().to_string(),
+            stat.min().to_string(),
+            stat.max().to_string(),
+            imbalance,
+        ];
+        writeln!(s, "{}", fields.join(",")).unwrap();
+    }
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+        assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+        assert_eq!(csv_field("Intel(R) Core(TM) i7"), "Intel(R) Core(TM) i7");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn csv_columns() {
+        let meta = Metadata {
+            cpu_model: "Some CPU, 3.0GHz".to_string(),
+            cores: 4,
+            features: vec!["hp-wait".to_string()],
+            workload: "insert=4,search=2".to_string(),
+            threads: vec![2],
+            ..Default::default()
+        };
+        let stats = ::BenchStats {
+            ident: "hp::queue::push::02".parse().unwrap(),
+            samples: vec![10, 20, 30],
+            thread_samples: vec![],
+            unit: ::Unit::Nanos,
+            op_histogram: None,
+            metrics: vec![],
+        };
+        let out = csv(&[stats], &meta);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains(",\"Some CPU, 3.0GHz\",4,"));
+        assert!(lines[1].contains(",\"insert=4,search=2\",hp,"));
+        assert!(lines[1].ends_with(",hp,queue_push,2,ns/iter,3,20,8,10,30,"));
+    }
+}
@@ -2,8 +2,9 @@
 
 # Run all benchmarks.
 
-SCHEMES="nothing hp hp-spin ebr cb"
-THREADS="1 2 4"
+THREADS="1,2,4"
+# Pin the threads, so that runs on the same machine are comparable.
+PLACEMENT="compact"
 DATE=`date +"%Y-%m-%d-%H:%M:%S"`
 
 OUTPUT="output-$DATE"
@@ -11,13 +12,10 @@ if [[ ! -d "$OUTPUT" ]] ; then mkdir "$OUTPUT" ; fi
 
 export RUSTFLAGS="-C target-cpu=native -C opt-level=3"
 
-for t in $(echo "$THREADS"); do
-  cargo run --release -- -t "$t" -d "$OUTPUT"
-done
+cargo run --release -- -t "$THREADS" -d "$OUTPUT" -p "$PLACEMENT" -f samples,json,csv
 
-for t in $(echo "$THREADS"); do
-  cargo run --release --features hp-wait -- -t "$t" -d "$OUTPUT" hp
-done
+# `hp-wait` is a feature of the build, so it needs a run of its own.
+cargo run --release --features hp-wait -- -t "$THREADS" -d "$OUTPUT" -p "$PLACEMENT" -f samples,json,csv -s hp
 
 # Since the date contains : we must tell tar to not interpret it as a port (or something).
 tar -zc --force-local -f "$DATE".tar.gz "$OUTPUT"/*
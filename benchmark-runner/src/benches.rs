@@ -2,12 +2,102 @@ use bench;
 
 use super::*;
 
-use bench::{black_box, StdThread};
+use bench::{black_box, Spawner, StdThread};
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Barrier};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::SeqCst;
+use std::thread;
+use std::time::Duration;
 
 use rand::Rng;
 
+use workload::{self, Generators};
+
 const DEBUG: bool = false;
 
+/// The resident memory of the process in kB, for `ThreadBencher::metric`. Memory that a scheme
+/// has not reclaimed yet shows up as growth of this over the samples.
+fn rss_kb<S>(_: &S) -> u64 {
+    let mut status = String::new();
+    File::open("/proc/self/status")
+        .and_then(|mut f| f.read_to_string(&mut status))
+        .ok()
+        .and_then(|_| {
+            status
+                .lines()
+                .find(|l| l.starts_with("VmRSS:"))
+                .and_then(|l| l.split_whitespace().nth(1))
+                .and_then(|kb| kb.parse().ok())
+        })
+        .unwrap_or(0)
+}
+
+/// The number of threads and their placement in the oversubscribed benchmarks: we run
+/// `OVERSUBSCRIPTION` threads on each of `num_threads` CPUs, so that there are more threads than
+/// cores regardless of the machine, and threads are preempted in the middle of operations.
+fn oversubscribed(num_threads: usize) -> (usize, bench::Placement) {
+    let cpus = bench::Placement::Compact
+        .cpus(num_threads)
+        .into_iter()
+        .map(|c| c.unwrap())
+        .collect();
+    (num_threads * OVERSUBSCRIPTION, bench::Placement::Cpus(cpus))
+}
+
+/// Handed to the function of a `Staller`.
+pub struct Stall {
+    started: Arc<Barrier>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Stall {
+    /// Let the benchmark start, and sleep until it is done. Call this inside the critical
+    /// section of the scheme.
+    pub fn wait(&self) {
+        self.started.wait();
+        while !self.stop.load(SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// A thread that stalls in the critical section of a scheme for a whole benchmark, as if it was
+/// descheduled or stopped by a page fault. Under EBR this stops all reclamation, and under HP
+/// it only stops the reclamation of what it protects.
+pub struct Staller<Sp> {
+    stop: Arc<AtomicBool>,
+    handle: Sp,
+}
+
+impl<Sp> Staller<Sp>
+where
+    Sp: Spawner<Return = (), Result = thread::Result<()>>,
+{
+    /// Start a thread that calls `f`, and wait until it has called `Stall::wait`.
+    pub fn start<F>(f: F) -> Self
+    where
+        F: FnOnce(&Stall) + Send + 'static,
+    {
+        let started = Arc::new(Barrier::new(2));
+        let stop = Arc::new(AtomicBool::new(false));
+        let stall = Stall {
+            started: started.clone(),
+            stop: stop.clone(),
+        };
+        let handle = Sp::spawn(move || f(&stall));
+        started.wait();
+        Staller { stop, handle }
+    }
+
+    pub fn stop(self) {
+        self.stop.store(true, SeqCst);
+        self.handle.join().unwrap();
+    }
+}
+
 pub mod hp {
 
     #[cfg(feature = "hp-wait")]
@@ -18,6 +108,8 @@ pub mod hp {
     use super::*;
     use comere::hp;
     use comere::hp::queue::Queue;
+    use comere::hp::seg_queue::SegQueue;
+    use comere::hp::wf_queue::WaitFreeQueue;
     use comere::hp::list::List;
 
     pub fn queue_push(num_threads: usize) -> bench::BenchStats {
@@ -93,6 +185,177 @@ pub mod hp {
         b.into_stats(format!("{}::queue::transfer::{}", NAME, num_threads))
     }
 
+    pub fn queue_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: Queue<u32>,
+        }
+
+        let state = State { queue: Queue::new() };
+
+        fn push_pop(state: &State) {
+            state.queue.push(1);
+            black_box(state.queue.pop());
+        }
+
+        let mut b = bench::ThreadBencher::<State, hp::JoinHandle<()>>::new(state, num_threads);
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("{}::queue::throughput::{}", NAME, num_threads))
+    }
+
+    pub fn queue_latency(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: Queue<u32>,
+        }
+
+        let state = State { queue: Queue::new() };
+
+        fn push_pop(state: &State) {
+            state.queue.push(1);
+            black_box(state.queue.pop());
+        }
+
+        let mut b = bench::ThreadBencher::<State, hp::JoinHandle<()>>::new(state, num_threads);
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.record_latency(true);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("{}::queue::latency::{}", NAME, num_threads))
+    }
+
+    pub fn queue_oversubscribed_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: Queue<u32>,
+        }
+
+        let state = State { queue: Queue::new() };
+
+        fn push_pop(state: &State) {
+            state.queue.push(1);
+            black_box(state.queue.pop());
+        }
+
+        let (n, placement) = oversubscribed(num_threads);
+        let mut b = bench::ThreadBencher::<State, hp::JoinHandle<()>>::with_placement(
+            state,
+            n,
+            &placement,
+        );
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.metric("rss_kb", rss_kb);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("{}::queue::oversubscribed_throughput::{}", NAME, num_threads))
+    }
+
+    pub fn queue_stalled_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: Arc<Queue<u32>>,
+        }
+
+        let state = State { queue: Arc::new(Queue::new()) };
+
+        fn push_pop(state: &State) {
+            state.queue.push(1);
+            black_box(state.queue.pop());
+        }
+
+        // The stalled thread pops a node, and sleeps while it still has a hazard pointer to it.
+        let queue = state.queue.clone();
+        queue.push(1);
+        let staller = Staller::<hp::JoinHandle<()>>::start(move |stall| {
+            queue.pop_hp_fn(|hp| {
+                stall.wait();
+                unsafe { hp.free() }
+            });
+        });
+        let mut b = bench::ThreadBencher::<State, hp::JoinHandle<()>>::new(state, num_threads);
+        b.set_n(STALLED_SAMPLES);
+        b.metric("rss_kb", rss_kb);
+        b.throughput_bench(push_pop, Duration::from_millis(STALLED_MILLIS));
+        let stats = b.into_stats(format!("{}::queue::stalled_throughput::{}", NAME, num_threads));
+        staller.stop();
+        stats
+    }
+
+    pub fn seg_queue_transfer(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            source: SegQueue<u32>,
+            sink: SegQueue<u32>,
+        }
+
+        let state = State {
+            source: SegQueue::new(),
+            sink: SegQueue::new(),
+        };
+
+        fn transfer(state: &State) {
+            while let Some(i) = state.source.pop() {
+                state.sink.push(i);
+            }
+        }
+
+        let mut b = bench::ThreadBencher::<State, hp::JoinHandle<()>>::new(state, num_threads);
+        b.before(|state| {
+            while let Some(_) = state.sink.pop() {}
+            for i in 0..NUM_ELEMENTS {
+                state.source.push(i as u32);
+            }
+        });
+        b.thread_bench(transfer);
+        b.into_stats(format!("{}::seg_queue::transfer::{}", NAME, num_threads))
+    }
+
+    pub fn wf_queue_transfer(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            source: WaitFreeQueue<u32>,
+            sink: WaitFreeQueue<u32>,
+            num_threads: usize,
+        }
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::cell::RefCell;
+        lazy_static! {
+            static ref THREAD_COUNTER: AtomicUsize = { AtomicUsize::new(0) };
+        }
+
+        thread_local! {
+            static THREAD_ID: RefCell<usize> = {
+                RefCell::new(THREAD_COUNTER.fetch_add(1, Ordering::SeqCst))
+            }
+        }
+
+        fn ti() -> usize {
+            THREAD_ID.with(|t| *t.borrow())
+        }
+
+        // The main thread fills the queues between the samples, so it gets the last `tid`.
+        let state = State {
+            source: WaitFreeQueue::new(num_threads + 1),
+            sink: WaitFreeQueue::new(num_threads + 1),
+            num_threads,
+        };
+
+        fn transfer(state: &State) {
+            let tid = ti();
+            while let Some(i) = state.source.pop(tid) {
+                state.sink.push(i, tid);
+            }
+        }
+
+        let mut b = bench::ThreadBencher::<State, hp::JoinHandle<()>>::new(state, num_threads);
+        b.before(|state| {
+            let tid = state.num_threads;
+            while let Some(_) = state.sink.pop(tid) {}
+            for i in 0..NUM_ELEMENTS {
+                state.source.push(i as u32, tid);
+            }
+        });
+
+        THREAD_COUNTER.store(0, Ordering::SeqCst);
+
+        b.thread_bench(transfer);
+        b.into_stats(format!("{}::wf_queue::transfer::{}", NAME, num_threads))
+    }
+
     pub fn list_remove(num_threads: usize) -> bench::BenchStats {
         struct State {
             list: List<u32>,
@@ -156,17 +419,19 @@ pub mod hp {
     pub fn list_real(num_threads: usize) -> bench::BenchStats {
         struct State {
             list: List<u32>,
+            workload: Generators,
         }
 
         let state = State {
             list: List::new(),
+            workload: workload::current().generators(),
         };
 
         fn real(state: &State) {
-            let mut rng = rand::thread_rng();
+            let mut ops = state.workload.generator();
             for _i in 0..NUM_ELEMENTS_SMALLER {
-                use super::Operation::*;
-                let op = random_op(&mut rng);
+                use workload::Operation::*;
+                let op = ops.next_op();
                 match op {
                     Insert(n) => {
                         let r = state.list.insert(n);
@@ -191,10 +456,7 @@ pub mod hp {
         let mut b = bench::ThreadBencher::<State, hp::JoinHandle<()>>::new(state, num_threads);
         b.before(|state| {
             while let Some(_) = state.list.remove_front() {}
-            let mut rng = rand::thread_rng();
-            let mut n: Vec<u32> = (0..NUM_ELEMENTS_SMALLER as u32).collect();
-            rng.shuffle(&mut n);
-            for &i in &n {
+            for &i in &state.workload.initial_keys() {
                 state.list.insert(i);
             }
         });
@@ -216,6 +478,8 @@ pub mod ebr {
     use super::*;
     use comere::ebr;
     use comere::ebr::queue::Queue;
+    use comere::ebr::seg_queue::SegQueue;
+    use comere::ebr::wf_queue::WaitFreeQueue;
     use comere::ebr::list::List;
 
     pub fn queue_push(num_threads: usize) -> bench::BenchStats {
@@ -297,6 +561,179 @@ pub mod ebr {
         b.into_stats(format!("ebr::queue::transfer::{}", num_threads))
     }
 
+    pub fn queue_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: Queue<u32>,
+        }
+
+        let state = State { queue: Queue::new() };
+
+        fn push_pop(state: &State) {
+            ebr::pin(|pin| {
+                state.queue.push(1, pin);
+                black_box(state.queue.pop(pin));
+            });
+        }
+
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("ebr::queue::throughput::{}", num_threads))
+    }
+
+    pub fn queue_latency(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: Queue<u32>,
+        }
+
+        let state = State { queue: Queue::new() };
+
+        fn push_pop(state: &State) {
+            ebr::pin(|pin| {
+                state.queue.push(1, pin);
+                black_box(state.queue.pop(pin));
+            });
+        }
+
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.record_latency(true);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("ebr::queue::latency::{}", num_threads))
+    }
+
+    pub fn queue_oversubscribed_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: Queue<u32>,
+        }
+
+        let state = State { queue: Queue::new() };
+
+        fn push_pop(state: &State) {
+            ebr::pin(|pin| {
+                state.queue.push(1, pin);
+                black_box(state.queue.pop(pin));
+            });
+        }
+
+        let (n, placement) = oversubscribed(num_threads);
+        let mut b =
+            bench::ThreadBencher::<State, StdThread<()>>::with_placement(state, n, &placement);
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.metric("rss_kb", rss_kb);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("ebr::queue::oversubscribed_throughput::{}", num_threads))
+    }
+
+    pub fn queue_stalled_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: Queue<u32>,
+        }
+
+        let state = State { queue: Queue::new() };
+
+        fn push_pop(state: &State) {
+            ebr::pin(|pin| {
+                state.queue.push(1, pin);
+                black_box(state.queue.pop(pin));
+            });
+        }
+
+        // The stalled thread keeps its epoch pinned, so the global epoch cannot advance.
+        let staller = Staller::<StdThread<()>>::start(|stall| ebr::pin(|_pin| stall.wait()));
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.set_n(STALLED_SAMPLES);
+        b.metric("rss_kb", rss_kb);
+        b.throughput_bench(push_pop, Duration::from_millis(STALLED_MILLIS));
+        let stats = b.into_stats(format!("ebr::queue::stalled_throughput::{}", num_threads));
+        staller.stop();
+        stats
+    }
+
+    pub fn seg_queue_transfer(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            source: SegQueue<u32>,
+            sink: SegQueue<u32>,
+        }
+
+        let state = State {
+            source: SegQueue::new(),
+            sink: SegQueue::new(),
+        };
+
+        fn transfer(state: &State) {
+            while let Some(i) = ebr::pin(|pin| state.source.pop(pin)) {
+                ebr::pin(|pin| state.sink.push(i, pin));
+            }
+        }
+
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.before(|state| {
+            ebr::pin(|pin| {
+                while let Some(_) = state.sink.pop(pin) {}
+                for i in 0..NUM_ELEMENTS {
+                    state.source.push(i as u32, pin);
+                }
+            });
+        });
+        b.thread_bench(transfer);
+        b.into_stats(format!("ebr::seg_queue::transfer::{}", num_threads))
+    }
+
+    pub fn wf_queue_transfer(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            source: WaitFreeQueue<u32>,
+            sink: WaitFreeQueue<u32>,
+            num_threads: usize,
+        }
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::cell::RefCell;
+        lazy_static! {
+            static ref THREAD_COUNTER: AtomicUsize = { AtomicUsize::new(0) };
+        }
+
+        thread_local! {
+            static THREAD_ID: RefCell<usize> = {
+                RefCell::new(THREAD_COUNTER.fetch_add(1, Ordering::SeqCst))
+            }
+        }
+
+        fn ti() -> usize {
+            THREAD_ID.with(|t| *t.borrow())
+        }
+
+        // The main thread fills the queues between the samples, so it gets the last `tid`.
+        let state = State {
+            source: WaitFreeQueue::new(num_threads + 1),
+            sink: WaitFreeQueue::new(num_threads + 1),
+            num_threads,
+        };
+
+        fn transfer(state: &State) {
+            let tid = ti();
+            while let Some(i) = ebr::pin(|pin| state.source.pop(tid, pin)) {
+                ebr::pin(|pin| state.sink.push(i, tid, pin));
+            }
+        }
+
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.before(|state| {
+            let tid = state.num_threads;
+            ebr::pin(|pin| {
+                while let Some(_) = state.sink.pop(tid, pin) {}
+                for i in 0..NUM_ELEMENTS {
+                    state.source.push(i as u32, tid, pin);
+                }
+            });
+        });
+
+        THREAD_COUNTER.store(0, Ordering::SeqCst);
+
+        b.thread_bench(transfer);
+        b.into_stats(format!("ebr::wf_queue::transfer::{}", num_threads))
+    }
+
     pub fn list_remove(num_threads: usize) -> bench::BenchStats {
         struct State {
             list: List<u32>,
@@ -359,17 +796,19 @@ pub mod ebr {
     pub fn list_real(num_threads: usize) -> bench::BenchStats {
         struct State {
             list: List<u32>,
+            workload: Generators,
         }
 
         let state = State {
             list: List::new(),
+            workload: workload::current().generators(),
         };
 
         fn real(state: &State) {
-            let mut rng = rand::thread_rng();
+            let mut ops = state.workload.generator();
             for _i in 0..NUM_ELEMENTS_SMALLER {
-                use super::Operation::*;
-                let op = random_op(&mut rng);
+                use workload::Operation::*;
+                let op = ops.next_op();
                 ebr::pin(|pin| match op {
                     Insert(n) => {
                         let r = state.list.insert(n, pin);
@@ -394,9 +833,7 @@ pub mod ebr {
         let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
         b.before(|state| {
             ebr::pin(|pin| while let Some(_) = state.list.remove_front(pin) {});
-            let mut rng = rand::thread_rng();
-            let mut n: Vec<u32> = (0..NUM_ELEMENTS_SMALLER as u32).collect();
-            rng.shuffle(&mut n);
+            let n = state.workload.initial_keys();
             ebr::pin(|pin| for &i in &n {
                 state.list.insert(i, pin);
             });
@@ -492,6 +929,89 @@ pub mod crossbeam {
         b.into_stats(format!("crossbeam::queue::transfer::{}", num_threads))
     }
 
+    pub fn queue_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: MsQueue<u32>,
+        }
+
+        let state = State { queue: MsQueue::new() };
+
+        fn push_pop(state: &State) {
+            state.queue.push(1);
+            black_box(state.queue.try_pop());
+        }
+
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("crossbeam::queue::throughput::{}", num_threads))
+    }
+
+    pub fn queue_latency(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: MsQueue<u32>,
+        }
+
+        let state = State { queue: MsQueue::new() };
+
+        fn push_pop(state: &State) {
+            state.queue.push(1);
+            black_box(state.queue.try_pop());
+        }
+
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.record_latency(true);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("crossbeam::queue::latency::{}", num_threads))
+    }
+
+    pub fn queue_oversubscribed_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: MsQueue<u32>,
+        }
+
+        let state = State { queue: MsQueue::new() };
+
+        fn push_pop(state: &State) {
+            state.queue.push(1);
+            black_box(state.queue.try_pop());
+        }
+
+        let (n, placement) = oversubscribed(num_threads);
+        let mut b =
+            bench::ThreadBencher::<State, StdThread<()>>::with_placement(state, n, &placement);
+        b.set_n(THROUGHPUT_SAMPLES);
+        b.metric("rss_kb", rss_kb);
+        b.throughput_bench(push_pop, Duration::from_millis(THROUGHPUT_MILLIS));
+        b.into_stats(format!("crossbeam::queue::oversubscribed_throughput::{}", num_threads))
+    }
+
+    pub fn queue_stalled_throughput(num_threads: usize) -> bench::BenchStats {
+        struct State {
+            queue: MsQueue<u32>,
+        }
+
+        let state = State { queue: MsQueue::new() };
+
+        fn push_pop(state: &State) {
+            state.queue.push(1);
+            black_box(state.queue.try_pop());
+        }
+
+        let staller = Staller::<StdThread<()>>::start(|stall| {
+            let _guard = ::crossbeam::epoch::pin();
+            stall.wait();
+        });
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.set_n(STALLED_SAMPLES);
+        b.metric("rss_kb", rss_kb);
+        b.throughput_bench(push_pop, Duration::from_millis(STALLED_MILLIS));
+        let stats = b.into_stats(format!("crossbeam::queue::stalled_throughput::{}", num_threads));
+        staller.stop();
+        stats
+    }
+
     pub fn nop(num_threads: usize) -> bench::BenchStats {
         #[inline(never)]
         fn nop(_s: &()) {}
@@ -585,6 +1105,36 @@ pub mod nothing {
         b.into_stats(format!("nothing::queue::transfer::{}", num_threads))
     }
 
+    pub fn array_queue_transfer(num_threads: usize) -> bench::BenchStats {
+        use comere::nothing::array_queue::ArrayQueue;
+
+        struct State {
+            source: ArrayQueue<u32>,
+            sink: ArrayQueue<u32>,
+        }
+
+        let state = State {
+            source: ArrayQueue::new(NUM_ELEMENTS),
+            sink: ArrayQueue::new(NUM_ELEMENTS),
+        };
+
+        fn transfer(state: &State) {
+            while let Some(i) = state.source.pop() {
+                assert!(state.sink.push(i).is_ok());
+            }
+        }
+
+        let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
+        b.before(|state| {
+            while let Some(_) = state.sink.pop() {}
+            for i in 0..NUM_ELEMENTS {
+                assert!(state.source.push(i as u32).is_ok());
+            }
+        });
+        b.thread_bench(transfer);
+        b.into_stats(format!("nothing::array_queue::transfer::{}", num_threads))
+    }
+
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::cell::RefCell;
     lazy_static! {
@@ -636,15 +1186,19 @@ pub mod nothing {
     pub fn list_real(num_threads: usize) -> bench::BenchStats {
         struct State {
             list: List<u32>,
+            workload: Generators,
         }
 
-        let state = State { list: List::new() };
+        let state = State {
+            list: List::new(),
+            workload: workload::current().generators(),
+        };
 
         fn real(state: &State) {
-            let mut rng = rand::thread_rng();
+            let mut ops = state.workload.generator();
             for _ in 0..NUM_ELEMENTS_SMALLER {
-                use super::Operation::*;
-                match random_op(&mut rng) {
+                use workload::Operation::*;
+                match ops.next_op() {
                     Insert(n) => {
                         // println!(" t{} insert", ti);
                         let r = state.list.insert(n, None);
@@ -673,10 +1227,7 @@ pub mod nothing {
         let mut b = bench::ThreadBencher::<State, StdThread<()>>::new(state, num_threads);
         b.before(|state| {
             while let Some(_) = state.list.remove_front() {}
-            let mut rng = rand::thread_rng();
-            let mut n: Vec<u32> = (0..NUM_ELEMENTS_SMALLER as u32).collect();
-            rng.shuffle(&mut n);
-            for &i in &n {
+            for &i in &state.workload.initial_keys() {
                 state.list.insert(i, None);
             }
         });
@@ -695,25 +1246,3 @@ pub mod nothing {
         b.into_stats(format!("nothing::nop::{}", num_threads))
     }
 }
-
-#[derive(Debug)]
-enum Operation {
-    Insert(u32),
-    Search(u32),
-    Remove(u32),
-    PopFront,
-}
-
-fn random_op<R: Rng>(rng: &mut R) -> Operation {
-    let r = rng.gen_range(0, 10);
-    let n = rng.gen_range(0, NUM_ELEMENTS_SMALLER as u32);
-    if r < 4 {
-        Operation::Insert(n)
-    } else if r < 6 {
-        Operation::Search(n)
-    } else if r < 8 {
-        Operation::Remove(n)
-    } else {
-        Operation::PopFront
-    }
-}
@@ -0,0 +1,304 @@
+/// Compare two benchmark runs.
+///
+/// A run is either an output directory of `benchmark-runner`, or an archive made by
+/// `benchmark.sh`. We match the sample files `s:<variant>-b:<name>-t:<threads>` of the two runs,
+/// and for each benchmark in both runs we report the relative change of the average, with a
+/// bootstrap confidence interval. If the interval does not contain 0, the change is significant.
+/// Whether a change is a regression depends on the unit, which is on a `# unit: ` line in the
+/// sample file.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use bench::Unit;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+/// The number of bootstrap resamples.
+const RESAMPLES: usize = 2000;
+/// The confidence level of the intervals, in percent.
+const CONFIDENCE: f64 = 95.0;
+
+/// A benchmark, as `(variant, name, threads)`.
+type Key = (String, String, usize);
+
+/// The samples of one benchmark.
+struct Samples {
+    /// The unit of the samples, or `None` if the file was written before we recorded it.
+    unit: Option<Unit>,
+    values: Vec<u64>,
+}
+
+/// Parse a sample file name, `s:<variant>-b:<name>-t:<threads>`.
+fn parse_filename(filename: &str) -> Option<Key> {
+    if !filename.starts_with("s:") {
+        return None;
+    }
+    let rest = &filename[2..];
+    let (b, t) = match (rest.find("-b:"), rest.rfind("-t:")) {
+        (Some(b), Some(t)) if b < t => (b, t),
+        _ => return None,
+    };
+    rest[t + 3..].parse().ok().map(|threads| {
+        (rest[..b].to_string(), rest[b + 3..t].to_string(), threads)
+    })
+}
+
+/// Find all sample files under `dir`, and read them.
+fn read_dir(dir: &Path, results: &mut BTreeMap<Key, Samples>) {
+    for entry in fs::read_dir(dir).expect("failed to read directory") {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            read_dir(&path, results);
+            continue;
+        }
+        let key = match path.file_name().and_then(|f| f.to_str()).and_then(parse_filename) {
+            Some(key) => key,
+            None => continue,
+        };
+        let file = File::open(&path).expect("failed to open sample file");
+        let mut unit = None;
+        let mut values = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.expect("failed to read sample file");
+            let line = line.trim();
+            if line.starts_with("# unit:") {
+                unit = Some(line["# unit:".len()..].trim().parse().unwrap_or_else(
+                    |e| panic!("{} in '{}'", e, path.display()),
+                ));
+            } else if let Ok(value) = line.parse() {
+                values.push(value);
+            }
+        }
+        if !values.is_empty() {
+            results.insert(key, Samples { unit, values });
+        }
+    }
+}
+
+/// Read a run from a directory, or from a `.tar.gz` archive, which we unpack to a temporary
+/// directory with `tar`.
+fn load(path: &str) -> BTreeMap<Key, Samples> {
+    let mut results = BTreeMap::new();
+    if path.ends_with(".tar.gz") {
+        let dir = unpack_dir(path);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("failed to make temporary directory");
+        // The archive names contain `:`, so we must tell tar it is not a remote host.
+        let status = Command::new("tar")
+            .args(&["-xzf", path, "--force-local", "-C"])
+            .arg(&dir)
+            .status()
+            .expect("failed to run tar");
+        assert!(status.success(), "tar failed to unpack {}", path);
+        read_dir(&dir, &mut results);
+        let _ = fs::remove_dir_all(&dir);
+    } else {
+        read_dir(Path::new(path), &mut results);
+    }
+    if results.is_empty() {
+        panic!("No sample files were found in '{}'", path);
+    }
+    results
+}
+
+fn unpack_dir(path: &str) -> PathBuf {
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or("archive")
+        .replace(':', "_");
+    ::std::env::temp_dir().join(format!("benchmark-compare-{}-{}", ::std::process::id(), name))
+}
+
+fn mean(samples: &[u64]) -> f64 {
+    samples.iter().map(|&s| s as f64).sum::<f64>() / samples.len() as f64
+}
+
+/// The mean of `samples` drawn with replacement.
+fn resample_mean<R: Rng>(samples: &[u64], rng: &mut R) -> f64 {
+    let n = samples.len();
+    (0..n).map(|_| samples[rng.gen_range(0, n)] as f64).sum::<f64>() / n as f64
+}
+
+/// The change from `old` to `new`, in percent. This is infinite if `old` is 0 and `new` is
+/// not, but never NaN.
+fn percent_change(old: f64, new: f64) -> f64 {
+    if old == new {
+        0.0
+    } else {
+        (new - old) / old * 100.0
+    }
+}
+
+/// The relative change of the mean from `old` to `new`, in percent, with a bootstrap
+/// confidence interval. Returns `None` if the mean of `old` is 0, since there is no relative
+/// change from 0.
+fn relative_change<R: Rng>(old: &[u64], new: &[u64], rng: &mut R) -> Option<(f64, f64, f64)> {
+    if mean(old) == 0.0 {
+        return None;
+    }
+    let change = percent_change(mean(old), mean(new));
+    // A resample of `old` may still be all zeros, but then the change is infinite, which sorts
+    // fine.
+    let mut changes = (0..RESAMPLES)
+        .map(|_| {
+            let o = resample_mean(old, rng);
+            let n = resample_mean(new, rng);
+            percent_change(o, n)
+        })
+        .collect::<Vec<f64>>();
+    changes.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let tail = (100.0 - CONFIDENCE) / 200.0;
+    let low = changes[(tail * RESAMPLES as f64) as usize];
+    let high = changes[((1.0 - tail) * RESAMPLES as f64) as usize - 1];
+    Some((change, low, high))
+}
+
+/// Is a larger value better for this benchmark? We go by the unit of the samples. Sample files
+/// written before we recorded the unit do not say what they measure, so for these we go by the
+/// name: the throughput and latency benchmarks both run for a fixed time and count operations,
+/// and the rest measure time.
+fn higher_is_better(name: &str, unit: Option<Unit>) -> bool {
+    match unit {
+        Some(unit) => unit.higher_is_better(),
+        None => name.contains("throughput") || name.contains("latency"),
+    }
+}
+
+/// Compare the runs at `old` and `new`, and print a report. Returns the number of significant
+/// regressions.
+pub fn compare(old: &str, new: &str) -> usize {
+    let old_results = load(old);
+    let new_results = load(new);
+    let mut rng = XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb]);
+
+    println!(
+        "{:<40} {:>14} {:>14} {:>9}  {:<22}",
+        "benchmark",
+        "old",
+        "new",
+        "change",
+        format!("{}% interval", CONFIDENCE)
+    );
+    let mut regressions = 0;
+    for (key, old_samples) in old_results.iter() {
+        let new_samples = match new_results.get(key) {
+            Some(s) => s,
+            None => continue,
+        };
+        let unit = match (old_samples.unit, new_samples.unit) {
+            (Some(old_unit), Some(new_unit)) if old_unit != new_unit => {
+                println!(
+                    "unit changed: {}::{}::{:02} ({} -> {})",
+                    key.0,
+                    key.1,
+                    key.2,
+                    old_unit.suffix(),
+                    new_unit.suffix()
+                );
+                continue;
+            }
+            (old_unit, new_unit) => new_unit.or(old_unit),
+        };
+        let old_samples = &old_samples.values;
+        let new_samples = &new_samples.values;
+        let (change, low, high) = match relative_change(old_samples, new_samples, &mut rng) {
+            Some(c) => c,
+            None => {
+                println!("old mean is 0: {}::{}::{:02}", key.0, key.1, key.2);
+                continue;
+            }
+        };
+        let significant = low > 0.0 || high < 0.0;
+        let worse = if higher_is_better(&key.1, unit) {
+            change < 0.0
+        } else {
+            change > 0.0
+        };
+        let flag = match (significant, worse) {
+            (true, true) => {
+                regressions += 1;
+                "REGRESSION"
+            }
+            (true, false) => "improvement",
+            (false, _) => "",
+        };
+        println!(
+            "{:<40} {:>14.0} {:>14.0} {:>+8.2}%  [{:+.2}%, {:+.2}%] {}",
+            format!("{}::{}::{:02}", key.0, key.1, key.2),
+            mean(old_samples),
+            mean(new_samples),
+            change,
+            low,
+            high,
+            flag
+        );
+    }
+
+    for key in old_results.keys().filter(|k| !new_results.contains_key(k)) {
+        println!("only in old: {}::{}::{:02}", key.0, key.1, key.2);
+    }
+    for key in new_results.keys().filter(|k| !old_results.contains_key(k)) {
+        println!("only in new: {}::{}::{:02}", key.0, key.1, key.2);
+    }
+    println!("{} significant regression(s)", regressions);
+    regressions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb])
+    }
+
+    #[test]
+    fn parse_filename_dashes() {
+        assert_eq!(
+            parse_filename("s:ebr-b:queue_push-t:04"),
+            Some(("ebr".to_string(), "queue_push".to_string(), 4))
+        );
+        assert_eq!(
+            parse_filename("s:hp-wait-b:list-real-t:16"),
+            Some(("hp-wait".to_string(), "list-real".to_string(), 16))
+        );
+    }
+
+    #[test]
+    fn parse_filename_rejects() {
+        assert_eq!(parse_filename("h:ebr-b:queue_latency-t:04"), None);
+        assert_eq!(parse_filename("r:default-t:01,02.json"), None);
+        assert_eq!(parse_filename("s:ebr-b:queue_push"), None);
+        assert_eq!(parse_filename("s:ebr-b:queue_push-t:x"), None);
+    }
+
+    #[test]
+    fn shifted_is_significant() {
+        let old = (0..100).map(|i| 1000 + i % 10).collect::<Vec<u64>>();
+        let new = old.iter().map(|&s| s * 3 / 2).collect::<Vec<u64>>();
+        let (change, low, high) = relative_change(&old, &new, &mut rng()).unwrap();
+        assert!((change - 50.0).abs() < 1.0);
+        assert!(low > 0.0 && low <= change && change <= high);
+    }
+
+    #[test]
+    fn identical_is_not_significant() {
+        let old = (0..100).map(|i| 1000 + i % 10).collect::<Vec<u64>>();
+        let (change, low, high) = relative_change(&old, &old, &mut rng()).unwrap();
+        assert_eq!(change, 0.0);
+        assert!(low <= 0.0 && 0.0 <= high);
+    }
+
+    #[test]
+    fn zero_mean_old() {
+        assert_eq!(relative_change(&[0, 0, 0], &[1, 2, 3], &mut rng()), None);
+        assert_eq!(relative_change(&[0, 0, 0], &[0, 0, 0], &mut rng()), None);
+        // Some resamples of `old` are all zeros, which must not panic.
+        let (_, _, high) = relative_change(&[0, 0, 1], &[1, 1, 1], &mut rng()).unwrap();
+        assert!(high.is_infinite());
+    }
+}
@@ -1,3 +1,4 @@
+#![feature(global_allocator, allocator_api)]
 #[macro_use]
 extern crate clap;
 extern crate crossbeam;
@@ -6,18 +7,38 @@ extern crate rand;
 #[macro_use]
 extern crate lazy_static;
 extern crate bench;
+extern crate time;
 
-use std::io::Write;
+use std::io::{Read, Write};
 use std::fs::File;
 use std::path::Path;
+use std::time::{Duration, Instant};
 
 mod benches;
+mod compare;
+mod memory;
+mod metadata;
+mod workload;
 use benches::{nothing, hp, ebr, crossbeam as cb};
 pub const NUM_ELEMENTS: usize = 256 * 256;
 pub const NUM_ELEMENTS_NOTHING: usize = 256 * 256;
 pub const NUM_ELEMENTS_SMALLER: usize = 256 * 4;
+/// The duration of each sample, and the number of samples, of the throughput benchmarks.
+pub const THROUGHPUT_MILLIS: u64 = 100;
+pub const THROUGHPUT_SAMPLES: usize = 20;
+/// The number of threads on each CPU in the oversubscribed benchmarks.
+pub const OVERSUBSCRIPTION: usize = 4;
+/// The duration of each sample, and the number of samples, of the stalled benchmarks. Under EBR,
+/// all memory retired during the benchmark is kept until the stalled thread wakes up, which is
+/// hundreds of MB per second and core, so we keep these short.
+pub const STALLED_MILLIS: u64 = 20;
+pub const STALLED_SAMPLES: usize = 10;
 
 
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: memory::Counting = memory::Counting;
+
 /// We need this, as somehow `(fn, String)` is not okay, while `(F(fn), String)` is.
 pub struct F(pub fn(usize) -> bench::BenchStats);
 
@@ -39,21 +60,38 @@ macro_rules! S {
 fn main() {
     let benches = S!(
         cb::nop,
+        cb::queue_latency,
+        cb::queue_oversubscribed_throughput,
         cb::queue_pop,
         cb::queue_push,
+        cb::queue_stalled_throughput,
+        cb::queue_throughput,
         cb::queue_transfer,
         ebr::list_remove,
         ebr::list_real,
         ebr::nop,
+        ebr::queue_latency,
+        ebr::queue_oversubscribed_throughput,
         ebr::queue_pop,
         ebr::queue_push,
+        ebr::queue_stalled_throughput,
+        ebr::queue_throughput,
         ebr::queue_transfer,
+        ebr::seg_queue_transfer,
+        ebr::wf_queue_transfer,
         hp::list_remove,
         hp::list_real,
         hp::nop,
+        hp::queue_latency,
+        hp::queue_oversubscribed_throughput,
         hp::queue_pop,
         hp::queue_push,
+        hp::queue_stalled_throughput,
+        hp::queue_throughput,
         hp::queue_transfer,
+        hp::seg_queue_transfer,
+        hp::wf_queue_transfer,
+        nothing::array_queue_transfer,
         nothing::list_remove,
         nothing::list_real,
         nothing::nop,
@@ -65,51 +103,270 @@ fn main() {
     let matches = clap_app!(benchmark_runner =>
         (version: "1.0")
         (author: "Martin Hafskjold Thoresen <martinhath@gmail.com>")
-        (@arg num_threads: -t +takes_value "Sets the number of threads in the benchmark")
+        (@arg num_threads: -t +takes_value {validate_threads}
+            "Sets the numbers of threads to run the benchmarks with, as a list of numbers and \
+             ranges, like 1,2,4-8")
+        (@arg repeat: -r --repeat +takes_value {validate_repeat}
+            "Runs each benchmark this many times, and merges the samples of the runs")
+        (@arg placement: -p --placement +takes_value {validate_placement}
+            "Pins the threads to CPUs: compact, scatter, physical (one thread per physical \
+             core), a list of CPUs like 0,2,4-7, or none. Defaults to none")
+        (@arg workload: -w --workload +takes_value
+            "Sets the workload of the list_real benchmarks, as key=value pairs separated by \
+             commas, like insert=40,search=20,remove=20,pop=20,keys=1024,dist=zipf:0.99,\
+             fill=1024,seed=42. dist is uniform, zipf:<exponent>, \
+             hotspot:<hot fraction>:<probability> or sequential")
+        (@arg workload_file: --("workload-file") +takes_value
+            "Reads the workload from a file, with one or more pairs on each line and # \
+             comments. Pairs given with --workload override the ones in the file")
+        (@arg scheme: -s --scheme +takes_value +use_delimiter
+            possible_values(&["nothing", "hp", "ebr", "crossbeam"])
+            "Only runs the benchmarks of these schemes, separated by commas")
         (@arg output_dir: -d +takes_value "Sets the output directory")
         (@arg name: +takes_value "The name of the benchmarks that is ran")
         (@arg stdout: --stdout "Print results to stdout")
+        (@arg format: -f --format +takes_value +use_delimiter
+            possible_values(&["samples", "json", "csv"])
+            "The formats to write the results in, separated by commas. Defaults to samples")
+        (@subcommand compare =>
+            (about: "Compares the results of two runs, and flags significant regressions")
+            (@arg old: +required "The old run, as an output directory or a .tar.gz archive")
+            (@arg new: +required "The new run, as an output directory or a .tar.gz archive"))
     ).get_matches();
 
-    let num_threads: usize = value_t!(matches, "num_threads", String)
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(4);
+    if let Some(matches) = matches.subcommand_matches("compare") {
+        let regressions = compare::compare(
+            matches.value_of("old").unwrap(),
+            matches.value_of("new").unwrap(),
+        );
+        ::std::process::exit(if regressions > 0 { 1 } else { 0 });
+    }
+
+    let threads = matches
+        .value_of("num_threads")
+        .map(|s| parse_threads(s).unwrap())
+        .unwrap_or(vec![4]);
+    let repeat = matches
+        .value_of("repeat")
+        .map(|s| parse_repeat(s).unwrap())
+        .unwrap_or(1);
     let filter_name = value_t!(matches, "name", String).unwrap_or("".to_string());
+    let schemes: Vec<&str> = matches
+        .values_of("scheme")
+        .map(|v| v.map(|s| if s == "crossbeam" { "cb" } else { s }).collect())
+        .unwrap_or(vec!["nothing", "hp", "ebr", "cb"]);
     let output_dir = value_t!(matches, "output_dir", String).unwrap_or(".".to_string());
     let stdout = matches.is_present("stdout");
+    let formats: Vec<&str> = matches
+        .values_of("format")
+        .map(|v| v.collect())
+        .unwrap_or(vec!["samples"]);
+    let placement: bench::Placement = matches
+        .value_of("placement")
+        .map(|p| p.parse().unwrap())
+        .unwrap_or(bench::Placement::None);
+    bench::affinity::set_default_placement(placement.clone());
+    let mut workload = match read_workload(
+        matches.value_of("workload_file"),
+        matches.value_of("workload"),
+    ) {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ::std::process::exit(1);
+        }
+    };
+    // Pick the seed here, so that the metadata has everything needed to rerun the workload.
+    if workload.seed.is_none() {
+        workload.seed = Some(rand::random());
+    }
+    workload::set(workload.clone());
+    let meta = metadata::collect(&threads, &placement, &workload);
+    // Reports go to stderr if stdout is for JSON or CSV, so that it can be parsed.
+    let quiet = stdout && formats.iter().any(|&f| f != "samples");
 
-    let stats: Vec<bench::BenchStats> = benches
+    let benches = benches
         .iter()
-        .filter(|&&(_, ref name)| name.contains(&filter_name))
-        .map(|&(ref f, ref name)| {
-            println!("calling {}", name);
-            f.call(num_threads)
+        .filter(|&&(_, ref name)| {
+            name.contains(&filter_name) && schemes.contains(&name.split("::").next().unwrap())
         })
-        .collect();
+        .collect::<Vec<_>>();
+    let total = threads.len() * benches.len() * repeat;
+    let start = Instant::now();
+    let mut done = 0;
+    let mut stats: Vec<bench::BenchStats> = vec![];
+    for &num_threads in threads.iter() {
+        for &&(ref f, ref name) in benches.iter() {
+            bench::set_default_metrics(memory::metrics(name.split("::").next().unwrap()));
+            let mut merged: Option<bench::BenchStats> = None;
+            for r in 0..repeat {
+                eprintln!(
+                    "[{}/{}] {} t={} run={}/{} elapsed={} eta={}",
+                    done + 1,
+                    total,
+                    name,
+                    num_threads,
+                    r + 1,
+                    repeat,
+                    fmt_duration(start.elapsed()),
+                    eta(start.elapsed(), done, total)
+                );
+                memory::reset();
+                let run = f.call(num_threads);
+                if quiet {
+                    eprintln!("{}", run.report());
+                } else {
+                    println!("{}", run.report());
+                }
+                merged = Some(match merged {
+                    Some(mut m) => {
+                        m.merge(run);
+                        m
+                    }
+                    None => run,
+                });
+                done += 1;
+            }
+            stats.extend(merged);
+        }
+    }
     if stats.len() == 0 {
         panic!(
-            "No benchmarks were left after matching with the pattern '{}'",
-            filter_name
+            "No benchmarks were left after matching with the pattern '{}' and the schemes {:?}",
+            filter_name,
+            schemes
         );
     }
     if stdout {
-        for stat in stats.iter() {
+        if formats.contains(&"samples") {
+            print_samples(&stats);
+        }
+        if formats.contains(&"json") {
+            print!("{}", bench::output::json(&stats, &meta));
+        }
+        if formats.contains(&"csv") {
+            print!("{}", bench::output::csv(&stats, &meta));
+        }
+        return;
+    }
+
+    if formats.contains(&"samples") {
+        write_samples(&stats, &output_dir);
+    }
+    // `benchmark.sh` runs us once for every set of features, with the same output directory, so
+    // these go in one file for each.
+    let features = meta.features.join("+");
+    let results_filename = format!(
+        "r:{}-t:{}",
+        if features.is_empty() { "default" } else { &features },
+        threads.iter().map(|t| format!("{:02}", t)).collect::<Vec<_>>().join(",")
+    );
+    if formats.contains(&"json") {
+        let path = Path::new(&output_dir).join(format!("{}.json", results_filename));
+        let mut file = File::create(path).unwrap();
+        write!(&mut file, "{}", bench::output::json(&stats, &meta)).unwrap();
+    }
+    if formats.contains(&"csv") {
+        let path = Path::new(&output_dir).join(format!("{}.csv", results_filename));
+        let mut file = File::create(path).unwrap();
+        write!(&mut file, "{}", bench::output::csv(&stats, &meta)).unwrap();
+    }
+}
+
+/// Parse a list of thread counts, like `1,2,4-8`, see `bench::affinity::parse_list`.
+fn parse_threads(s: &str) -> Result<Vec<usize>, String> {
+    let threads = bench::affinity::parse_list(s)?;
+    if threads.contains(&0) {
+        return Err("0 is not a positive number of threads".to_string());
+    }
+    Ok(threads)
+}
+
+fn validate_threads(s: String) -> Result<(), String> {
+    parse_threads(&s).map(|_| ())
+}
+
+fn parse_repeat(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("'{}' is not a positive number of runs", s)),
+    }
+}
+
+fn validate_repeat(s: String) -> Result<(), String> {
+    parse_repeat(&s).map(|_| ())
+}
+
+fn validate_placement(s: String) -> Result<(), String> {
+    s.parse::<bench::Placement>().and_then(|p| p.check())
+}
+
+/// The workload of the file, if any, with the pairs of `spec` on top.
+fn read_workload(file: Option<&str>, spec: Option<&str>) -> Result<workload::Workload, String> {
+    let mut s = String::new();
+    if let Some(path) = file {
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut s))
+            .map_err(|e| format!("could not read the workload file {}: {}", path, e))?;
+    }
+    if let Some(spec) = spec {
+        s.push('\n');
+        s.push_str(spec);
+    }
+    s.parse()
+        .map_err(|e| match file {
+            Some(path) => format!("invalid workload in {}: {}", path, e),
+            None => format!("invalid workload: {}", e),
+        })
+}
+
+fn fmt_duration(d: Duration) -> String {
+    let s = d.as_secs();
+    if s >= 3600 {
+        format!("{}h{:02}m{:02}s", s / 3600, s / 60 % 60, s % 60)
+    } else if s >= 60 {
+        format!("{}m{:02}s", s / 60, s % 60)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+/// Estimate the time left, assuming the remaining runs take as long as the average run so far.
+fn eta(elapsed: Duration, done: usize, total: usize) -> String {
+    if done == 0 {
+        return "?".to_string();
+    }
+    let per_run = (elapsed.as_secs() * 1_000 + elapsed.subsec_nanos() as u64 / 1_000_000) /
+        done as u64;
+    fmt_duration(Duration::from_millis(per_run * (total - done) as u64))
+}
+
+fn print_samples(stats: &[bench::BenchStats]) {
+    for stat in stats.iter() {
+        println!(
+            "# s:{}-b:{}-t:{}",
+            stat.variant(),
+            stat.name(),
+            stat.threads()
+        );
+        println!("# unit: {}", stat.unit().suffix());
+        for sample in stat.samples() {
+            println!("{}", sample);
+        }
+        if let Some(hist) = stat.op_histogram() {
             println!(
-                "# s:{}-b:{}-t:{}",
+                "# h:{}-b:{}-t:{}",
                 stat.variant(),
                 stat.name(),
                 stat.threads()
             );
-            for sample in stat.samples() {
-                println!("{}", sample);
-            }
-
+            print!("{}", hist.export());
         }
-        return;
-    }
 
+    }
+}
 
+fn write_samples(stats: &[bench::BenchStats], output_dir: &str) {
     for stat in stats.iter() {
         let output_filename = format!(
             "s:{}-b:{}-t:{:02}",
@@ -120,8 +377,69 @@ fn main() {
 
         let mut file = File::create(Path::new(&output_dir).join(output_filename)).unwrap();
 
+        // `compare` needs the unit to know if a larger sample is better.
+        write!(&mut file, "# unit: {}\n", stat.unit().suffix()).unwrap();
         for sample in stat.samples() {
             write!(&mut file, "{}\n", sample).unwrap();
         }
+
+        // The latency histogram goes in a file of its own, with `h:` instead of `s:`.
+        if let Some(hist) = stat.op_histogram() {
+            let hist_filename = format!(
+                "h:{}-b:{}-t:{:02}",
+                stat.variant(),
+                stat.name(),
+                stat.threads()
+            );
+            let mut file = File::create(Path::new(&output_dir).join(hist_filename)).unwrap();
+            write!(&mut file, "{}", hist.export()).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn workload_file_then_cli() {
+        let path = ::std::env::temp_dir().join(format!("workload-{}", ::std::process::id()));
+        let file = b"# The file\ninsert=1,search=1\nkeys=64,fill=8,seed=3\n";
+        File::create(&path).and_then(|mut f| f.write_all(file)).unwrap();
+        let path = path.to_str().unwrap();
+
+        let w = read_workload(Some(path), None).unwrap();
+        assert_eq!((w.insert, w.search, w.keys, w.seed), (1, 1, 64, Some(3)));
+        // The pairs on the command line win over the ones in the file.
+        let w = read_workload(Some(path), Some("search=5,seed=random")).unwrap();
+        assert_eq!((w.insert, w.search, w.keys, w.seed), (1, 5, 64, None));
+        let w = read_workload(None, Some("search=5")).unwrap();
+        assert_eq!(w.search, 5);
+        assert_eq!(w.keys, workload::Workload::default().keys);
+
+        assert!(read_workload(Some(path), Some("keys=0")).is_err());
+        fs::remove_file(path).unwrap();
+        assert!(read_workload(Some(path), None).is_err());
+    }
+
+    #[test]
+    fn threads() {
+        assert_eq!(parse_threads("4"), Ok(vec![4]));
+        assert_eq!(parse_threads("1,2,4-8"), Ok(vec![1, 2, 4, 5, 6, 7, 8]));
+        assert_eq!(parse_threads("3-3, 16"), Ok(vec![3, 16]));
+        assert!(parse_threads("8-4").is_err());
+        assert!(parse_threads("0").is_err());
+        assert!(parse_threads("0-4").is_err());
+        assert!(parse_threads("1,").is_err());
+        assert!(parse_threads("x").is_err());
+    }
+
+    #[test]
+    fn repeat() {
+        assert_eq!(parse_repeat("3"), Ok(3));
+        assert!(parse_repeat("0").is_err());
+        assert!(parse_repeat("foo").is_err());
     }
 }
@@ -0,0 +1,117 @@
+/// Memory accounting for the benchmarks.
+///
+/// With the `count-allocations` feature, `Counting` is the global allocator of the runner, and
+/// counts the live bytes, the peak of the live bytes, and the number of allocations of the whole
+/// process. Every allocation then updates atomics shared by all threads, which slows down the
+/// benchmarks, so the feature is off by default. The functions here are read by `ThreadBencher`
+/// after every sample, see `bench::set_default_metrics`, together with the garbage the scheme of
+/// the benchmark has retired but not yet freed.
+///
+/// The counters are plain atomics and not `lazy_static`, since the allocator must not allocate.
+
+#[cfg(feature = "count-allocations")]
+use std::heap::{Alloc, AllocErr, Layout, System};
+#[cfg(feature = "count-allocations")]
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+#[cfg(feature = "count-allocations")]
+use std::sync::atomic::Ordering::Relaxed;
+
+use comere;
+
+#[cfg(feature = "count-allocations")]
+static LIVE: AtomicUsize = ATOMIC_USIZE_INIT;
+#[cfg(feature = "count-allocations")]
+static PEAK: AtomicUsize = ATOMIC_USIZE_INIT;
+#[cfg(feature = "count-allocations")]
+static ALLOCATIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The system allocator, with counting.
+#[cfg(feature = "count-allocations")]
+pub struct Counting;
+
+#[cfg(feature = "count-allocations")]
+unsafe impl<'a> Alloc for &'a Counting {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let size = layout.size();
+        let ret = System.alloc(layout);
+        if ret.is_ok() {
+            let live = LIVE.fetch_add(size, Relaxed) + size;
+            ALLOCATIONS.fetch_add(1, Relaxed);
+            let mut peak = PEAK.load(Relaxed);
+            while live > peak {
+                match PEAK.compare_exchange_weak(peak, live, Relaxed, Relaxed) {
+                    Ok(_) => break,
+                    Err(p) => peak = p,
+                }
+            }
+        }
+        ret
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Start counting the peak and the allocations over from now.
+#[cfg(feature = "count-allocations")]
+pub fn reset() {
+    PEAK.store(LIVE.load(Relaxed), Relaxed);
+    ALLOCATIONS.store(0, Relaxed);
+}
+
+#[cfg(not(feature = "count-allocations"))]
+pub fn reset() {}
+
+/// The number of bytes allocated and not freed.
+#[cfg(feature = "count-allocations")]
+pub fn live_bytes() -> u64 {
+    LIVE.load(Relaxed) as u64
+}
+
+/// The largest number of live bytes since the last call, or `reset`.
+#[cfg(feature = "count-allocations")]
+pub fn peak_bytes() -> u64 {
+    PEAK.swap(LIVE.load(Relaxed), Relaxed) as u64
+}
+
+/// The number of allocations since the last call, or `reset`.
+#[cfg(feature = "count-allocations")]
+pub fn allocations() -> u64 {
+    ALLOCATIONS.swap(0, Relaxed) as u64
+}
+
+pub fn ebr_pending_garbage() -> u64 {
+    comere::ebr::pending_garbage() as u64
+}
+
+pub fn hp_pending_garbage() -> u64 {
+    comere::hp::pending_garbage() as u64
+}
+
+#[cfg(feature = "count-allocations")]
+fn allocator_metrics() -> Vec<(String, fn() -> u64)> {
+    vec![
+        ("live_bytes".to_string(), live_bytes),
+        ("peak_bytes".to_string(), peak_bytes),
+        ("allocations".to_string(), allocations),
+    ]
+}
+
+#[cfg(not(feature = "count-allocations"))]
+fn allocator_metrics() -> Vec<(String, fn() -> u64)> {
+    vec![]
+}
+
+/// The metrics to read after every sample of a benchmark of `scheme`. The allocator metrics are
+/// only there with the `count-allocations` feature.
+pub fn metrics(scheme: &str) -> Vec<(String, fn() -> u64)> {
+    let mut metrics = allocator_metrics();
+    match scheme {
+        "ebr" => metrics.push(("pending_garbage".to_string(), ebr_pending_garbage)),
+        "hp" => metrics.push(("pending_garbage".to_string(), hp_pending_garbage)),
+        _ => {}
+    }
+    metrics
+}
@@ -0,0 +1,85 @@
+/// Find out what machine and build we are running on, for the metadata of the results.
+///
+/// Everything here is best effort: if we cannot find something out, we use `"unknown"` rather
+/// than failing the run.
+
+use std::fs::File;
+use std::io::Read;
+use std::process::Command;
+
+use bench::{Metadata, Placement};
+use workload::Workload;
+use time;
+
+/// The features of `benchmark-runner` that change the code being benchmarked, or the allocator it
+/// runs with.
+const FEATURES: &'static [(&'static str, bool)] = &[
+    ("hp-wait", cfg!(feature = "hp-wait")),
+    ("ebr-free-list", cfg!(feature = "ebr-free-list")),
+    ("hp-free-list", cfg!(feature = "hp-free-list")),
+    ("nothing-free-list", cfg!(feature = "nothing-free-list")),
+    ("ebr-pool", cfg!(feature = "ebr-pool")),
+    ("hp-pool", cfg!(feature = "hp-pool")),
+    ("nothing-pool", cfg!(feature = "nothing-pool")),
+    ("count-allocations", cfg!(feature = "count-allocations")),
+];
+
+fn read_file(path: &str) -> Option<String> {
+    let mut s = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut s)).ok().map(|_| s)
+}
+
+fn run(cmd: &str, args: &[&str]) -> Option<String> {
+    Command::new(cmd)
+        .args(args)
+        .output()
+        .ok()
+        .and_then(|o| if o.status.success() {
+            String::from_utf8(o.stdout).ok()
+        } else {
+            None
+        })
+        .map(|s| s.trim().to_string())
+}
+
+/// The CPU model and the number of logical cores, from `/proc/cpuinfo`.
+fn cpu() -> (String, usize) {
+    let cpuinfo = read_file("/proc/cpuinfo").unwrap_or_default();
+    let model = cpuinfo
+        .lines()
+        .find(|l| l.starts_with("model name"))
+        .and_then(|l| l.splitn(2, ':').nth(1))
+        .map(|m| m.trim().to_string())
+        .unwrap_or("unknown".to_string());
+    let cores = cpuinfo.lines().filter(|l| l.starts_with("processor")).count();
+    (model, cores)
+}
+
+/// The names of the enabled features.
+pub fn features() -> Vec<String> {
+    FEATURES
+        .iter()
+        .filter(|&&(_, enabled)| enabled)
+        .map(|&(name, _)| name.to_string())
+        .collect()
+}
+
+pub fn collect(threads: &[usize], placement: &Placement, workload: &Workload) -> Metadata {
+    let (cpu_model, cores) = cpu();
+    let kernel = read_file("/proc/sys/kernel/osrelease")
+        .map(|s| s.trim().to_string())
+        .or_else(|| run("uname", &["-r"]))
+        .unwrap_or("unknown".to_string());
+    let git_commit = run("git", &["rev-parse", "HEAD"]).unwrap_or("unknown".to_string());
+    Metadata {
+        cpu_model: cpu_model,
+        cores: cores,
+        kernel: kernel,
+        git_commit: git_commit,
+        features: features(),
+        placement: placement.to_string(),
+        workload: workload.to_string(),
+        threads: threads.to_vec(),
+        timestamp: time::now_utc().rfc3339().to_string(),
+    }
+}
@@ -0,0 +1,466 @@
+/// The workload of the `list_real` benchmarks.
+///
+/// A workload is the mix of operations, the range of the keys, how the keys are distributed,
+/// how many keys are in the list when a sample starts, and the seed of the random numbers. It is
+/// written as `key=value` pairs, separated by commas or newlines, like
+///
+/// ```text
+/// insert=40,search=20,remove=20,pop=20
+/// keys=1024,dist=zipf:0.99,fill=512,seed=42
+/// ```
+///
+/// where the operation mix is in weights, not percent, and `dist` is one of `uniform`,
+/// `zipf:<exponent>`, `hotspot:<hot fraction of keys>:<fraction of operations on hot keys>` or
+/// `sequential`. Pairs that are not given are as in `Workload::default`, and `#` starts a
+/// comment, so that a workload can be kept in a file.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::SeqCst;
+
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+
+use NUM_ELEMENTS_SMALLER;
+
+#[derive(Debug, PartialEq)]
+pub enum Operation {
+    Insert(u32),
+    Search(u32),
+    Remove(u32),
+    PopFront,
+}
+
+/// How the keys of the operations are chosen in `0..keys`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    Uniform,
+    /// Key `k` is chosen with a probability proportional to `1 / (k + 1)^s`, so the small keys,
+    /// which are at the front of the list, are the most popular.
+    Zipf(f64),
+    /// The first `hot` fraction of the keys get the `probability` fraction of the operations.
+    Hotspot { hot: f64, probability: f64 },
+    /// Each thread goes through the keys in order, from a random start.
+    Sequential,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    /// The weights of insert, search, remove, and pop front.
+    pub insert: u32,
+    pub search: u32,
+    pub remove: u32,
+    pub pop: u32,
+    pub keys: u32,
+    pub distribution: Distribution,
+    /// The number of keys inserted before every sample.
+    pub fill: u32,
+    /// If `None`, we use a new random seed for every run.
+    pub seed: Option<u64>,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            insert: 4,
+            search: 2,
+            remove: 2,
+            pop: 2,
+            keys: NUM_ELEMENTS_SMALLER as u32,
+            distribution: Distribution::Uniform,
+            fill: NUM_ELEMENTS_SMALLER as u32,
+            seed: None,
+        }
+    }
+}
+
+lazy_static! {
+    static ref WORKLOAD: Mutex<Workload> = Mutex::new(Workload::default());
+}
+
+/// Set the workload of the benchmarks that use `current`.
+pub fn set(workload: Workload) {
+    *WORKLOAD.lock().unwrap() = workload;
+}
+
+pub fn current() -> Workload {
+    WORKLOAD.lock().unwrap().clone()
+}
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid value for '{}'", value, key))
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Distribution, Self::Err> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        let fraction = |v: &str| {
+            parse_number::<f64>("dist", v).and_then(|f| if 0.0 <= f && f <= 1.0 {
+                Ok(f)
+            } else {
+                Err(format!("'{}' is not a fraction between 0 and 1", v))
+            })
+        };
+        match (parts[0], parts.len()) {
+            ("uniform", 1) => Ok(Distribution::Uniform),
+            ("sequential", 1) => Ok(Distribution::Sequential),
+            ("zipf", 2) => {
+                parse_number::<f64>("dist", parts[1]).and_then(|s| if s > 0.0 {
+                    Ok(Distribution::Zipf(s))
+                } else {
+                    Err("the exponent of zipf must be positive".to_string())
+                })
+            }
+            ("hotspot", 3) => {
+                Ok(Distribution::Hotspot {
+                    hot: fraction(parts[1])?,
+                    probability: fraction(parts[2])?,
+                })
+            }
+            _ => Err(format!(
+                "'{}' is not a distribution; expected uniform, zipf:<exponent>, \
+                 hotspot:<hot>:<probability> or sequential",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Distribution::Uniform => write!(f, "uniform"),
+            Distribution::Zipf(s) => write!(f, "zipf:{}", s),
+            Distribution::Hotspot { hot, probability } => {
+                write!(f, "hotspot:{}:{}", hot, probability)
+            }
+            Distribution::Sequential => write!(f, "sequential"),
+        }
+    }
+}
+
+impl FromStr for Workload {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Workload, Self::Err> {
+        let mut w = Workload::default();
+        let pairs = s.lines()
+            .map(|l| l.splitn(2, '#').next().unwrap())
+            .flat_map(|l| l.split(','))
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty());
+        for pair in pairs {
+            let mut kv = pair.splitn(2, '=');
+            let key = kv.next().unwrap().trim();
+            let value = match kv.next() {
+                Some(v) => v.trim(),
+                None => return Err(format!("'{}' is not a key=value pair", pair)),
+            };
+            match key {
+                "insert" => w.insert = parse_number(key, value)?,
+                "search" => w.search = parse_number(key, value)?,
+                "remove" => w.remove = parse_number(key, value)?,
+                "pop" => w.pop = parse_number(key, value)?,
+                "keys" => w.keys = parse_number(key, value)?,
+                "dist" => w.distribution = value.parse()?,
+                "fill" => w.fill = parse_number(key, value)?,
+                "seed" if value == "random" => w.seed = None,
+                "seed" => w.seed = Some(parse_number(key, value)?),
+                _ => return Err(format!("'{}' is not a workload setting", key)),
+            }
+        }
+        let total = w.insert
+            .checked_add(w.search)
+            .and_then(|t| t.checked_add(w.remove))
+            .and_then(|t| t.checked_add(w.pop));
+        match total {
+            Some(0) => return Err("the operation mix is empty".to_string()),
+            None => {
+                return Err(format!(
+                    "the weights of the operation mix add up to more than {}",
+                    u32::max_value()
+                ))
+            }
+            Some(_) => {}
+        }
+        if w.keys == 0 {
+            return Err("the key range is empty".to_string());
+        }
+        if w.fill > w.keys {
+            return Err(format!("cannot fill {} keys out of {}", w.fill, w.keys));
+        }
+        Ok(w)
+    }
+}
+
+/// The same format as `FromStr`, with every setting, so that the workload of a run can be
+/// reproduced from its metadata.
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "insert={},search={},remove={},pop={},keys={},dist={},fill={},seed=",
+            self.insert,
+            self.search,
+            self.remove,
+            self.pop,
+            self.keys,
+            self.distribution,
+            self.fill
+        )?;
+        match self.seed {
+            Some(seed) => write!(f, "{}", seed),
+            None => write!(f, "random"),
+        }
+    }
+}
+
+/// Hands out generators with their own random streams, so that the threads of a benchmark do not
+/// share a random number generator, and get the same streams in every run with the same seed.
+pub struct Generators {
+    workload: Workload,
+    seed: u64,
+    next_stream: AtomicUsize,
+}
+
+/// The `splitmix64` step, which we use to make seeds for the streams.
+fn splitmix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Workload {
+    pub fn generators(self) -> Generators {
+        let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        Generators {
+            workload: self,
+            seed: seed,
+            next_stream: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Generators {
+    fn rng(&self) -> XorShiftRng {
+        let stream = self.next_stream.fetch_add(1, SeqCst) as u64;
+        let a = splitmix(self.seed ^ splitmix(stream));
+        let b = splitmix(a);
+        // `XorShiftRng` must not be seeded with only zeros.
+        XorShiftRng::from_seed([a as u32 | 1, (a >> 32) as u32, b as u32, (b >> 32) as u32])
+    }
+
+    /// A generator of operations, with a new random stream.
+    pub fn generator(&self) -> Generator {
+        let mut rng = self.rng();
+        let next = rng.gen_range(0, self.workload.keys);
+        Generator {
+            workload: self.workload.clone(),
+            rng: rng,
+            next: next,
+        }
+    }
+
+    /// The keys to insert before a sample, in random order.
+    pub fn initial_keys(&self) -> Vec<u32> {
+        let mut rng = self.rng();
+        let mut keys = (0..self.workload.keys).collect::<Vec<u32>>();
+        rng.shuffle(&mut keys);
+        keys.truncate(self.workload.fill as usize);
+        keys
+    }
+}
+
+pub struct Generator {
+    workload: Workload,
+    rng: XorShiftRng,
+    /// The next key of the sequential distribution.
+    next: u32,
+}
+
+impl Generator {
+    fn key(&mut self) -> u32 {
+        let keys = self.workload.keys;
+        match self.workload.distribution {
+            Distribution::Uniform => self.rng.gen_range(0, keys),
+            Distribution::Zipf(s) => {
+                // We sample the continuous version of the distribution by inverting its CDF,
+                // which is close enough, and does not need a table of all keys.
+                let u = self.rng.gen::<f64>();
+                let n = keys as f64 + 1.0;
+                let x = if (s - 1.0).abs() < 1e-9 {
+                    n.powf(u)
+                } else {
+                    ((n.powf(1.0 - s) - 1.0) * u + 1.0).powf(1.0 / (1.0 - s))
+                };
+                // `x` is in `1..n`, but rounding may put it just below 1.
+                ::std::cmp::min(x.max(1.0) as u32, keys) - 1
+            }
+            Distribution::Hotspot { hot, probability } => {
+                let hot_keys = ::std::cmp::max((keys as f64 * hot) as u32, 1);
+                if hot_keys >= keys || self.rng.gen::<f64>() < probability {
+                    self.rng.gen_range(0, hot_keys)
+                } else {
+                    self.rng.gen_range(hot_keys, keys)
+                }
+            }
+            Distribution::Sequential => {
+                let k = self.next;
+                self.next = (self.next + 1) % keys;
+                k
+            }
+        }
+    }
+
+    pub fn next_op(&mut self) -> Operation {
+        // The fields are public, so the weights may not have been checked by `FromStr`, and we
+        // add them as `u64`, where they cannot overflow.
+        let w = &self.workload;
+        let (insert, search, remove, pop) =
+            (w.insert as u64, w.search as u64, w.remove as u64, w.pop as u64);
+        let r = self.rng.gen_range(0, insert + search + remove + pop);
+        if r < insert {
+            Operation::Insert(self.key())
+        } else if r < insert + search {
+            Operation::Search(self.key())
+        } else if r < insert + search + remove {
+            Operation::Remove(self.key())
+        } else {
+            Operation::PopFront
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DISTRIBUTIONS: [&'static str; 6] = [
+        "uniform",
+        "zipf:0.99",
+        "zipf:1",
+        "zipf:2.5",
+        "hotspot:0.1:0.9",
+        "sequential",
+    ];
+
+    #[test]
+    fn parse_workload() {
+        let w: Workload = "insert=1, search=2 # a comment\nremove=3,pop=0,keys=100,dist=zipf:0.5,\
+                           fill=10,seed=7"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            w,
+            Workload {
+                insert: 1,
+                search: 2,
+                remove: 3,
+                pop: 0,
+                keys: 100,
+                distribution: Distribution::Zipf(0.5),
+                fill: 10,
+                seed: Some(7),
+            }
+        );
+        assert_eq!("".parse::<Workload>().unwrap(), Workload::default());
+        assert_eq!("seed=random".parse::<Workload>().unwrap().seed, None);
+    }
+
+    #[test]
+    fn parse_workload_errors() {
+        assert!("insert".parse::<Workload>().is_err());
+        assert!("insert=x".parse::<Workload>().is_err());
+        assert!("inserts=1".parse::<Workload>().is_err());
+        assert!("insert=0,search=0,remove=0,pop=0".parse::<Workload>().is_err());
+        assert!("insert=4294967295,search=1".parse::<Workload>().is_err());
+        assert!("insert=4294967295,search=0,remove=0,pop=0".parse::<Workload>().is_ok());
+        assert!("keys=0".parse::<Workload>().is_err());
+        assert!("keys=10,fill=11".parse::<Workload>().is_err());
+    }
+
+    #[test]
+    fn parse_distribution() {
+        assert_eq!("uniform".parse(), Ok(Distribution::Uniform));
+        assert_eq!("zipf:0.99".parse(), Ok(Distribution::Zipf(0.99)));
+        assert_eq!(
+            "hotspot:0.2:0.8".parse(),
+            Ok(Distribution::Hotspot {
+                hot: 0.2,
+                probability: 0.8,
+            })
+        );
+        assert_eq!("sequential".parse(), Ok(Distribution::Sequential));
+        for bad in &["", "zipf", "zipf:0", "zipf:x", "hotspot:0.1", "hotspot:2:0.5", "uniform:1"] {
+            assert!(bad.parse::<Distribution>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for dist in DISTRIBUTIONS.iter() {
+            let d: Distribution = dist.parse().unwrap();
+            assert_eq!(d.to_string().parse(), Ok(d));
+            let w = Workload {
+                distribution: d,
+                seed: Some(42),
+                ..Workload::default()
+            };
+            assert_eq!(w.to_string().parse(), Ok(w.clone()));
+        }
+        let w = Workload::default();
+        assert_eq!(w.to_string().parse(), Ok(w));
+    }
+
+    #[test]
+    fn same_seed_same_streams() {
+        for dist in DISTRIBUTIONS.iter() {
+            let w: Workload = format!("dist={},seed=1234", dist).parse().unwrap();
+            let (a, b) = (w.clone().generators(), w.generators());
+            assert_eq!(a.initial_keys(), b.initial_keys());
+            for _ in 0..4 {
+                let (mut ga, mut gb) = (a.generator(), b.generator());
+                for _ in 0..1000 {
+                    assert_eq!(ga.next_op(), gb.next_op());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keys_in_range() {
+        for dist in DISTRIBUTIONS.iter() {
+            for &keys in &[1, 2, 7, 1024] {
+                let w: Workload = format!("keys={},fill=0,dist={},seed=5", keys, dist)
+                    .parse()
+                    .unwrap();
+                let mut g = w.generators().generator();
+                for _ in 0..10_000 {
+                    let k = g.key();
+                    assert!(k < keys, "{} gave key {} of {}", dist, k, keys);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_weights() {
+        let max = u32::max_value();
+        let w = Workload {
+            insert: max,
+            search: max,
+            remove: max,
+            pop: max,
+            seed: Some(1),
+            ..Workload::default()
+        };
+        let mut g = w.generators().generator();
+        for _ in 0..1000 {
+            g.next_op();
+        }
+    }
+}
@@ -0,0 +1,182 @@
+/// Allocators for the memory that `Owned` points to.
+///
+/// Each scheme chooses its allocator through `Scheme::Alloc` on its guard type, so that node
+/// memory can come from somewhere else than the global allocator, for instance a per-thread free
+/// list. The scheme decides when memory is safe to free, and the allocator decides what freeing
+/// means.
+///
+/// Since `Owned::from_box` lets the user hand us memory from `Box`, every allocator must accept
+/// memory from `Box` in `free`. In practice this means that allocators get their memory from
+/// `Global` in the end.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+
+/// An allocator for single objects of any type.
+pub trait Alloc {
+    /// Allocates memory for one `T`. The memory is not initialized.
+    fn alloc<T>() -> *mut T;
+
+    /// Frees memory for one `T`, without dropping the `T`. `ptr` must come from `alloc` of the
+    /// same allocator, or from `Box`.
+    unsafe fn free<T>(ptr: *mut T);
+}
+
+/// Implemented by the guard types of the schemes, in order to choose how `Owned` allocates and
+/// frees memory.
+pub trait Scheme {
+    type Alloc: Alloc;
+}
+
+/// The global allocator. Memory from here is the same as memory from `Box`.
+#[derive(Debug)]
+pub struct Global;
+
+impl Alloc for Global {
+    fn alloc<T>() -> *mut T {
+        // `Vec` gives us memory for `T` without initializing it, and with the same layout as
+        // `Box<T>`. This also handles zero sized types.
+        let mut v = Vec::<T>::with_capacity(1);
+        let ptr = v.as_mut_ptr();
+        mem::forget(v);
+        ptr
+    }
+
+    unsafe fn free<T>(ptr: *mut T) {
+        drop(Vec::from_raw_parts(ptr, 0, 1));
+    }
+}
+
+/// The maximum number of blocks of one size we keep in a thread's free list. Blocks freed when
+/// the list is full go back to `Global`.
+const FREE_LIST_CAPACITY: usize = 1024;
+
+/// A block in a free list, together with the function that frees it to `Global`, which we need
+/// since we do not know the type of the block when the thread exits.
+struct Block {
+    ptr: usize,
+    free: unsafe fn(usize),
+}
+
+unsafe fn free_global<T>(ptr: usize) {
+    Global::free(ptr as *mut T);
+}
+
+/// The free lists of one thread, keyed on the size and alignment of the blocks.
+struct FreeLists {
+    lists: HashMap<(usize, usize), Vec<Block>>,
+}
+
+impl Drop for FreeLists {
+    fn drop(&mut self) {
+        for (_, list) in self.lists.drain() {
+            for block in list {
+                unsafe { (block.free)(block.ptr) };
+            }
+        }
+    }
+}
+
+thread_local! {
+    static FREE_LISTS: RefCell<FreeLists> = RefCell::new(FreeLists { lists: HashMap::new() });
+}
+
+/// A per-thread free list on top of `Global`. Freed memory is kept by the freeing thread, and
+/// handed out again to the next allocation of the same size and alignment on that thread, so
+/// that we avoid calls to the global allocator when memory is recycled.
+///
+/// A thread that allocates more than it frees still goes to `Global`, and the blocks in a free
+/// list are freed to `Global` when the thread exits.
+#[derive(Debug)]
+pub struct FreeList;
+
+impl FreeList {
+    fn key<T>() -> (usize, usize) {
+        (mem::size_of::<T>(), mem::align_of::<T>())
+    }
+
+    /// Returns the number of blocks for a `T` in the free list of the current thread.
+    pub fn len<T>() -> usize {
+        FREE_LISTS
+            .try_with(|f| {
+                f.borrow().lists.get(&Self::key::<T>()).map(|l| l.len()).unwrap_or(0)
+            })
+            .unwrap_or(0)
+    }
+}
+
+impl Alloc for FreeList {
+    fn alloc<T>() -> *mut T {
+        if mem::size_of::<T>() == 0 {
+            return Global::alloc();
+        }
+        // The free list may already be destroyed if we are called from another thread local
+        // destructor, in which case we use `Global` directly.
+        let block = FREE_LISTS
+            .try_with(|f| {
+                f.borrow_mut()
+                    .lists
+                    .get_mut(&Self::key::<T>())
+                    .and_then(|l| l.pop())
+            })
+            .ok()
+            .and_then(|b| b);
+        match block {
+            Some(block) => block.ptr as *mut T,
+            None => Global::alloc(),
+        }
+    }
+
+    unsafe fn free<T>(ptr: *mut T) {
+        if mem::size_of::<T>() == 0 {
+            return Global::free(ptr);
+        }
+        let block = Block {
+            ptr: ptr as usize,
+            free: free_global::<T>,
+        };
+        let kept = FREE_LISTS
+            .try_with(|f| {
+                let mut f = f.borrow_mut();
+                let list = f.lists.entry(Self::key::<T>()).or_insert_with(Vec::new);
+                if list.len() < FREE_LIST_CAPACITY {
+                    list.push(block);
+                    true
+                } else {
+                    false
+                }
+            })
+            .unwrap_or(false);
+        if !kept {
+            Global::free(ptr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn free_list_reuse() {
+        let a = FreeList::alloc::<[u64; 3]>();
+        let len = FreeList::len::<[u64; 3]>();
+        unsafe { FreeList::free(a) };
+        assert_eq!(FreeList::len::<[u64; 3]>(), len + 1);
+        // The next allocation of the same size gets the same block back.
+        let b = FreeList::alloc::<[u64; 3]>();
+        assert_eq!(a, b);
+        assert_eq!(FreeList::len::<[u64; 3]>(), len);
+        unsafe { FreeList::free(b) };
+    }
+
+    #[test]
+    fn free_list_from_box() {
+        let b = Box::into_raw(Box::new(1234usize));
+        unsafe { FreeList::free(b) };
+        let p = FreeList::alloc::<usize>();
+        assert_eq!(p, b);
+        unsafe { Global::free(p) };
+    }
+}
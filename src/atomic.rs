@@ -0,0 +1,1750 @@
+/// Tagged atomic pointers, shared by all the schemes.
+///
+/// `Atomic`, `Owned` and `Ptr` are parameterized by a guard type `G`, which is a marker type
+/// for the scheme they belong to. Methods that do not depend on the scheme are implemented here
+/// for all `G`. Each scheme implements the methods that load a pointer on its own guard type,
+/// since `ebr` passes a `Pin` to them, while `hp` and `nothing` do not. Those schemes
+/// implement `Unguarded`, and get the methods from here. The schemes have type aliases for
+/// their own guard type, so that `ebr::atomic::Atomic<T>` is `Atomic<T, ebr::atomic::Ebr>`.
+
+// NOTE:
+// This code was initially yanked from
+//   http://www.github.com/jeehoonkang/crossbeam-epoch
+// from the branch `handle`, 02.10.17.
+use std::borrow::{Borrow, BorrowMut};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::AtomicUsize;
+#[cfg(target_pointer_width = "32")]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use alloc::{Alloc, Scheme};
+
+/// Given ordering for the success case in a compare-exchange operation, returns the strongest
+/// appropriate ordering for the failure case.
+#[inline]
+fn strongest_failure_ordering(ord: Ordering) -> Ordering {
+    use self::Ordering::*;
+    match ord {
+        Relaxed | Release => Relaxed,
+        Acquire | AcqRel => Acquire,
+        _ => SeqCst,
+    }
+}
+
+/// Memory orderings for compare-and-set operations.
+///
+/// A compare-and-set operation can have different memory orderings depending on whether it
+/// succeeds or fails. This trait generalizes different ways of specifying memory orderings.
+///
+/// The two ways of specifying orderings for compare-and-set are:
+///
+/// 1. Just one `Ordering` for the success case. In case of failure, the strongest appropriate
+///    ordering is chosen.
+/// 2. A pair of `Ordering`s. The first one is for the success case, while the second one is
+///    for the failure case.
+pub trait CompareAndSetOrdering {
+    /// The ordering of the operation when it succeeds.
+    fn success(&self) -> Ordering;
+
+    /// The ordering of the operation when it fails.
+    ///
+    /// The failure ordering can't be `Release` or `AcqRel` and must be equivalent or weaker than
+    /// the success ordering.
+    fn failure(&self) -> Ordering;
+}
+
+impl CompareAndSetOrdering for Ordering {
+    #[inline]
+    fn success(&self) -> Ordering {
+        *self
+    }
+
+    #[inline]
+    fn failure(&self) -> Ordering {
+        strongest_failure_ordering(*self)
+    }
+}
+
+impl CompareAndSetOrdering for (Ordering, Ordering) {
+    #[inline]
+    fn success(&self) -> Ordering {
+        self.0
+    }
+
+    #[inline]
+    fn failure(&self) -> Ordering {
+        self.1
+    }
+}
+
+/// Panics if the pointer is not properly unaligned.
+#[inline]
+fn ensure_aligned<T>(raw: *const T) {
+    if raw as usize & low_bits::<T>() != 0 {
+        panic!("unaligned pointer");
+    }
+}
+
+/// Returns a bitmask containing the unused least significant bits of an aligned pointer to `T`.
+#[inline]
+fn low_bits<T>() -> usize {
+    (1 << mem::align_of::<T>().trailing_zeros()) - 1
+}
+
+/// Given a tagged pointer `data`, returns the same pointer, but tagged with `tag`.  `tag` is
+/// truncated to be fit into the unused bits of the pointer to `T`.
+#[inline]
+fn data_with_tag<T>(data: usize, tag: usize) -> usize {
+    (data & !low_bits::<T>()) | (tag & low_bits::<T>())
+}
+
+/// Implemented by the guard types of schemes where loading a pointer does not take a guard
+/// argument, like `hp` and `nothing`. Loads return a `Ptr` with any lifetime, and it is up to the
+/// scheme to make sure the pointee is not freed while the `Ptr` is used.
+pub trait Unguarded: Scheme {}
+
+/// A pointer that can be stored in an `Atomic`, which is either an `Owned` or a `Ptr`.
+pub trait Pointer<T, G> {
+    /// Returns the tagged pointer, and gives up ownership of the pointee if we had it.
+    fn into_data(self) -> usize;
+
+    /// Makes a pointer out of a tagged pointer returned by `into_data`.
+    ///
+    /// This is unsafe, since for an `Owned` we must not make two out of the same pointer.
+    unsafe fn from_data(data: usize) -> Self;
+}
+
+impl<T, G: Scheme> Pointer<T, G> for Owned<T, G> {
+    fn into_data(self) -> usize {
+        let data = self.data;
+        mem::forget(self);
+        data
+    }
+
+    unsafe fn from_data(data: usize) -> Self {
+        Owned::from_data(data)
+    }
+}
+
+impl<'scope, T, G: Scheme> Pointer<T, G> for Ptr<'scope, T, G> {
+    fn into_data(self) -> usize {
+        self.data
+    }
+
+    unsafe fn from_data(data: usize) -> Self {
+        Ptr::from_data(data)
+    }
+}
+
+/// The error returned when a compare-exchange fails. It has both the actual value of the
+/// `Atomic`, and the pointer we tried to store, so that a retry loop does not have to load the
+/// `Atomic` again, or allocate a new node.
+#[derive(Debug)]
+pub struct CompareExchangeError<'scope, T: 'scope, G, P: Pointer<T, G>> {
+    /// The value of the `Atomic` when the compare-exchange failed.
+    pub current: Ptr<'scope, T, G>,
+    /// The pointer we tried to store.
+    pub new: P,
+}
+
+/// An atomic pointer that can be safely shared between threads.
+///
+/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
+/// least significant bits of the address.  More precisely, a tag should be less than `(1 <<
+/// mem::align_of::<T>().trailing_zeros())`.
+///
+/// In `ebr`, any method that loads the pointer must be passed a `Pin`.
+#[derive(Debug)]
+pub struct Atomic<T, G> {
+    pub data: AtomicUsize,
+    _marker: PhantomData<(*mut T, G)>,
+}
+
+unsafe impl<T: Send + Sync, G> Send for Atomic<T, G> {}
+unsafe impl<T: Send + Sync, G> Sync for Atomic<T, G> {}
+
+impl<T, G: Scheme> Atomic<T, G> {
+    /// Returns a new atomic pointer pointing to the tagged pointer `data`.
+    fn from_data(data: usize) -> Self {
+        Atomic {
+            data: AtomicUsize::new(data),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a new null atomic pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Atomic;
+    ///
+    /// let a = Atomic::<i32>::null();
+    /// ```
+    #[cfg(not(feature = "nightly"))]
+    pub fn null() -> Self {
+        Atomic {
+            data: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a new null atomic pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Atomic;
+    ///
+    /// let a = Atomic::<i32>::null();
+    /// ```
+    #[cfg(feature = "nightly")]
+    pub const fn null() -> Self {
+        Atomic {
+            data: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Allocates `value` on the heap and returns a new atomic pointer pointing to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Atomic;
+    ///
+    /// let a = Atomic::new(1234);
+    /// ```
+    pub fn new(value: T) -> Self {
+        Self::from_owned(Owned::new(value))
+    }
+
+    /// Returns a new atomic pointer pointing to `owned`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{Atomic, Owned};
+    ///
+    /// let a = Atomic::from_owned(Owned::new(1234));
+    /// ```
+    pub fn from_owned(owned: Owned<T, G>) -> Self {
+        let data = owned.data;
+        mem::forget(owned);
+        Self::from_data(data)
+    }
+
+    /// Returns a new atomic pointer pointing to `ptr`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{Atomic, Ptr};
+    ///
+    /// let a = Atomic::from_ptr(Ptr::<i32>::null());
+    /// ```
+    pub fn from_ptr(ptr: Ptr<T, G>) -> Self {
+        Self::from_data(ptr.data)
+    }
+
+    /// Stores a `Ptr` into the atomic pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// a.store(Ptr::null(), SeqCst);
+    /// ```
+    pub fn store(&self, new: Ptr<T, G>, ord: Ordering) {
+        self.data.store(new.data, ord);
+    }
+
+    /// Stores an `Owned` into the atomic pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::null();
+    /// a.store_owned(Owned::new(1234), SeqCst);
+    /// ```
+    pub fn store_owned(&self, new: Owned<T, G>, ord: Ordering) {
+        let data = new.data;
+        mem::forget(new);
+        self.data.store(data, ord);
+    }
+
+    /// The implementation of `load`, for all guard types.
+    pub(crate) fn load_raw<'scope>(&self, ord: Ordering) -> Ptr<'scope, T, G> {
+        Ptr::from_data(self.data.load(ord))
+    }
+
+    /// The implementation of `swap`, for all guard types.
+    pub(crate) fn swap_raw<'scope>(&self, new: Ptr<T, G>, ord: Ordering) -> Ptr<'scope, T, G> {
+        Ptr::from_data(self.data.swap(new.data, ord))
+    }
+
+    /// The implementation of `compare_and_set`, for all guard types.
+    pub(crate) fn compare_and_set_raw<'scope, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: Ptr<T, G>,
+        ord: O,
+    ) -> Result<(), Ptr<'scope, T, G>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        match self.data.compare_exchange(
+            current.data,
+            new.data,
+            ord.success(),
+            ord.failure(),
+        ) {
+            Ok(_) => Ok(()),
+            Err(previous) => Err(Ptr::from_data(previous)),
+        }
+    }
+
+    /// The implementation of `compare_and_set_weak`, for all guard types.
+    pub(crate) fn compare_and_set_weak_raw<'scope, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: Ptr<T, G>,
+        ord: O,
+    ) -> Result<(), Ptr<'scope, T, G>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        match self.data.compare_exchange_weak(
+            current.data,
+            new.data,
+            ord.success(),
+            ord.failure(),
+        ) {
+            Ok(_) => Ok(()),
+            Err(previous) => Err(Ptr::from_data(previous)),
+        }
+    }
+
+    /// The implementation of `compare_exchange`, for all guard types.
+    pub(crate) fn compare_exchange_raw<'scope, P, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: P,
+        ord: O,
+    ) -> Result<Ptr<'scope, T, G>, CompareExchangeError<'scope, T, G, P>>
+    where
+        P: Pointer<T, G>,
+        O: CompareAndSetOrdering,
+    {
+        let new = new.into_data();
+        match self.data.compare_exchange(
+            current.data,
+            new,
+            ord.success(),
+            ord.failure(),
+        ) {
+            Ok(_) => Ok(Ptr::from_data(new)),
+            Err(previous) => Err(CompareExchangeError {
+                current: Ptr::from_data(previous),
+                new: unsafe { P::from_data(new) },
+            }),
+        }
+    }
+
+    /// The implementation of `compare_exchange_weak`, for all guard types.
+    pub(crate) fn compare_exchange_weak_raw<'scope, P, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: P,
+        ord: O,
+    ) -> Result<Ptr<'scope, T, G>, CompareExchangeError<'scope, T, G, P>>
+    where
+        P: Pointer<T, G>,
+        O: CompareAndSetOrdering,
+    {
+        let new = new.into_data();
+        match self.data.compare_exchange_weak(
+            current.data,
+            new,
+            ord.success(),
+            ord.failure(),
+        ) {
+            Ok(_) => Ok(Ptr::from_data(new)),
+            Err(previous) => Err(CompareExchangeError {
+                current: Ptr::from_data(previous),
+                new: unsafe { P::from_data(new) },
+            }),
+        }
+    }
+
+    /// The implementation of `fetch_and`, for all guard types.
+    pub(crate) fn fetch_and_raw<'scope>(&self, val: usize, ord: Ordering) -> Ptr<'scope, T, G> {
+        Ptr::from_data(self.data.fetch_and(val | !low_bits::<T>(), ord))
+    }
+
+    /// The implementation of `fetch_or`, for all guard types.
+    pub(crate) fn fetch_or_raw<'scope>(&self, val: usize, ord: Ordering) -> Ptr<'scope, T, G> {
+        Ptr::from_data(self.data.fetch_or(val & low_bits::<T>(), ord))
+    }
+
+    /// The implementation of `fetch_xor`, for all guard types.
+    pub(crate) fn fetch_xor_raw<'scope>(&self, val: usize, ord: Ordering) -> Ptr<'scope, T, G> {
+        Ptr::from_data(self.data.fetch_xor(val & low_bits::<T>(), ord))
+    }
+}
+
+impl<T, G: Unguarded> Atomic<T, G> {
+    /// Loads a `Ptr` from the atomic pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::Atomic;
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// let p = a.load(SeqCst);
+    /// ```
+    pub fn load<'scope>(&self, ord: Ordering) -> Ptr<'scope, T, G> {
+        self.load_raw(ord)
+    }
+
+    /// Stores a `Ptr` into the atomic pointer, returning the previous `Ptr`.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Owned, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// let p = a.swap(Ptr::null(), SeqCst);
+    /// ```
+    pub fn swap<'scope>(&self, new: Ptr<T, G>, ord: Ordering) -> Ptr<'scope, T, G> {
+        self.swap_raw(new, ord)
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// The return value is a result indicating whether the new pointer was written. On failure the
+    /// actual current value is returned.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// let mut curr = a.load(SeqCst);
+    /// let res = a.compare_and_set(curr, Ptr::null(), SeqCst);
+    /// ```
+    pub fn compare_and_set<'scope, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: Ptr<T, G>,
+        ord: O,
+    ) -> Result<(), Ptr<'scope, T, G>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        self.compare_and_set_raw(current, new, ord)
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// Unlike [`compare_and_set`], this method is allowed to spuriously fail even when
+    /// comparison succeeds, which can result in more efficient code on some platforms.
+    /// The return value is a result indicating whether the new pointer was written. On failure the
+    /// actual current value is returned.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// let mut curr = a.load(SeqCst);
+    /// loop {
+    ///     match a.compare_and_set(curr, Ptr::null(), SeqCst) {
+    ///         Ok(()) => break,
+    ///         Err(c) => curr = c,
+    ///     }
+    /// }
+    /// ```
+    pub fn compare_and_set_weak<'scope, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: Ptr<T, G>,
+        ord: O,
+    ) -> Result<(), Ptr<'scope, T, G>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        self.compare_and_set_weak_raw(current, new, ord)
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// The return value is a result indicating whether the new pointer was written. On success the
+    /// pointer that was written is returned. On failure a [`CompareExchangeError`] with `new` and
+    /// the actual current value is returned.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    /// [`CompareExchangeError`]: struct.CompareExchangeError.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// let mut curr = a.load(SeqCst);
+    /// let res = a.compare_and_set_owned(curr, Owned::new(5678), SeqCst);
+    /// ```
+    pub fn compare_and_set_owned<'scope, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: Owned<T, G>,
+        ord: O,
+    ) -> Result<Ptr<'scope, T, G>, CompareExchangeError<'scope, T, G, Owned<T, G>>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        self.compare_exchange_raw(current, new, ord)
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// Unlike [`compare_and_set_owned`], this method is allowed to spuriously fail even when
+    /// comparison succeeds, which can result in more efficient code on some platforms.
+    /// The return value is a result indicating whether the new pointer was written. On success the
+    /// pointer that was written is returned. On failure a [`CompareExchangeError`] with `new` and
+    /// the actual current value is returned.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`compare_and_set_owned`]: struct.Atomic.html#method.compare_and_set_owned
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    /// [`CompareExchangeError`]: struct.CompareExchangeError.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// let mut new = Owned::new(5678);
+    /// let mut ptr = a.load(SeqCst);
+    /// loop {
+    ///     match a.compare_and_set_weak_owned(ptr, new, SeqCst) {
+    ///         Ok(p) => {
+    ///             ptr = p;
+    ///             break;
+    ///         }
+    ///         Err(e) => {
+    ///             ptr = e.current;
+    ///             new = e.new;
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn compare_and_set_weak_owned<'scope, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: Owned<T, G>,
+        ord: O,
+    ) -> Result<Ptr<'scope, T, G>, CompareExchangeError<'scope, T, G, Owned<T, G>>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        self.compare_exchange_weak_raw(current, new, ord)
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// `new` may be either an `Owned` or a `Ptr`. On success the pointer that was written is
+    /// returned. On failure a [`CompareExchangeError`] is returned, which has both the actual
+    /// current value and `new`, so that a retry loop needs neither to load the pointer again nor
+    /// to allocate a new node.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    /// [`CompareExchangeError`]: struct.CompareExchangeError.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Owned, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// let curr = a.load(SeqCst);
+    /// let new = a.compare_exchange(curr, Owned::new(5678), SeqCst).unwrap();
+    /// // `curr` is stale now, so this fails, and gives us back both pointers.
+    /// let err = a.compare_exchange(curr, Ptr::null(), SeqCst).unwrap_err();
+    /// assert_eq!(err.current, new);
+    /// assert!(err.new.is_null());
+    /// ```
+    pub fn compare_exchange<'scope, P, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: P,
+        ord: O,
+    ) -> Result<Ptr<'scope, T, G>, CompareExchangeError<'scope, T, G, P>>
+    where
+        P: Pointer<T, G>,
+        O: CompareAndSetOrdering,
+    {
+        self.compare_exchange_raw(current, new, ord)
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// Unlike [`compare_exchange`], this method is allowed to spuriously fail even when
+    /// comparison succeeds, which can result in more efficient code on some platforms.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`compare_exchange`]: struct.Atomic.html#method.compare_exchange
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// let mut new = Owned::new(5678);
+    /// let mut curr = a.load(SeqCst);
+    /// loop {
+    ///     match a.compare_exchange_weak(curr, new, SeqCst) {
+    ///         Ok(_) => break,
+    ///         Err(e) => {
+    ///             curr = e.current;
+    ///             new = e.new;
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn compare_exchange_weak<'scope, P, O>(
+        &self,
+        current: Ptr<T, G>,
+        new: P,
+        ord: O,
+    ) -> Result<Ptr<'scope, T, G>, CompareExchangeError<'scope, T, G, P>>
+    where
+        P: Pointer<T, G>,
+        O: CompareAndSetOrdering,
+    {
+        self.compare_exchange_weak_raw(current, new, ord)
+    }
+
+    /// Bitwise "and" with the current tag.
+    ///
+    /// Performs a bitwise "and" operation on the current tag and the argument `val`, and sets the
+    /// new tag to the result. Returns the previous pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(3));
+    /// assert_eq!(a.fetch_and(2, SeqCst).tag(), 3);
+    /// assert_eq!(a.load(SeqCst).tag(), 2);
+    /// ```
+    pub fn fetch_and<'scope>(&self, val: usize, ord: Ordering) -> Ptr<'scope, T, G> {
+        self.fetch_and_raw(val, ord)
+    }
+
+    /// Bitwise "or" with the current tag.
+    ///
+    /// Performs a bitwise "or" operation on the current tag and the argument `val`, and sets the
+    /// new tag to the result. Returns the previous pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(1));
+    /// assert_eq!(a.fetch_or(2, SeqCst).tag(), 1);
+    /// assert_eq!(a.load(SeqCst).tag(), 3);
+    /// ```
+    pub fn fetch_or<'scope>(&self, val: usize, ord: Ordering) -> Ptr<'scope, T, G> {
+        self.fetch_or_raw(val, ord)
+    }
+
+    /// Bitwise "xor" with the current tag.
+    ///
+    /// Performs a bitwise "xor" operation on the current tag and the argument `val`, and sets the
+    /// new tag to the result. Returns the previous pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::{Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(1));
+    /// assert_eq!(a.fetch_xor(3, SeqCst).tag(), 1);
+    /// assert_eq!(a.load(SeqCst).tag(), 2);
+    /// ```
+    pub fn fetch_xor<'scope>(&self, val: usize, ord: Ordering) -> Ptr<'scope, T, G> {
+        self.fetch_xor_raw(val, ord)
+    }
+}
+
+impl<T, G: Scheme> Default for Atomic<T, G> {
+    fn default() -> Self {
+        Atomic::null()
+    }
+}
+
+impl<T, G: Scheme> From<T> for Atomic<T, G> {
+    fn from(t: T) -> Self {
+        Atomic::new(t)
+    }
+}
+
+impl<T, G: Scheme> From<Box<T>> for Atomic<T, G> {
+    fn from(b: Box<T>) -> Self {
+        Atomic::from_owned(Owned::from_box(b))
+    }
+}
+
+impl<T, G: Scheme> From<Owned<T, G>> for Atomic<T, G> {
+    fn from(owned: Owned<T, G>) -> Self {
+        Atomic::from_owned(owned)
+    }
+}
+
+impl<'scope, T, G: Scheme> From<Ptr<'scope, T, G>> for Atomic<T, G> {
+    fn from(ptr: Ptr<T, G>) -> Self {
+        Atomic::from_ptr(ptr)
+    }
+}
+
+/// An owned heap-allocated object.
+///
+/// This type is very similar to `Box<T>`.
+///
+/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
+/// least significant bits of the address.
+#[derive(Debug)]
+pub struct Owned<T, G: Scheme> {
+    pub data: usize,
+    _marker: PhantomData<(Box<T>, G)>,
+}
+
+impl<T, G: Scheme> Owned<T, G> {
+    /// Returns a new owned pointer pointing to the tagged pointer `data`.
+    pub(crate) unsafe fn from_data(data: usize) -> Self {
+        Owned {
+            data: data,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Allocates `value` with the allocator of the scheme, and returns a new owned pointer
+    /// pointing to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Owned;
+    ///
+    /// let o = Owned::new(1234);
+    /// ```
+    pub fn new(value: T) -> Self {
+        unsafe {
+            let raw = G::Alloc::alloc::<T>();
+            ptr::write(raw, value);
+            Self::from_raw(raw)
+        }
+    }
+
+    /// Returns a new owned pointer pointing to `b`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pointer (the `Box`) is not properly aligned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Owned;
+    ///
+    /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
+    /// ```
+    pub fn from_box(b: Box<T>) -> Self {
+        unsafe { Self::from_raw(Box::into_raw(b)) }
+    }
+
+    /// Returns a new owned pointer pointing to `raw`.
+    ///
+    /// This function is unsafe because improper use may lead to memory problems. Argument `raw`
+    /// must be a valid pointer. Also, a double-free may occur if the function is called twice on
+    /// the same raw pointer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `raw` is not properly aligned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Owned;
+    ///
+    /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
+    /// ```
+    pub unsafe fn from_raw(raw: *mut T) -> Self {
+        ensure_aligned(raw);
+        Self::from_data(raw as usize)
+    }
+
+    /// The implementation of `into_ptr`, for all guard types.
+    pub(crate) fn into_ptr_raw<'scope>(self) -> Ptr<'scope, T, G> {
+        let data = self.data;
+        mem::forget(self);
+        Ptr::from_data(data)
+    }
+
+    /// Returns the tag stored within the pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Owned;
+    ///
+    /// assert_eq!(Owned::new(1234).tag(), 0);
+    /// ```
+    pub fn tag(&self) -> usize {
+        self.data & low_bits::<T>()
+    }
+
+    /// Returns the same pointer, but tagged with `tag`. `tag` is truncated to be fit into the
+    /// unused bits of the pointer to `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Owned;
+    ///
+    /// let o = Owned::new(0u64);
+    /// assert_eq!(o.tag(), 0);
+    /// let o = o.with_tag(5);
+    /// assert_eq!(o.tag(), 5);
+    /// ```
+    pub fn with_tag(self, tag: usize) -> Self {
+        let data = self.data;
+        mem::forget(self);
+        unsafe { Self::from_data(data_with_tag::<T>(data, tag)) }
+    }
+}
+
+impl<T, G: Unguarded> Owned<T, G> {
+    /// Converts the owned pointer to a [`Ptr`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::nothing::atomic::Owned;
+    ///
+    /// let o = Owned::new(1234);
+    /// let p = o.into_ptr();
+    /// ```
+    ///
+    /// [`Ptr`]: struct.Ptr.html
+    pub fn into_ptr<'scope>(self) -> Ptr<'scope, T, G> {
+        self.into_ptr_raw()
+    }
+}
+
+impl<T, G: Scheme> Drop for Owned<T, G> {
+    fn drop(&mut self) {
+        let raw = (self.data & !low_bits::<T>()) as *mut T;
+        unsafe {
+            ptr::drop_in_place(raw);
+            G::Alloc::free(raw);
+        }
+    }
+}
+
+impl<T, G: Scheme> Deref for Owned<T, G> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        let raw = (self.data & !low_bits::<T>()) as *const T;
+        unsafe { &*(raw) }
+    }
+}
+
+impl<T, G: Scheme> DerefMut for Owned<T, G> {
+    fn deref_mut(&mut self) -> &mut T {
+        let raw = (self.data & !low_bits::<T>()) as *mut T;
+        unsafe { &mut *(raw) }
+    }
+}
+
+impl<T, G: Scheme> From<T> for Owned<T, G> {
+    fn from(t: T) -> Self {
+        Owned::new(t)
+    }
+}
+
+impl<T, G: Scheme> From<Box<T>> for Owned<T, G> {
+    fn from(b: Box<T>) -> Self {
+        Owned::from_box(b)
+    }
+}
+
+impl<T, G: Scheme> Borrow<T> for Owned<T, G> {
+    fn borrow(&self) -> &T {
+        &**self
+    }
+}
+
+impl<T, G: Scheme> BorrowMut<T> for Owned<T, G> {
+    fn borrow_mut(&mut self) -> &mut T {
+        &mut **self
+    }
+}
+
+impl<T, G: Scheme> AsRef<T> for Owned<T, G> {
+    fn as_ref(&self) -> &T {
+        &**self
+    }
+}
+
+impl<T, G: Scheme> AsMut<T> for Owned<T, G> {
+    fn as_mut(&mut self) -> &mut T {
+        &mut **self
+    }
+}
+
+/// A pointer to an object protected by the memory reclamation scheme.
+///
+/// The pointer is valid for use only within `'scope`.
+///
+/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
+/// least significant bits of the address.
+#[derive(Debug)]
+pub struct Ptr<'scope, T: 'scope, G> {
+    pub data: usize,
+    _marker: PhantomData<(&'scope (), *const T, G)>,
+}
+
+unsafe impl<'scope, T: Send, G> Send for Ptr<'scope, T, G> {}
+
+impl<'scope, T, G> PartialEq for Ptr<'scope, T, G> {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl<'scope, T, G> Clone for Ptr<'scope, T, G> {
+    fn clone(&self) -> Self {
+        Ptr {
+            data: self.data,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'scope, T, G> Copy for Ptr<'scope, T, G> {}
+
+impl<'scope, T, G: Scheme> Ptr<'scope, T, G> {
+    /// Returns a new pointer pointing to the tagged pointer `data`.
+    pub(crate) fn from_data(data: usize) -> Self {
+        Ptr {
+            data: data,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a new null pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Ptr;
+    ///
+    /// let p = Ptr::<i32>::null();
+    /// assert!(p.is_null());
+    /// ```
+    pub fn null() -> Self {
+        Ptr {
+            data: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a new pointer pointing to `raw`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `raw` is not properly aligned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::Ptr;
+    ///
+    /// let p = unsafe { Ptr::from_raw(Box::into_raw(Box::new(1234))) };
+    /// assert!(!p.is_null());
+    /// ```
+    pub fn from_raw(raw: *const T) -> Self {
+        ensure_aligned(raw);
+        Ptr {
+            data: raw as usize,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns `true` if the pointer is null.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::null();
+    /// epoch::pin(|scope| {
+    ///     assert!(a.load(SeqCst, scope).is_null());
+    ///     a.store_owned(Owned::new(1234), SeqCst);
+    ///     assert!(!a.load(SeqCst, scope).is_null());
+    /// });
+    /// ```
+    pub fn is_null(&self) -> bool {
+        self.as_raw().is_null()
+    }
+
+    /// Converts the pointer to a raw pointer (without the tag).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let o = Owned::new(1234);
+    /// let raw = &*o as *const _;
+    /// let a = Atomic::from_owned(o);
+    ///
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     assert_eq!(p.as_raw(), raw);
+    /// });
+    /// ```
+    pub fn as_raw(&self) -> *const T {
+        (self.data & !low_bits::<T>()) as *const T
+    }
+
+    /// Dereferences the pointer.
+    ///
+    /// Returns a reference to the pointee that is valid in `'scope`.
+    ///
+    /// # Safety
+    ///
+    /// Dereferencing a pointer is unsafe because it could be pointing to invalid memory.
+    ///
+    /// Another concern is the possiblity of data races due to lack of proper synchronization.
+    /// For example, consider the following scenario:
+    ///
+    /// 1. A thread creates a new object: `a.store_owned(Owned::new(10), Relaxed)`
+    /// 2. Another thread reads it: `*a.load(Relaxed, scope).as_ref().unwrap()`
+    ///
+    /// The problem is that relaxed orderings don't synchronize initialization of the object with
+    /// the read from the second thread. This is a data race. A possible solution would be to use
+    /// `Release` and `Acquire` orderings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     unsafe {
+    ///         assert_eq!(p.deref(), &1234);
+    ///     }
+    /// });
+    /// ```
+    pub unsafe fn deref(&self) -> &'scope T {
+        &*self.as_raw()
+    }
+
+    /// Converts the pointer to a reference.
+    ///
+    /// Returns `None` if the pointer is null, or else a reference to the object wrapped in `Some`.
+    ///
+    /// # Safety
+    ///
+    /// Dereferencing a pointer is unsafe because it could be pointing to invalid memory.
+    ///
+    /// Another concern is the possiblity of data races due to lack of proper synchronization.
+    /// For example, consider the following scenario:
+    ///
+    /// 1. A thread creates a new object: `a.store_owned(Owned::new(10), Relaxed)`
+    /// 2. Another thread reads it: `*a.load(Relaxed, scope).as_ref().unwrap()`
+    ///
+    /// The problem is that relaxed orderings don't synchronize initialization of the object with
+    /// the read from the second thread. This is a data race. A possible solution would be to use
+    /// `Release` and `Acquire` orderings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     unsafe {
+    ///         assert_eq!(p.as_ref(), Some(&1234));
+    ///     }
+    /// });
+    /// ```
+    pub unsafe fn as_ref(&self) -> Option<&'scope T> {
+        self.as_raw().as_ref()
+    }
+
+    /// Takes ownership of the pointee.
+    ///
+    /// # Safety
+    ///
+    /// This method may be called only if the pointer is valid and nobody else is holding a
+    /// reference to the same object.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// unsafe {
+    ///     epoch::unprotected(|scope| {
+    ///         let p = a.load(SeqCst, scope);
+    ///         drop(p.into_owned());
+    ///     });
+    /// }
+    /// ```
+    pub unsafe fn into_owned(self) -> Owned<T, G> {
+        Owned::from_data(self.data)
+    }
+
+    /// Returns the tag stored within the pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::from_owned(Owned::new(0u64).with_tag(5));
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     assert_eq!(p.tag(), 5);
+    /// });
+    /// ```
+    pub fn tag(&self) -> usize {
+        self.data & low_bits::<T>()
+    }
+
+    /// Returns the same pointer, but tagged with `tag`. `tag` is truncated to be fit into the
+    /// unused bits of the pointer to `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(0u64);
+    /// epoch::pin(|scope| {
+    ///     let p1 = a.load(SeqCst, scope);
+    ///     let p2 = p1.with_tag(5);
+    ///
+    ///     assert_eq!(p1.tag(), 0);
+    ///     assert_eq!(p2.tag(), 5);
+    ///     assert_eq!(p1.as_raw(), p2.as_raw());
+    /// });
+    /// ```
+    pub fn with_tag(&self, tag: usize) -> Self {
+        Self::from_data(data_with_tag::<T>(self.data, tag))
+    }
+}
+
+impl<'scope, T, G: Scheme> Default for Ptr<'scope, T, G> {
+    fn default() -> Self {
+        Ptr::null()
+    }
+}
+
+/// A pointer together with the version of the `AtomicVersioned` it was loaded from.
+#[derive(Debug)]
+pub struct Versioned<'scope, T: 'scope, G> {
+    pub ptr: Ptr<'scope, T, G>,
+    pub version: u64,
+}
+
+impl<'scope, T, G> PartialEq for Versioned<'scope, T, G> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr && self.version == other.version
+    }
+}
+
+impl<'scope, T, G> Clone for Versioned<'scope, T, G> {
+    fn clone(&self) -> Self {
+        Versioned {
+            ptr: self.ptr,
+            version: self.version,
+        }
+    }
+}
+
+impl<'scope, T, G> Copy for Versioned<'scope, T, G> {}
+
+/// Two words, which are compared and swapped together with `cmpxchg16b`. `lo` is the pointer and
+/// `hi` is the version.
+///
+/// If the CPU does not have `cmpxchg16b`, we do not use `hi`, and pack the version into `lo`
+/// instead, as on other 64-bit platforms.
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug)]
+#[repr(C, align(16))]
+struct DoubleWord {
+    lo: Packed,
+    hi: AtomicUsize,
+}
+
+/// Compare the 16 bytes at `dst` with `old`, and write `new` if they are equal. Returns the
+/// previous value, and whether we wrote `new`. `dst` must be 16 byte aligned, and the CPU must
+/// have `cmpxchg16b`, see `has_cmpxchg16b`.
+///
+/// `cmpxchg16b` takes the low word of `new` in `rbx`, but LLVM may use `rbx` as the base pointer,
+/// so we cannot bind it as an operand. Instead we swap it in and out of `rsi` ourselves.
+#[cfg(target_arch = "x86_64")]
+unsafe fn cmpxchg16b(
+    dst: *mut usize,
+    old: (usize, usize),
+    new: (usize, usize),
+) -> (usize, usize, bool) {
+    let prev_lo: usize;
+    let prev_hi: usize;
+    let ok: u8;
+    asm!("xchgq %rsi, %rbx
+          lock cmpxchg16b ($7)
+          xchgq %rsi, %rbx
+          sete $0"
+         : "=r"(ok), "={rax}"(prev_lo), "={rdx}"(prev_hi)
+         : "{rax}"(old.0), "{rdx}"(old.1), "{rsi}"(new.0), "{rcx}"(new.1), "{rdi}"(dst)
+         : "memory", "cc"
+         : "volatile");
+    (prev_lo, prev_hi, ok != 0)
+}
+
+/// Does the CPU have `cmpxchg16b`? Some early x86_64 CPUs do not, so `target_arch = "x86_64"`
+/// is not enough. This is bit 13 of `ecx` from `cpuid` leaf 1. `cpuid` writes `rbx`, which we
+/// save in `rsi`, for the same reason as in `cmpxchg16b`.
+#[cfg(target_arch = "x86_64")]
+fn has_cmpxchg16b() -> bool {
+    lazy_static! {
+        static ref HAS_CMPXCHG16B: bool = {
+            let _eax: u32;
+            let ecx: u32;
+            let _edx: u32;
+            unsafe {
+                asm!("movq %rbx, %rsi
+                      cpuid
+                      movq %rsi, %rbx"
+                     : "={eax}"(_eax), "={ecx}"(ecx), "={edx}"(_edx)
+                     : "{eax}"(1u32), "{ecx}"(0u32)
+                     : "rsi"
+                     : "volatile");
+            }
+            ecx & (1 << 13) != 0
+        };
+    }
+    *HAS_CMPXCHG16B
+}
+
+#[cfg(target_arch = "x86_64")]
+impl DoubleWord {
+    fn new(ptr: usize, version: u64) -> Self {
+        if !has_cmpxchg16b() {
+            return DoubleWord {
+                lo: Packed::new(ptr, version),
+                hi: AtomicUsize::new(0),
+            };
+        }
+        DoubleWord {
+            lo: Packed { data: AtomicUsize::new(ptr) },
+            hi: AtomicUsize::new(version as usize),
+        }
+    }
+
+    fn as_mut_ptr(&self) -> *mut usize {
+        &self.lo.data as *const AtomicUsize as *mut usize
+    }
+
+    /// There is no 16 byte load, and emulating one with `cmpxchg16b` would make every load a
+    /// locked write to the cache line. Instead we load the two words one by one, so we may get
+    /// the version of one value and the pointer of a later one. Since the version is incremented
+    /// on every CAS, only one value ever has a given version, so a torn result is never equal to
+    /// the current value, and a `compare_exchange` with it fails.
+    fn load(&self, ord: Ordering) -> (usize, u64) {
+        if !has_cmpxchg16b() {
+            return self.lo.load(ord);
+        }
+        let version = self.hi.load(ord);
+        let ptr = self.lo.data.load(ord);
+        (ptr, version as u64)
+    }
+
+    /// `cmpxchg16b` is a full barrier, so we ignore the orderings.
+    fn compare_exchange(
+        &self,
+        current: (usize, u64),
+        new: (usize, u64),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(), (usize, u64)> {
+        if !has_cmpxchg16b() {
+            return self.lo.compare_exchange(current, new, success, failure);
+        }
+        let (ptr, version, ok) = unsafe {
+            cmpxchg16b(
+                self.as_mut_ptr(),
+                (current.0, current.1 as usize),
+                (new.0, new.1 as usize),
+            )
+        };
+        if ok { Ok(()) } else { Err((ptr, version as u64)) }
+    }
+}
+
+/// The number of bits of the version in `Packed`.
+#[cfg(target_pointer_width = "64")]
+const PACKED_VERSION_BITS: usize = 16;
+#[cfg(target_pointer_width = "64")]
+const PACKED_PTR_MASK: usize = (1 << (64 - PACKED_VERSION_BITS)) - 1;
+
+/// The fallback when we do not have `cmpxchg16b`: we pack the version into the upper 16 bits of
+/// the pointer, which are unused on 64-bit platforms with 48-bit virtual addresses. The version
+/// wraps at 2^16.
+///
+/// This is `repr(C)`, so that `DoubleWord` can use `data` as its low word.
+#[cfg(target_pointer_width = "64")]
+#[derive(Debug)]
+#[repr(C)]
+struct Packed {
+    data: AtomicUsize,
+}
+
+#[cfg(target_pointer_width = "64")]
+impl Packed {
+    fn pack(ptr: usize, version: u64) -> usize {
+        assert_eq!(ptr & !PACKED_PTR_MASK, 0, "pointer uses the upper 16 bits");
+        ptr | ((version as usize) << (64 - PACKED_VERSION_BITS))
+    }
+
+    fn unpack(data: usize) -> (usize, u64) {
+        (data & PACKED_PTR_MASK, (data >> (64 - PACKED_VERSION_BITS)) as u64)
+    }
+
+    fn new(ptr: usize, version: u64) -> Self {
+        Packed { data: AtomicUsize::new(Self::pack(ptr, version)) }
+    }
+
+    fn load(&self, ord: Ordering) -> (usize, u64) {
+        Self::unpack(self.data.load(ord))
+    }
+
+    fn compare_exchange(
+        &self,
+        current: (usize, u64),
+        new: (usize, u64),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(), (usize, u64)> {
+        self.data
+            .compare_exchange(
+                Self::pack(current.0, current.1),
+                Self::pack(new.0, new.1),
+                success,
+                failure,
+            )
+            .map(|_| ())
+            .map_err(Self::unpack)
+    }
+}
+
+/// On 32-bit platforms there is no room in the pointer, but the pointer and a 32-bit version fit
+/// together in one 64-bit atomic. The version wraps at 2^32.
+#[cfg(target_pointer_width = "32")]
+#[derive(Debug)]
+struct Wide {
+    data: AtomicU64,
+}
+
+#[cfg(target_pointer_width = "32")]
+impl Wide {
+    fn pack(ptr: usize, version: u64) -> u64 {
+        ptr as u64 | (version << 32)
+    }
+
+    fn unpack(data: u64) -> (usize, u64) {
+        (data as u32 as usize, data >> 32)
+    }
+
+    fn new(ptr: usize, version: u64) -> Self {
+        Wide { data: AtomicU64::new(Self::pack(ptr, version)) }
+    }
+
+    fn load(&self, ord: Ordering) -> (usize, u64) {
+        Self::unpack(self.data.load(ord))
+    }
+
+    fn compare_exchange(
+        &self,
+        current: (usize, u64),
+        new: (usize, u64),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(), (usize, u64)> {
+        self.data
+            .compare_exchange(
+                Self::pack(current.0, current.1),
+                Self::pack(new.0, new.1),
+                success,
+                failure,
+            )
+            .map(|_| ())
+            .map_err(Self::unpack)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+type VersionedRepr = DoubleWord;
+#[cfg(all(not(target_arch = "x86_64"), target_pointer_width = "64"))]
+type VersionedRepr = Packed;
+#[cfg(target_pointer_width = "32")]
+type VersionedRepr = Wide;
+#[cfg(not(any(target_pointer_width = "32", target_pointer_width = "64")))]
+compile_error!("AtomicVersioned needs 32 or 64 bit pointers");
+
+/// An atomic pointer with a version, which is incremented on every successful
+/// `compare_and_set`. A CAS only succeeds if both the pointer and the version are the ones we
+/// loaded, so it fails if the pointer was changed and changed back in between, which avoids the
+/// ABA problem even if nodes are reused.
+///
+/// On x86_64 the version is 64 bits, and we use `cmpxchg16b`. `load` reads the pointer and the
+/// version one by one, so it is a plain load, but it may return a pair which was never stored.
+/// Such a pair makes the next `compare_and_set` fail, after which we have a consistent one. On
+/// other 64-bit platforms, and on x86_64 CPUs without `cmpxchg16b`, the version is packed into
+/// the upper 16 bits of the pointer, and wraps at 2^16. On 32-bit platforms, the pointer and a
+/// 32-bit version share one 64-bit atomic, and the version wraps at 2^32.
+#[derive(Debug)]
+pub struct AtomicVersioned<T, G> {
+    repr: VersionedRepr,
+    _marker: PhantomData<(*mut T, G)>,
+}
+
+unsafe impl<T: Send + Sync, G> Send for AtomicVersioned<T, G> {}
+unsafe impl<T: Send + Sync, G> Sync for AtomicVersioned<T, G> {}
+
+impl<T, G: Scheme> AtomicVersioned<T, G> {
+    /// Returns a new null atomic pointer with version 0.
+    pub fn null() -> Self {
+        Self::from_ptr(Ptr::null())
+    }
+
+    /// Returns a new atomic pointer pointing to `ptr`, with version 0.
+    pub fn from_ptr(ptr: Ptr<T, G>) -> Self {
+        AtomicVersioned {
+            repr: VersionedRepr::new(ptr.data, 0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Allocates `value` on the heap and returns a new atomic pointer pointing to it, with
+    /// version 0.
+    pub fn new(value: T) -> Self {
+        Self::from_ptr(Owned::new(value).into_ptr_raw())
+    }
+
+    /// The implementation of `load`, for all guard types.
+    pub(crate) fn load_raw<'scope>(&self, ord: Ordering) -> Versioned<'scope, T, G> {
+        let (data, version) = self.repr.load(ord);
+        Versioned {
+            ptr: Ptr::from_data(data),
+            version: version,
+        }
+    }
+
+    /// The implementation of `compare_and_set`, for all guard types.
+    pub(crate) fn compare_and_set_raw<'scope, O>(
+        &self,
+        current: Versioned<T, G>,
+        new: Ptr<T, G>,
+        ord: O,
+    ) -> Result<Versioned<'scope, T, G>, Versioned<'scope, T, G>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        let version = current.version.wrapping_add(1);
+        match self.repr.compare_exchange(
+            (current.ptr.data, current.version),
+            (new.data, version),
+            ord.success(),
+            ord.failure(),
+        ) {
+            Ok(()) => Ok(Versioned {
+                ptr: Ptr::from_data(new.data),
+                version: version,
+            }),
+            Err((data, version)) => Err(Versioned {
+                ptr: Ptr::from_data(data),
+                version: version,
+            }),
+        }
+    }
+}
+
+impl<T, G: Unguarded> AtomicVersioned<T, G> {
+    /// Loads the pointer and its version. On x86_64 the two may come from different values, see
+    /// `AtomicVersioned`, in which case a `compare_and_set` with them fails.
+    pub fn load<'scope>(&self, ord: Ordering) -> Versioned<'scope, T, G> {
+        self.load_raw(ord)
+    }
+
+    /// Stores `new` if the pointer and version are the same as in `current`, and increments the
+    /// version. On success, the new pointer and version are returned. On failure, the actual
+    /// current pointer and version are returned.
+    pub fn compare_and_set<'scope, O>(
+        &self,
+        current: Versioned<T, G>,
+        new: Ptr<T, G>,
+        ord: O,
+    ) -> Result<Versioned<'scope, T, G>, Versioned<'scope, T, G>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        self.compare_and_set_raw(current, new, ord)
+    }
+}
+
+impl<T, G: Scheme> Default for AtomicVersioned<T, G> {
+    fn default() -> Self {
+        AtomicVersioned::null()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_arch = "x86_64")]
+    use super::DoubleWord;
+    #[cfg(target_pointer_width = "64")]
+    use super::Packed;
+    #[cfg(target_pointer_width = "32")]
+    use super::Wide;
+    use nothing::atomic::{Atomic, AtomicVersioned, Owned, Ptr};
+
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::thread::spawn;
+
+    #[test]
+    fn compare_exchange_error() {
+        let a = Atomic::new(1);
+        let first = a.load(SeqCst);
+        let second = a.compare_exchange(first, Owned::new(2), SeqCst).unwrap();
+
+        // On failure we get back both the current value and the `Owned` we tried to store.
+        let err = a.compare_exchange(first, Owned::new(3), SeqCst).unwrap_err();
+        assert!(err.current == second);
+        assert_eq!(*err.new, 3);
+        let err = a.compare_exchange_weak(first, Ptr::null(), SeqCst).unwrap_err();
+        assert!(err.current == second);
+        assert!(err.new.is_null());
+
+        let third = a.compare_exchange(err.current, err.new, SeqCst).unwrap();
+        assert!(third.is_null());
+        unsafe {
+            drop(first.into_owned());
+            drop(second.into_owned());
+        }
+    }
+
+    #[test]
+    fn versioned_cas() {
+        let a = AtomicVersioned::new(1);
+        let first = a.load(SeqCst);
+        assert_eq!(first.version, 0);
+        let other = Owned::new(2).into_ptr();
+
+        let second = a.compare_and_set(first, other, SeqCst).unwrap();
+        assert_eq!(second.version, 1);
+        assert!(second.ptr == other);
+        // Swing the pointer back. The version is now different, so a CAS with `first` fails,
+        // even though the pointer is the same.
+        let third = a.compare_and_set(second, first.ptr, SeqCst).unwrap();
+        assert!(third.ptr == first.ptr);
+        let err = a.compare_and_set(first, Ptr::null(), SeqCst).unwrap_err();
+        assert!(err == third);
+        assert_eq!(a.load(SeqCst).version, 2);
+
+        unsafe {
+            drop(first.ptr.into_owned());
+            drop(other.into_owned());
+        }
+    }
+
+    #[test]
+    fn versioned_concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 4;
+
+        let a: Arc<AtomicVersioned<usize>> = Arc::new(AtomicVersioned::null());
+        let threads = (0..N_THREADS)
+            .map(|_| {
+                let a = a.clone();
+                spawn(move || for _ in 0..N {
+                    let mut current = a.load(SeqCst);
+                    while let Err(c) = a.compare_and_set(current, current.ptr, SeqCst) {
+                        current = c;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        assert_eq!(a.load(SeqCst).version, (N_THREADS * N) as u64);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn double_word() {
+        let d = DoubleWord::new(0x1000, 0);
+        assert_eq!(d.load(SeqCst), (0x1000, 0));
+        assert!(d.compare_exchange((0x1000, 0), (0x2000, 1), SeqCst, SeqCst).is_ok());
+        // A torn load, with the old version and the new pointer, is not the current value.
+        assert_eq!(
+            d.compare_exchange((0x2000, 0), (0x3000, 1), SeqCst, SeqCst),
+            Err((0x2000, 1))
+        );
+        assert_eq!(d.load(SeqCst), (0x2000, 1));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn packed() {
+        let p = Packed::new(0x1000, 0xffff);
+        assert_eq!(p.load(SeqCst), (0x1000, 0xffff));
+        assert!(p.compare_exchange((0x1000, 0), (0x2000, 1), SeqCst, SeqCst).is_err());
+        // The version wraps around.
+        assert!(
+            p.compare_exchange((0x1000, 0xffff), (0x2000, 0x10000), SeqCst, SeqCst)
+                .is_ok()
+        );
+        assert_eq!(p.load(SeqCst), (0x2000, 0));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "32")]
+    fn wide() {
+        let p = Wide::new(0x1000, 0xffff_ffff);
+        assert_eq!(p.load(SeqCst), (0x1000, 0xffff_ffff));
+        assert!(p.compare_exchange((0x1000, 0), (0x2000, 1), SeqCst, SeqCst).is_err());
+        // The version wraps around.
+        assert!(
+            p.compare_exchange((0x1000, 0xffff_ffff), (0x2000, 0x1_0000_0000), SeqCst, SeqCst)
+                .is_ok()
+        );
+        assert_eq!(p.load(SeqCst), (0x2000, 0));
+    }
+}
@@ -0,0 +1,100 @@
+/// A counter which is split into stripes, so that threads updating it do not contend on the same
+/// cache line. Each thread updates its own stripe, and reading the counter sums all stripes.
+///
+/// The sum is not a snapshot, since threads may update stripes we have already read while we
+/// sum, so the value is only approximate under concurrent updates.
+
+use std::sync::atomic::{AtomicIsize, AtomicUsize};
+use std::sync::atomic::Ordering::Relaxed;
+
+lazy_static! {
+    /// Used to hand out stripe indices to threads.
+    static ref NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+}
+
+thread_local! {
+    static STRIPE: usize = NEXT_STRIPE.fetch_add(1, Relaxed);
+}
+
+/// One stripe of the counter. We pad it to 64 bytes, so that two stripes do not share a cache
+/// line.
+#[derive(Debug)]
+struct Stripe {
+    count: AtomicIsize,
+    _padding: [usize; 7],
+}
+
+#[derive(Debug)]
+pub struct StripedCounter {
+    stripes: Vec<Stripe>,
+}
+
+impl StripedCounter {
+    /// Make a new counter with `n` stripes. If there are more than `n` threads, some threads
+    /// share a stripe.
+    pub fn new(n: usize) -> Self {
+        assert!(n > 0);
+        Self {
+            stripes: (0..n)
+                .map(|_| {
+                    Stripe {
+                        count: AtomicIsize::new(0),
+                        _padding: [0; 7],
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Add `n` to the stripe of the current thread.
+    pub fn add(&self, n: isize) {
+        let i = STRIPE.with(|i| *i) % self.stripes.len();
+        self.stripes[i].count.fetch_add(n, Relaxed);
+    }
+
+    /// Return the sum of all stripes. Since a thread may decrement its stripe for an increment
+    /// made in another stripe, the sum may be negative while we read it, so we clamp it at 0.
+    pub fn get(&self) -> usize {
+        let sum: isize = self.stripes.iter().map(|s| s.count.load(Relaxed)).sum();
+        if sum < 0 { 0 } else { sum as usize }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread::spawn;
+    use std::sync::Arc;
+
+    #[test]
+    fn add_get() {
+        let c = StripedCounter::new(4);
+        assert_eq!(c.get(), 0);
+        c.add(10);
+        c.add(-3);
+        assert_eq!(c.get(), 7);
+        c.add(-10);
+        assert_eq!(c.get(), 0);
+    }
+
+    #[test]
+    fn concurrent() {
+        const N_THREADS: usize = 8;
+        const N: usize = 1024 * 16;
+
+        let c = Arc::new(StripedCounter::new(4));
+        let threads = (0..N_THREADS)
+            .map(|_| {
+                let c = c.clone();
+                spawn(move || for _ in 0..N {
+                    c.add(1);
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        assert_eq!(c.get(), N_THREADS * N);
+    }
+}
@@ -1,192 +1,33 @@
-use std::borrow::{Borrow, BorrowMut};
-use std::marker::PhantomData;
-use std::mem;
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::AtomicUsize;
+/// The `ebr` instance of the tagged pointers in `::atomic`. Loading a pointer takes a `Pin`, and
+/// the returned `Ptr` may only be used while the thread is pinned.
+
 use std::sync::atomic::Ordering;
 
+use alloc::{self, Scheme};
 use super::Pin;
+pub use atomic::{CompareAndSetOrdering, Pointer};
 
-/// Given ordering for the success case in a compare-exchange operation, returns the strongest
-/// appropriate ordering for the failure case.
-#[inline]
-fn strongest_failure_ordering(ord: Ordering) -> Ordering {
-    use self::Ordering::*;
-    match ord {
-        Relaxed | Release => Relaxed,
-        Acquire | AcqRel => Acquire,
-        _ => SeqCst,
-    }
-}
-
-/// Memory orderings for compare-and-set operations.
-///
-/// A compare-and-set operation can have different memory orderings depending on whether it
-/// succeeds or fails. This trait generalizes different ways of specifying memory orderings.
-///
-/// The two ways of specifying orderings for compare-and-set are:
-///
-/// 1. Just one `Ordering` for the success case. In case of failure, the strongest appropriate
-///    ordering is chosen.
-/// 2. A pair of `Ordering`s. The first one is for the success case, while the second one is
-///    for the failure case.
-pub trait CompareAndSetOrdering {
-    /// The ordering of the operation when it succeeds.
-    fn success(&self) -> Ordering;
-
-    /// The ordering of the operation when it fails.
-    ///
-    /// The failure ordering can't be `Release` or `AcqRel` and must be equivalent or weaker than
-    /// the success ordering.
-    fn failure(&self) -> Ordering;
-}
-
-impl CompareAndSetOrdering for Ordering {
-    #[inline]
-    fn success(&self) -> Ordering {
-        *self
-    }
-
-    #[inline]
-    fn failure(&self) -> Ordering {
-        strongest_failure_ordering(*self)
-    }
-}
-
-impl CompareAndSetOrdering for (Ordering, Ordering) {
-    #[inline]
-    fn success(&self) -> Ordering {
-        self.0
-    }
-
-    #[inline]
-    fn failure(&self) -> Ordering {
-        self.1
-    }
-}
-
-/// Panics if the pointer is not properly unaligned.
-#[inline]
-fn ensure_aligned<T>(raw: *const T) {
-    if raw as usize & low_bits::<T>() != 0 {
-        panic!("unaligned pointer");
-    }
-}
-
-/// Returns a bitmask containing the unused least significant bits of an aligned pointer to `T`.
-#[inline]
-fn low_bits<T>() -> usize {
-    (1 << mem::align_of::<T>().trailing_zeros()) - 1
-}
-
-/// Given a tagged pointer `data`, returns the same pointer, but tagged with `tag`.  `tag` is
-/// truncated to be fit into the unused bits of the pointer to `T`.
-#[inline]
-fn data_with_tag<T>(data: usize, tag: usize) -> usize {
-    (data & !low_bits::<T>()) | (tag & low_bits::<T>())
-}
-
-/// An atomic pointer that can be safely shared between threads.
-///
-/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
-/// least significant bits of the address.  More precisely, a tag should be less than `(1 <<
-/// mem::align_of::<T>().trailing_zeros())`.
-///
-/// Any method that loads the pointer must be passed a [`Pin`].
-///
-/// [`Pin`]: struct.Pin.html
+/// The guard type of the `ebr` pointers.
 #[derive(Debug)]
-pub struct Atomic<T> {
-    data: AtomicUsize,
-    _marker: PhantomData<*mut T>,
+pub struct Ebr;
+
+impl Scheme for Ebr {
+    #[cfg(not(any(feature = "ebr-free-list", feature = "ebr-pool")))]
+    type Alloc = alloc::Global;
+    #[cfg(feature = "ebr-free-list")]
+    type Alloc = alloc::FreeList;
+    #[cfg(all(feature = "ebr-pool", not(feature = "ebr-free-list")))]
+    type Alloc = ::pool::Pool;
 }
 
-unsafe impl<T: Send + Sync> Send for Atomic<T> {}
-unsafe impl<T: Send + Sync> Sync for Atomic<T> {}
+pub type Atomic<T> = ::atomic::Atomic<T, Ebr>;
+pub type Owned<T> = ::atomic::Owned<T, Ebr>;
+pub type Ptr<'scope, T> = ::atomic::Ptr<'scope, T, Ebr>;
+pub type AtomicVersioned<T> = ::atomic::AtomicVersioned<T, Ebr>;
+pub type Versioned<'scope, T> = ::atomic::Versioned<'scope, T, Ebr>;
+pub type CompareExchangeError<'scope, T, P> = ::atomic::CompareExchangeError<'scope, T, Ebr, P>;
 
 impl<T> Atomic<T> {
-    /// Returns a new atomic pointer pointing to the tagged pointer `data`.
-    fn from_data(data: usize) -> Self {
-        Atomic {
-            data: AtomicUsize::new(data),
-            _marker: PhantomData,
-        }
-    }
-
-    /// Returns a new null atomic pointer.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Atomic;
-    ///
-    /// let a = Atomic::<i32>::null();
-    /// ```
-    #[cfg(not(feature = "nightly"))]
-    pub fn null() -> Self {
-        Atomic {
-            data: AtomicUsize::new(0),
-            _marker: PhantomData,
-        }
-    }
-
-    /// Returns a new null atomic pointer.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Atomic;
-    ///
-    /// let a = Atomic::<i32>::null();
-    /// ```
-    #[cfg(feature = "nightly")]
-    pub const fn null() -> Self {
-        Atomic {
-            data: AtomicUsize::new(0),
-            _marker: PhantomData,
-        }
-    }
-
-    /// Allocates `value` on the heap and returns a new atomic pointer pointing to it.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Atomic;
-    ///
-    /// let a = Atomic::new(1234);
-    /// ```
-    pub fn new(value: T) -> Self {
-        Self::from_owned(Owned::new(value))
-    }
-
-    /// Returns a new atomic pointer pointing to `owned`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{Atomic, Owned};
-    ///
-    /// let a = Atomic::from_owned(Owned::new(1234));
-    /// ```
-    pub fn from_owned(owned: Owned<T>) -> Self {
-        let data = owned.data;
-        mem::forget(owned);
-        Self::from_data(data)
-    }
-
-    /// Returns a new atomic pointer pointing to `ptr`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{Atomic, Ptr};
-    ///
-    /// let a = Atomic::from_ptr(Ptr::<i32>::null());
-    /// ```
-    pub fn from_ptr(ptr: Ptr<T>) -> Self {
-        Self::from_data(ptr.data)
-    }
 
     /// Loads a `Ptr` from the atomic pointer.
     ///
@@ -207,50 +48,9 @@ impl<T> Atomic<T> {
     /// });
     /// ```
     pub fn load<'scope>(&self, ord: Ordering, _: Pin<'scope>) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.load(ord))
+        self.load_raw(ord)
     }
 
-    /// Stores a `Ptr` into the atomic pointer.
-    ///
-    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
-    /// operation.
-    ///
-    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic, Ptr};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::new(1234);
-    /// a.store(Ptr::null(), SeqCst);
-    /// ```
-    pub fn store(&self, new: Ptr<T>, ord: Ordering) {
-        self.data.store(new.data, ord);
-    }
-
-    /// Stores an `Owned` into the atomic pointer.
-    ///
-    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
-    /// operation.
-    ///
-    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic, Owned};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::null();
-    /// a.store_owned(Owned::new(1234), SeqCst);
-    /// ```
-    pub fn store_owned(&self, new: Owned<T>, ord: Ordering) {
-        let data = new.data;
-        mem::forget(new);
-        self.data.store(data, ord);
-    }
 
     /// Stores a `Ptr` into the atomic pointer, returning the previous `Ptr`.
     ///
@@ -271,9 +71,10 @@ impl<T> Atomic<T> {
     /// });
     /// ```
     pub fn swap<'scope>(&self, new: Ptr<T>, ord: Ordering, _: Pin<'scope>) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.swap(new.data, ord))
+        self.swap_raw(new, ord)
     }
 
+
     /// Stores `new` into the atomic pointer if the current value is the same as `current`.
     ///
     /// The return value is a result indicating whether the new pointer was written. On failure the
@@ -307,17 +108,10 @@ impl<T> Atomic<T> {
     where
         O: CompareAndSetOrdering,
     {
-        match self.data.compare_exchange(
-            current.data,
-            new.data,
-            ord.success(),
-            ord.failure(),
-        ) {
-            Ok(_) => Ok(()),
-            Err(previous) => Err(Ptr::from_data(previous)),
-        }
+        self.compare_and_set_raw(current, new, ord)
     }
 
+
     /// Stores `new` into the atomic pointer if the current value is the same as `current`.
     ///
     /// Unlike [`compare_and_set`], this method is allowed to spuriously fail even when
@@ -359,27 +153,21 @@ impl<T> Atomic<T> {
     where
         O: CompareAndSetOrdering,
     {
-        match self.data.compare_exchange_weak(
-            current.data,
-            new.data,
-            ord.success(),
-            ord.failure(),
-        ) {
-            Ok(_) => Ok(()),
-            Err(previous) => Err(Ptr::from_data(previous)),
-        }
+        self.compare_and_set_weak_raw(current, new, ord)
     }
 
+
     /// Stores `new` into the atomic pointer if the current value is the same as `current`.
     ///
     /// The return value is a result indicating whether the new pointer was written. On success the
-    /// pointer that was written is returned. On failure `new` and the actual current value are
-    /// returned.
+    /// pointer that was written is returned. On failure a [`CompareExchangeError`] with `new` and
+    /// the actual current value is returned.
     ///
     /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
     /// ordering of this operation.
     ///
     /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    /// [`CompareExchangeError`]: struct.CompareExchangeError.html
     ///
     /// # Examples
     ///
@@ -400,38 +188,28 @@ impl<T> Atomic<T> {
         new: Owned<T>,
         ord: O,
         _: Pin<'scope>,
-    ) -> Result<Ptr<'scope, T>, (Ptr<'scope, T>, Owned<T>)>
+    ) -> Result<Ptr<'scope, T>, CompareExchangeError<'scope, T, Owned<T>>>
     where
         O: CompareAndSetOrdering,
     {
-        match self.data.compare_exchange(
-            current.data,
-            new.data,
-            ord.success(),
-            ord.failure(),
-        ) {
-            Ok(_) => {
-                let data = new.data;
-                mem::forget(new);
-                Ok(Ptr::from_data(data))
-            }
-            Err(previous) => Err((Ptr::from_data(previous), new)),
-        }
+        self.compare_exchange_raw(current, new, ord)
     }
 
+
     /// Stores `new` into the atomic pointer if the current value is the same as `current`.
     ///
     /// Unlike [`compare_and_set_owned`], this method is allowed to spuriously fail even when
     /// comparison succeeds, which can result in more efficient code on some platforms.
     /// The return value is a result indicating whether the new pointer was written. On success the
-    /// pointer that was written is returned. On failure `new` and the actual current value are
-    /// returned.
+    /// pointer that was written is returned. On failure a [`CompareExchangeError`] with `new` and
+    /// the actual current value is returned.
     ///
     /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
     /// ordering of this operation.
     ///
     /// [`compare_and_set_owned`]: struct.Atomic.html#method.compare_and_set_owned
     /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    /// [`CompareExchangeError`]: struct.CompareExchangeError.html
     ///
     /// # Examples
     ///
@@ -450,9 +228,9 @@ impl<T> Atomic<T> {
     ///                 ptr = p;
     ///                 break;
     ///             }
-    ///             Err((p, n)) => {
-    ///                 ptr = p;
-    ///                 new = n;
+    ///             Err(e) => {
+    ///                 ptr = e.current;
+    ///                 new = e.new;
     ///             }
     ///         }
     ///     }
@@ -464,25 +242,107 @@ impl<T> Atomic<T> {
         new: Owned<T>,
         ord: O,
         _: Pin<'scope>,
-    ) -> Result<Ptr<'scope, T>, (Ptr<'scope, T>, Owned<T>)>
+    ) -> Result<Ptr<'scope, T>, CompareExchangeError<'scope, T, Owned<T>>>
     where
         O: CompareAndSetOrdering,
     {
-        match self.data.compare_exchange_weak(
-            current.data,
-            new.data,
-            ord.success(),
-            ord.failure(),
-        ) {
-            Ok(_) => {
-                let data = new.data;
-                mem::forget(new);
-                Ok(Ptr::from_data(data))
-            }
-            Err(previous) => Err((Ptr::from_data(previous), new)),
-        }
+        self.compare_exchange_weak_raw(current, new, ord)
     }
 
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// `new` may be either an `Owned` or a `Ptr`. On success the pointer that was written is
+    /// returned. On failure a [`CompareExchangeError`] is returned, which has both the actual
+    /// current value and `new`, so that a retry loop needs neither to load the pointer again nor
+    /// to allocate a new node.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    /// [`CompareExchangeError`]: struct.CompareExchangeError.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic, Owned, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// epoch::pin(|scope| {
+    ///     let curr = a.load(SeqCst, scope);
+    ///     let new = a.compare_exchange(curr, Owned::new(5678), SeqCst, scope).unwrap();
+    ///     // `curr` is stale now, so this fails, and gives us back both pointers.
+    ///     let err = a.compare_exchange(curr, Ptr::null(), SeqCst, scope).unwrap_err();
+    ///     assert_eq!(err.current, new);
+    ///     assert!(err.new.is_null());
+    /// });
+    /// ```
+    pub fn compare_exchange<'scope, P, O>(
+        &self,
+        current: Ptr<T>,
+        new: P,
+        ord: O,
+        _: Pin<'scope>,
+    ) -> Result<Ptr<'scope, T>, CompareExchangeError<'scope, T, P>>
+    where
+        P: Pointer<T, Ebr>,
+        O: CompareAndSetOrdering,
+    {
+        self.compare_exchange_raw(current, new, ord)
+    }
+
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// Unlike [`compare_exchange`], this method is allowed to spuriously fail even when
+    /// comparison succeeds, which can result in more efficient code on some platforms.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`compare_exchange`]: struct.Atomic.html#method.compare_exchange
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use comere::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// epoch::pin(|scope| {
+    ///     let mut new = Owned::new(5678);
+    ///     let mut curr = a.load(SeqCst, scope);
+    ///     loop {
+    ///         match a.compare_exchange_weak(curr, new, SeqCst, scope) {
+    ///             Ok(_) => break,
+    ///             Err(e) => {
+    ///                 curr = e.current;
+    ///                 new = e.new;
+    ///             }
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn compare_exchange_weak<'scope, P, O>(
+        &self,
+        current: Ptr<T>,
+        new: P,
+        ord: O,
+        _: Pin<'scope>,
+    ) -> Result<Ptr<'scope, T>, CompareExchangeError<'scope, T, P>>
+    where
+        P: Pointer<T, Ebr>,
+        O: CompareAndSetOrdering,
+    {
+        self.compare_exchange_weak_raw(current, new, ord)
+    }
+
+
     /// Bitwise "and" with the current tag.
     ///
     /// Performs a bitwise "and" operation on the current tag and the argument `val`, and sets the
@@ -506,9 +366,10 @@ impl<T> Atomic<T> {
     /// });
     /// ```
     pub fn fetch_and<'scope>(&self, val: usize, ord: Ordering, _: Pin<'scope>) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_and(val | !low_bits::<T>(), ord))
+        self.fetch_and_raw(val, ord)
     }
 
+
     /// Bitwise "or" with the current tag.
     ///
     /// Performs a bitwise "or" operation on the current tag and the argument `val`, and sets the
@@ -532,9 +393,10 @@ impl<T> Atomic<T> {
     /// });
     /// ```
     pub fn fetch_or<'scope>(&self, val: usize, ord: Ordering, _: Pin<'scope>) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_or(val & low_bits::<T>(), ord))
+        self.fetch_or_raw(val, ord)
     }
 
+
     /// Bitwise "xor" with the current tag.
     ///
     /// Performs a bitwise "xor" operation on the current tag and the argument `val`, and sets the
@@ -558,112 +420,11 @@ impl<T> Atomic<T> {
     /// });
     /// ```
     pub fn fetch_xor<'scope>(&self, val: usize, ord: Ordering, _: Pin<'scope>) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_xor(val & low_bits::<T>(), ord))
-    }
-}
-
-impl<T> Default for Atomic<T> {
-    fn default() -> Self {
-        Atomic::null()
-    }
-}
-
-impl<T> From<T> for Atomic<T> {
-    fn from(t: T) -> Self {
-        Atomic::new(t)
-    }
-}
-
-impl<T> From<Box<T>> for Atomic<T> {
-    fn from(b: Box<T>) -> Self {
-        Atomic::from_owned(Owned::from_box(b))
-    }
-}
-
-impl<T> From<Owned<T>> for Atomic<T> {
-    fn from(owned: Owned<T>) -> Self {
-        Atomic::from_owned(owned)
-    }
-}
-
-impl<'scope, T> From<Ptr<'scope, T>> for Atomic<T> {
-    fn from(ptr: Ptr<T>) -> Self {
-        Atomic::from_ptr(ptr)
+        self.fetch_xor_raw(val, ord)
     }
 }
 
-/// An owned heap-allocated object.
-///
-/// This type is very similar to `Box<T>`.
-///
-/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
-/// least significant bits of the address.
-#[derive(Debug)]
-pub struct Owned<T> {
-    pub data: usize,
-    _marker: PhantomData<Box<T>>,
-}
-
 impl<T> Owned<T> {
-    /// Returns a new owned pointer pointing to the tagged pointer `data`.
-    unsafe fn from_data(data: usize) -> Self {
-        Owned {
-            data: data,
-            _marker: PhantomData,
-        }
-    }
-
-    /// Allocates `value` on the heap and returns a new owned pointer pointing to it.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Owned;
-    ///
-    /// let o = Owned::new(1234);
-    /// ```
-    pub fn new(value: T) -> Self {
-        Self::from_box(Box::new(value))
-    }
-
-    /// Returns a new owned pointer pointing to `b`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the pointer (the `Box`) is not properly aligned.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Owned;
-    ///
-    /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
-    /// ```
-    pub fn from_box(b: Box<T>) -> Self {
-        unsafe { Self::from_raw(Box::into_raw(b)) }
-    }
-
-    /// Returns a new owned pointer pointing to `raw`.
-    ///
-    /// This function is unsafe because improper use may lead to memory problems. Argument `raw`
-    /// must be a valid pointer. Also, a double-free may occur if the function is called twice on
-    /// the same raw pointer.
-    ///
-    /// # Panics
-    ///
-    /// Panics if `raw` is not properly aligned.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Owned;
-    ///
-    /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
-    /// ```
-    pub unsafe fn from_raw(raw: *mut T) -> Self {
-        ensure_aligned(raw);
-        Self::from_data(raw as usize)
-    }
 
     /// Converts the owned pointer to a [`Ptr`].
     ///
@@ -680,360 +441,32 @@ impl<T> Owned<T> {
     ///
     /// [`Ptr`]: struct.Ptr.html
     pub fn into_ptr<'scope>(self, _: Pin<'scope>) -> Ptr<'scope, T> {
-        let data = self.data;
-        mem::forget(self);
-        Ptr::from_data(data)
-    }
-
-    /// Returns the tag stored within the pointer.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Owned;
-    ///
-    /// assert_eq!(Owned::new(1234).tag(), 0);
-    /// ```
-    pub fn tag(&self) -> usize {
-        self.data & low_bits::<T>()
-    }
-
-    /// Returns the same pointer, but tagged with `tag`. `tag` is truncated to be fit into the
-    /// unused bits of the pointer to `T`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Owned;
-    ///
-    /// let o = Owned::new(0u64);
-    /// assert_eq!(o.tag(), 0);
-    /// let o = o.with_tag(5);
-    /// assert_eq!(o.tag(), 5);
-    /// ```
-    pub fn with_tag(self, tag: usize) -> Self {
-        let data = self.data;
-        mem::forget(self);
-        unsafe { Self::from_data(data_with_tag::<T>(data, tag)) }
-    }
-}
-
-impl<T> Drop for Owned<T> {
-    fn drop(&mut self) {
-        let raw = (self.data & !low_bits::<T>()) as *mut T;
-        unsafe {
-            drop(Box::from_raw(raw));
-        }
-    }
-}
-
-impl<T> Deref for Owned<T> {
-    type Target = T;
-
-    fn deref(&self) -> &T {
-        let raw = (self.data & !low_bits::<T>()) as *const T;
-        unsafe { &*(raw) }
-    }
-}
-
-impl<T> DerefMut for Owned<T> {
-    fn deref_mut(&mut self) -> &mut T {
-        let raw = (self.data & !low_bits::<T>()) as *mut T;
-        unsafe { &mut *(raw) }
-    }
-}
-
-impl<T> From<T> for Owned<T> {
-    fn from(t: T) -> Self {
-        Owned::new(t)
-    }
-}
-
-impl<T> From<Box<T>> for Owned<T> {
-    fn from(b: Box<T>) -> Self {
-        Owned::from_box(b)
-    }
-}
-
-impl<T> Borrow<T> for Owned<T> {
-    fn borrow(&self) -> &T {
-        &**self
+        self.into_ptr_raw()
     }
 }
 
-impl<T> BorrowMut<T> for Owned<T> {
-    fn borrow_mut(&mut self) -> &mut T {
-        &mut **self
-    }
-}
-
-impl<T> AsRef<T> for Owned<T> {
-    fn as_ref(&self) -> &T {
-        &**self
-    }
-}
-
-impl<T> AsMut<T> for Owned<T> {
-    fn as_mut(&mut self) -> &mut T {
-        &mut **self
-    }
-}
-
-/// A pointer to an object protected by the epoch GC.
-///
-/// The pointer is valid for use only within `'scope`.
-///
-/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
-/// least significant bits of the address.
-#[derive(Debug)]
-pub struct Ptr<'scope, T: 'scope> {
-    pub data: usize,
-    _marker: PhantomData<(&'scope (), *const T)>,
-}
-
-unsafe impl<'scope, T: Send> Send for Ptr<'scope, T> {}
-
-impl<'scope, T> Clone for Ptr<'scope, T> {
-    fn clone(&self) -> Self {
-        Ptr {
-            data: self.data,
-            _marker: PhantomData,
-        }
-    }
-}
-
-impl<'scope, T> Copy for Ptr<'scope, T> {}
-
-impl<'scope, T> Ptr<'scope, T> {
-    /// Returns a new pointer pointing to the tagged pointer `data`.
-    fn from_data(data: usize) -> Self {
-        Ptr {
-            data: data,
-            _marker: PhantomData,
-        }
-    }
-
-    /// Returns a new null pointer.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Ptr;
-    ///
-    /// let p = Ptr::<i32>::null();
-    /// assert!(p.is_null());
-    /// ```
-    pub fn null() -> Self {
-        Ptr {
-            data: 0,
-            _marker: PhantomData,
-        }
-    }
-
-    /// Returns a new pointer pointing to `raw`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if `raw` is not properly aligned.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::Ptr;
-    ///
-    /// let p = unsafe { Ptr::from_raw(Box::into_raw(Box::new(1234))) };
-    /// assert!(!p.is_null());
-    /// ```
-    pub fn from_raw(raw: *const T) -> Self {
-        ensure_aligned(raw);
-        Ptr {
-            data: raw as usize,
-            _marker: PhantomData,
-        }
-    }
-
-    /// Returns `true` if the pointer is null.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic, Owned};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::null();
-    /// epoch::pin(|scope| {
-    ///     assert!(a.load(SeqCst, scope).is_null());
-    ///     a.store_owned(Owned::new(1234), SeqCst);
-    ///     assert!(!a.load(SeqCst, scope).is_null());
-    /// });
-    /// ```
-    pub fn is_null(&self) -> bool {
-        self.as_raw().is_null()
-    }
-
-    /// Converts the pointer to a raw pointer (without the tag).
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic, Owned};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let o = Owned::new(1234);
-    /// let raw = &*o as *const _;
-    /// let a = Atomic::from_owned(o);
-    ///
-    /// epoch::pin(|scope| {
-    ///     let p = a.load(SeqCst, scope);
-    ///     assert_eq!(p.as_raw(), raw);
-    /// });
-    /// ```
-    pub fn as_raw(&self) -> *const T {
-        (self.data & !low_bits::<T>()) as *const T
-    }
-
-    /// Dereferences the pointer.
-    ///
-    /// Returns a reference to the pointee that is valid in `'scope`.
-    ///
-    /// # Safety
-    ///
-    /// Dereferencing a pointer is unsafe because it could be pointing to invalid memory.
-    ///
-    /// Another concern is the possiblity of data races due to lack of proper synchronization.
-    /// For example, consider the following scenario:
-    ///
-    /// 1. A thread creates a new object: `a.store_owned(Owned::new(10), Relaxed)`
-    /// 2. Another thread reads it: `*a.load(Relaxed, scope).as_ref().unwrap()`
-    ///
-    /// The problem is that relaxed orderings don't synchronize initialization of the object with
-    /// the read from the second thread. This is a data race. A possible solution would be to use
-    /// `Release` and `Acquire` orderings.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::new(1234);
-    /// epoch::pin(|scope| {
-    ///     let p = a.load(SeqCst, scope);
-    ///     unsafe {
-    ///         assert_eq!(p.deref(), &1234);
-    ///     }
-    /// });
-    /// ```
-    pub unsafe fn deref(&self) -> &'scope T {
-        &*self.as_raw()
-    }
+impl<T> AtomicVersioned<T> {
 
-    /// Converts the pointer to a reference.
-    ///
-    /// Returns `None` if the pointer is null, or else a reference to the object wrapped in `Some`.
-    ///
-    /// # Safety
-    ///
-    /// Dereferencing a pointer is unsafe because it could be pointing to invalid memory.
-    ///
-    /// Another concern is the possiblity of data races due to lack of proper synchronization.
-    /// For example, consider the following scenario:
-    ///
-    /// 1. A thread creates a new object: `a.store_owned(Owned::new(10), Relaxed)`
-    /// 2. Another thread reads it: `*a.load(Relaxed, scope).as_ref().unwrap()`
-    ///
-    /// The problem is that relaxed orderings don't synchronize initialization of the object with
-    /// the read from the second thread. This is a data race. A possible solution would be to use
-    /// `Release` and `Acquire` orderings.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::new(1234);
-    /// epoch::pin(|scope| {
-    ///     let p = a.load(SeqCst, scope);
-    ///     unsafe {
-    ///         assert_eq!(p.as_ref(), Some(&1234));
-    ///     }
-    /// });
-    /// ```
-    pub unsafe fn as_ref(&self) -> Option<&'scope T> {
-        self.as_raw().as_ref()
-    }
-
-    /// Takes ownership of the pointee.
-    ///
-    /// # Safety
-    ///
-    /// This method may be called only if the pointer is valid and nobody else is holding a
-    /// reference to the same object.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::new(1234);
-    /// unsafe {
-    ///     epoch::unprotected(|scope| {
-    ///         let p = a.load(SeqCst, scope);
-    ///         drop(p.into_owned());
-    ///     });
-    /// }
-    /// ```
-    pub unsafe fn into_owned(self) -> Owned<T> {
-        Owned::from_data(self.data)
-    }
-
-    /// Returns the tag stored within the pointer.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic, Owned};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::from_owned(Owned::new(0u64).with_tag(5));
-    /// epoch::pin(|scope| {
-    ///     let p = a.load(SeqCst, scope);
-    ///     assert_eq!(p.tag(), 5);
-    /// });
-    /// ```
-    pub fn tag(&self) -> usize {
-        self.data & low_bits::<T>()
+    /// Loads the pointer and its version. The returned pointer may only be used while the thread
+    /// is pinned.
+    pub fn load<'scope>(&self, ord: Ordering, _: Pin<'scope>) -> Versioned<'scope, T> {
+        self.load_raw(ord)
     }
 
-    /// Returns the same pointer, but tagged with `tag`. `tag` is truncated to be fit into the
-    /// unused bits of the pointer to `T`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use comere::{self as epoch, Atomic};
-    /// use std::sync::atomic::Ordering::SeqCst;
-    ///
-    /// let a = Atomic::new(0u64);
-    /// epoch::pin(|scope| {
-    ///     let p1 = a.load(SeqCst, scope);
-    ///     let p2 = p1.with_tag(5);
-    ///
-    ///     assert_eq!(p1.tag(), 0);
-    ///     assert_eq!(p2.tag(), 5);
-    ///     assert_eq!(p1.as_raw(), p2.as_raw());
-    /// });
-    /// ```
-    pub fn with_tag(&self, tag: usize) -> Self {
-        Self::from_data(data_with_tag::<T>(self.data, tag))
-    }
-}
-
-impl<'scope, T> Default for Ptr<'scope, T> {
-    fn default() -> Self {
-        Ptr::null()
+    /// Stores `new` if the pointer and version are the same as in `current`, and increments the
+    /// version. On success, the new pointer and version are returned. On failure, the actual
+    /// current pointer and version are returned.
+    pub fn compare_and_set<'scope, O>(
+        &self,
+        current: Versioned<T>,
+        new: Ptr<T>,
+        ord: O,
+        _: Pin<'scope>,
+    ) -> Result<Versioned<'scope, T>, Versioned<'scope, T>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        self.compare_and_set_raw(current, new, ord)
     }
 }
 
@@ -1,6 +1,7 @@
 use std::sync::atomic::Ordering::SeqCst;
 use super::atomic::{Owned, Atomic, Ptr};
 use std::mem::ManuallyDrop;
+use std::borrow::Borrow;
 use super::{Pin, pin};
 
 const STUCK_N: usize = 100_000;
@@ -135,6 +136,101 @@ impl<T> List<T> {
             _marker: ::std::marker::PhantomData,
         }
     }
+
+    /// Return a cursor pointing to the first element of the list.
+    pub fn cursor<'scope>(&'scope self, pin: Pin<'scope>) -> Cursor<'scope, T> {
+        Cursor {
+            list: self,
+            prev: &self.head,
+            curr: self.head.load(SeqCst, pin),
+            pin: pin,
+        }
+    }
+
+    /// Return the first element for which `f` returns `true`. The element may be removed while
+    /// we hold the reference, but its node is not freed before `pin` ends.
+    ///
+    /// Removing moves the element out of the node, and the thread which removed it may drop it
+    /// while we are reading it. That is only safe if `T` has no destructor, so we require
+    /// `T: Copy`.
+    pub fn find<'scope, F>(&'scope self, mut f: F, pin: Pin<'scope>) -> Option<&'scope T>
+    where
+        T: Copy,
+        F: FnMut(&T) -> bool,
+    {
+        let mut cursor = self.cursor(pin);
+        while let Some(t) = cursor.get() {
+            if f(t) {
+                return Some(t);
+            }
+            cursor.next();
+        }
+        None
+    }
+
+    /// Return the first element which borrows as `key`. This is useful for key/value records,
+    /// where `T: Borrow<K>` extracts the key. As with `find`, we require `T: Copy`.
+    pub fn get<'scope, Q: ?Sized>(&'scope self, key: &Q, pin: Pin<'scope>) -> Option<&'scope T>
+    where
+        T: Copy + Borrow<Q>,
+        Q: PartialEq,
+    {
+        self.find(|t| t.borrow() == key, pin)
+    }
+
+    /// Remove and return the first element for which `f` returns `true`.
+    pub fn remove_if<'scope, F>(&self, mut f: F, pin: Pin<'scope>) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut cursor = self.cursor(pin);
+        while let Some(t) = cursor.get() {
+            if f(t) {
+                if let Some(t) = cursor.remove_current() {
+                    return Some(t);
+                }
+                // Some other thread removed it first.
+            }
+            cursor.next();
+        }
+        None
+    }
+
+    /// Replace the first element for which `f` returns `Some(new)` with `new`, and return the old
+    /// element. The element is replaced by swinging the pointer to its node to a new node, so
+    /// concurrent readers see either the old or the new element, never a partial write.
+    pub fn update<'scope, F>(&self, mut f: F, pin: Pin<'scope>) -> Option<T>
+    where
+        F: FnMut(&T) -> Option<T>,
+    {
+        let mut cursor = self.cursor(pin);
+        while let Some(t) = cursor.get() {
+            if let Some(new) = f(t) {
+                if let Ok(old) = cursor.replace_current(new) {
+                    return Some(old);
+                }
+                // Some other thread removed it first.
+            }
+            cursor.next();
+        }
+        None
+    }
+
+    /// Remove all elements for which `f` returns `false`, in one pass over the list.
+    ///
+    /// If the cursor loses its position because of concurrent removals, we restart from the head,
+    /// so `f` may be called more than once for an element.
+    pub fn retain<'scope, F>(&self, mut f: F, pin: Pin<'scope>)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut cursor = self.cursor(pin);
+        while let Some(t) = cursor.get() {
+            if f(t) || cursor.remove_current().is_none() {
+                cursor.next();
+            }
+        }
+    }
 }
 
 /// An iterator for `List`
@@ -157,6 +253,192 @@ impl<'a, T> Iterator for Iter<'a, T> {
     }
 }
 
+/// A cursor into a `List`.
+///
+/// The cursor points to an element in the list, and remembers the `next` pointer that points to
+/// it, so that we can insert and remove elements at the cursor without walking the list from the
+/// head again. When the cursor is past the last element, `get` returns `None`.
+pub struct Cursor<'scope, T: 'static> {
+    list: &'scope List<T>,
+    prev: &'scope Atomic<Node<T>>,
+    curr: Ptr<'scope, Node<T>>,
+    pin: Pin<'scope>,
+}
+
+impl<'scope, T> Cursor<'scope, T> {
+    /// Move the cursor back to the first element of the list.
+    fn reset(&mut self) {
+        self.prev = &self.list.head;
+        self.curr = self.list.head.load(SeqCst, self.pin);
+    }
+
+    /// Return a reference to the current element, if any.
+    pub fn get(&self) -> Option<&'scope T> {
+        unsafe { self.curr.as_ref() }.map(|node| &*node.data)
+    }
+
+    /// Move the cursor to the next element in the list. Returns `false` if we have walked past
+    /// the end of the list.
+    ///
+    /// If the current element is removed by another thread, we continue from its predecessor
+    /// instead. If that is also removed, we have lost our position, and restart from the head.
+    pub fn next(&mut self) -> bool {
+        loop {
+            let node = match unsafe { self.curr.as_ref() } {
+                Some(node) => node,
+                None => return false,
+            };
+            let next = node.next.load(SeqCst, self.pin);
+            if next.tag() == 0 {
+                self.prev = &node.next;
+                self.curr = next;
+                return !next.is_null();
+            }
+            // `curr` is being removed. Once it is unlinked, `prev` points to its successor.
+            let fresh = self.prev.load(SeqCst, self.pin);
+            if fresh.tag() != 0 {
+                self.reset();
+                return !self.curr.is_null();
+            }
+            if fresh != self.curr {
+                self.curr = fresh;
+                return !fresh.is_null();
+            }
+        }
+    }
+
+    /// Insert `data` right after the current element. The cursor is not moved, so the next call
+    /// to `next` moves to the inserted element.
+    ///
+    /// Returns `Err(data)` if the cursor is past the end of the list, or if the current element
+    /// was removed.
+    pub fn insert_after(&mut self, data: T) -> Result<(), T> {
+        let node = match unsafe { self.curr.as_ref() } {
+            Some(node) => node,
+            None => return Err(data),
+        };
+        let mut new = Owned::new(Node::new(data));
+        loop {
+            let next = node.next.load(SeqCst, self.pin);
+            if next.tag() != 0 {
+                // `data` is `ManuallyDrop`, so dropping the node does not drop it.
+                let data = unsafe { ::std::ptr::read(&(*new).data) };
+                return Err(ManuallyDrop::into_inner(data));
+            }
+            new.next.store(next, SeqCst);
+            match node.next.compare_and_set_owned(next, new, SeqCst, self.pin) {
+                Ok(_) => return Ok(()),
+                Err(e) => new = e.new,
+            }
+        }
+    }
+
+    /// Remove the current element from the list, and return it. The cursor is moved to the next
+    /// element.
+    ///
+    /// Returns `None` if the cursor is past the end of the list, or if some other thread removed
+    /// the element first.
+    pub fn remove_current(&mut self) -> Option<T> {
+        loop {
+            let node = match unsafe { self.curr.as_ref() } {
+                Some(node) => node,
+                None => return None,
+            };
+            // Mark the node, so that no other thread inserts after it, or removes its successor.
+            let next = node.next.load(SeqCst, self.pin);
+            if next.tag() != 0 {
+                return None;
+            }
+            if node.next
+                .compare_and_set(next, next.with_tag(1), SeqCst, self.pin)
+                .is_err()
+            {
+                continue;
+            }
+            if self.prev
+                .compare_and_set(self.curr, next, SeqCst, self.pin)
+                .is_ok()
+            {
+                let data = unsafe { ::std::ptr::read(&node.data) };
+                unsafe {
+                    self.pin.add_garbage(self.curr.into_owned());
+                }
+                self.curr = next;
+                return Some(ManuallyDrop::into_inner(data));
+            }
+            // Either a node was inserted in front of us, or the previous node is being removed.
+            // Unmark, and find our predecessor again.
+            let _ = node.next
+                .compare_and_set(next.with_tag(1), next, SeqCst, self.pin);
+            if !self.relocate() {
+                return None;
+            }
+        }
+    }
+
+    /// Replace the current element with `data`, and return the old element. The cursor is moved
+    /// to the new element.
+    ///
+    /// Returns `Err(data)` if the cursor is past the end of the list, or if some other thread
+    /// removed the element first.
+    pub fn replace_current(&mut self, data: T) -> Result<T, T> {
+        let mut new = Owned::new(Node::new(data));
+        loop {
+            let node = match unsafe { self.curr.as_ref() } {
+                Some(node) => node,
+                None => break,
+            };
+            // Mark the node, so that no other thread inserts after it, or removes its successor.
+            let next = node.next.load(SeqCst, self.pin);
+            if next.tag() != 0 {
+                break;
+            }
+            if node.next
+                .compare_and_set(next, next.with_tag(1), SeqCst, self.pin)
+                .is_err()
+            {
+                continue;
+            }
+            new.next.store(next, SeqCst);
+            match self.prev
+                .compare_and_set_owned(self.curr, new, SeqCst, self.pin)
+            {
+                Ok(new) => {
+                    let data = unsafe { ::std::ptr::read(&node.data) };
+                    unsafe {
+                        self.pin.add_garbage(self.curr.into_owned());
+                    }
+                    self.curr = new;
+                    return Ok(ManuallyDrop::into_inner(data));
+                }
+                Err(e) => new = e.new,
+            }
+            // As in `remove_current`: unmark, and find our predecessor again.
+            let _ = node.next
+                .compare_and_set(next.with_tag(1), next, SeqCst, self.pin);
+            if !self.relocate() {
+                break;
+            }
+        }
+        // `data` is `ManuallyDrop`, so dropping the node does not drop it.
+        let data = unsafe { ::std::ptr::read(&(*new).data) };
+        Err(ManuallyDrop::into_inner(data))
+    }
+
+    /// Walk from the head to the current node again, after we failed to unlink it because `prev`
+    /// changed. Returns `false` if the node is no longer in the list.
+    fn relocate(&mut self) -> bool {
+        let target = self.curr;
+        self.reset();
+        while self.curr != target {
+            if !self.next() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
 impl<T: ::std::cmp::PartialEq> List<T> {
     /// Remove the first node in the list where `node.data == key`
     ///
@@ -409,7 +691,7 @@ mod test {
     use rand::{thread_rng, Rng};
 
     use std::thread::spawn;
-    use std::sync::Arc;
+    use std::sync::{Arc, Barrier};
 
     #[test]
     fn insert() {
@@ -455,6 +737,170 @@ mod test {
         pin(|pin| assert_eq!(list.iter(pin).next(), None));
     }
 
+    #[test]
+    fn cursor() {
+        let list = List::new();
+        const N: usize = 32;
+        pin(|pin| for i in 0..N {
+            list.insert(i, pin);
+        });
+        pin(|pin| {
+            // Remove the even elements, and insert `i + 100` after the odd ones.
+            let mut cursor = list.cursor(pin);
+            while let Some(&i) = cursor.get() {
+                if i % 2 == 0 {
+                    assert_eq!(cursor.remove_current(), Some(i));
+                } else {
+                    assert!(cursor.insert_after(i + 100).is_ok());
+                    assert!(cursor.next());
+                    assert_eq!(cursor.get(), Some(&(i + 100)));
+                    cursor.next();
+                }
+            }
+            assert!(!cursor.next());
+            assert_eq!(cursor.remove_current(), None);
+            assert_eq!(cursor.insert_after(0), Err(0));
+
+            let v = list.iter(pin).cloned().collect::<Vec<_>>();
+            let expected = (0..N)
+                .rev()
+                .filter(|i| i % 2 == 1)
+                .flat_map(|i| vec![i, i + 100])
+                .collect::<Vec<_>>();
+            assert_eq!(v, expected);
+        });
+    }
+
+    #[test]
+    fn cursor_remove_concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 16;
+
+        let list = Arc::new(List::new());
+        pin(|pin| for i in 0..N {
+            list.insert(i, pin);
+        });
+        // Threads must not exit while the others are still pinned, since exiting frees the
+        // thread local garbage.
+        let barrier = Arc::new(Barrier::new(N_THREADS));
+
+        let threads = (0..N_THREADS)
+            .map(|thread_id| {
+                let list = list.clone();
+                let barrier = barrier.clone();
+                spawn(move || {
+                    let mut removed = 0;
+                    pin(|pin| {
+                        let mut cursor = list.cursor(pin);
+                        while let Some(&i) = cursor.get() {
+                            if i % N_THREADS == thread_id {
+                                if cursor.remove_current().is_some() {
+                                    removed += 1;
+                                }
+                            } else {
+                                cursor.next();
+                            }
+                        }
+                    });
+                    barrier.wait();
+                    removed
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert_eq!(t.join().unwrap(), N / N_THREADS);
+        }
+        pin(|pin| assert_eq!(list.iter(pin).next(), None));
+    }
+
+    #[test]
+    fn predicates() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Record {
+            key: usize,
+            value: usize,
+        }
+        impl ::std::borrow::Borrow<usize> for Record {
+            fn borrow(&self) -> &usize {
+                &self.key
+            }
+        }
+
+        let list = List::new();
+        const N: usize = 32;
+        pin(|pin| {
+            for i in 0..N {
+                list.insert(Record { key: i, value: i * 10 }, pin);
+            }
+            assert_eq!(list.get(&5, pin).map(|r| r.value), Some(50));
+            assert_eq!(list.get(&N, pin), None);
+            assert_eq!(list.find(|r| r.value == 70, pin).map(|r| r.key), Some(7));
+            assert_eq!(list.find(|r| r.value == 71, pin), None);
+
+            assert_eq!(list.remove_if(|r| r.key == 3, pin).map(|r| r.value), Some(30));
+            assert_eq!(list.remove_if(|r| r.key == 3, pin), None);
+
+            list.retain(|r| r.key % 4 != 0, pin);
+            let mut keys = list.iter(pin).map(|r| r.key).collect::<Vec<_>>();
+            keys.sort();
+            let expected = (0..N).filter(|i| i % 4 != 0 && *i != 3).collect::<Vec<_>>();
+            assert_eq!(keys, expected);
+
+            let old = list.update(|r| if r.key == 5 {
+                Some(Record { key: 5, value: 51 })
+            } else {
+                None
+            }, pin);
+            assert_eq!(old, Some(Record { key: 5, value: 50 }));
+            assert_eq!(list.get(&5, pin).map(|r| r.value), Some(51));
+            assert_eq!(list.update(|r| if r.key == 3 { Some(*r) } else { None }, pin), None);
+            assert_eq!(list.iter(pin).count(), expected.len());
+        });
+    }
+
+    #[test]
+    fn update_concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 256;
+        const ROUNDS: usize = 16;
+
+        let list: Arc<List<(usize, usize)>> = Arc::new(List::new());
+        pin(|pin| for i in 0..N {
+            list.insert((i, 0), pin);
+        });
+        let barrier = Arc::new(Barrier::new(N_THREADS));
+
+        let threads = (0..N_THREADS)
+            .map(|_| {
+                let list = list.clone();
+                let barrier = barrier.clone();
+                spawn(move || {
+                    for _ in 0..ROUNDS {
+                        for i in 0..N {
+                            let old = pin(|pin| {
+                                list.update(|&(k, v)| {
+                                    if k == i { Some((k, v + 1)) } else { None }
+                                }, pin)
+                            });
+                            assert!(old.is_some());
+                        }
+                    }
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        // No update was lost, and no element was duplicated.
+        let mut v = pin(|pin| list.iter(pin).cloned().collect::<Vec<_>>());
+        v.sort();
+        let expected = (0..N).map(|i| (i, N_THREADS * ROUNDS)).collect::<Vec<_>>();
+        assert_eq!(v, expected);
+    }
+
     #[test]
     fn stress_test() {
         const N_THREADS: usize = 4;
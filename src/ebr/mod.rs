@@ -48,6 +48,10 @@ pub mod queue;
 #[allow(unused_variables)]
 #[allow(dead_code)]
 pub mod list;
+pub mod deque;
+pub mod priority_queue;
+pub mod seg_queue;
+pub mod wf_queue;
 
 use std::marker::PhantomData;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -224,6 +228,8 @@ struct GlobalState {
     epoch: AtomicUsize,
     pins: list::List<ThreadPinMarker>,
     garbage: queue::Queue<(usize, Bag)>,
+    /// The number of objects in the bags in `garbage`.
+    garbage_count: AtomicUsize,
     next_thread_id: AtomicUsize,
 }
 
@@ -251,6 +257,7 @@ impl GlobalState {
     /// epoch.
     fn add_garbage_bag<'scope>(&self, bag: Bag, _pin: Pin<'scope>) {
         let epoch = self.epoch.load(Ordering::SeqCst);
+        self.garbage_count.fetch_add(bag.index, Ordering::Relaxed);
         self.garbage.push((epoch, bag), _pin);
     }
 
@@ -261,6 +268,7 @@ impl GlobalState {
                 pin,
             )
         {
+            self.garbage_count.fetch_sub(bag.index, Ordering::Relaxed);
             // TODO: share this among other threads. Find out a good way to do this.
             // Idea: set the global state to something, eg. use a second lower bit in `epoch`
             // to signal that we are in "freeing mode". Then all threads calling 'pin' will be
@@ -315,6 +323,7 @@ lazy_static! {
             epoch: AtomicUsize::new(0),
             pins: list::List::new(),
             garbage: queue::Queue::new(),
+            garbage_count: AtomicUsize::new(0),
             next_thread_id: AtomicUsize::new(0),
         }
     };
@@ -333,11 +342,7 @@ impl LocalState {
     ///
     /// Note that we assume that only one thread is calling this on some data.
     /// This is maybe enforced by `Owned`?
-    fn add_garbage<'scope, T>(&mut self, o: Owned<T>, pin: Pin<'scope>)
-    where
-        T: 'static,
-    {
-        let g = Garbage::new(o);
+    fn add_garbage<'scope>(&mut self, g: Garbage, pin: Pin<'scope>) {
         match self.garbage_bag.try_insert(g) {
             Ok(()) => {}
             Err(o) => {
@@ -409,7 +414,7 @@ impl<'scope> Pin<'scope> {
     where
         T: 'static,
     {
-        LOCAL_EPOCH.with(|l| l.borrow_mut().add_garbage(o, *self));
+        LOCAL_EPOCH.with(|l| l.borrow_mut().add_garbage(Garbage::new(o), *self));
     }
 }
 
@@ -453,6 +458,13 @@ where
     ret
 }
 
+/// Returns the number of objects in the global garbage list, which are retired but not yet freed.
+/// Garbage in the local bags of the threads is not counted, so this lags by up to one bag per
+/// thread.
+pub fn pending_garbage() -> usize {
+    GLOBAL.garbage_count.load(Ordering::Relaxed)
+}
+
 
 
 #[cfg(test)]
@@ -466,6 +478,26 @@ mod test {
             pin(|pin| pin.add_garbage(atomic::Owned::new(0usize)));
         }
     }
+
+    #[test]
+    #[cfg(feature = "ebr-pool")]
+    fn add_garbage_reuses_memory() {
+        use std::collections::HashSet;
+        use pool::Pool;
+        // A type of its own size, so that no other test uses the same pool.
+        type T = [usize; 17];
+        // Retire nodes until the epoch has advanced far enough for some of them to be freed.
+        let mut retired = HashSet::new();
+        while Pool::len::<T>() == 0 {
+            assert!(retired.len() < 1024 * 1024, "the garbage was never freed");
+            let o = atomic::Owned::<T>::new([0; 17]);
+            retired.insert(o.data);
+            pin(|pin| pin.add_garbage(o));
+        }
+        // The next allocation takes a retired node from the pool.
+        let o = atomic::Owned::<T>::new([1; 17]);
+        assert!(retired.contains(&o.data));
+    }
 }
 
 mod bench {
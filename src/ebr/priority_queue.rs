@@ -0,0 +1,444 @@
+/// A lock-free priority queue, as described by Lindén and Jonsson in "A Skiplist-Based
+/// Concurrent Priority Queue with Minimal Memory Contention".
+///
+/// The queue is a skiplist, where `delete_min` logically deletes the first node by marking the
+/// `next[0]` pointer of its predecessor. This way the deleted nodes always form a prefix of the
+/// list. The nodes in the prefix are not physically removed one by one; instead, when a thread
+/// sees that the prefix is longer than `BOUND_OFFSET`, it swings the head past the whole prefix,
+/// and retires all of the nodes at once.
+///
+/// Note that `insert` never inserts into the deleted prefix, so a key which is smaller than the
+/// keys in the prefix is inserted right after it.
+
+use std::cell::Cell;
+use std::mem::ManuallyDrop;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+
+use super::Pin;
+use super::atomic::{Owned, Atomic, Ptr};
+
+/// The maximum height of a node.
+const NUM_LEVELS: usize = 16;
+
+/// The number of deleted nodes we accept in the prefix, before we try to remove them.
+const BOUND_OFFSET: usize = 32;
+
+pub struct Node<K, V> {
+    /// The key of the node. This is `None` for the head and tail sentinels.
+    key: Option<K>,
+    /// The value is moved out when the node is deleted, so we never drop it with the node.
+    value: ManuallyDrop<V>,
+    /// `true` while the node is being linked into the higher levels. Nodes after an inserting
+    /// node in the prefix are not retired, since the inserting thread may still link to them.
+    inserting: AtomicBool,
+    next: [Atomic<Node<K, V>>; NUM_LEVELS],
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Self {
+        Self {
+            key: Some(key),
+            value: ManuallyDrop::new(value),
+            inserting: AtomicBool::new(true),
+            next: Default::default(),
+        }
+    }
+
+    fn sentinel() -> Self {
+        Self {
+            key: None,
+            value: unsafe { ::std::mem::uninitialized() },
+            inserting: AtomicBool::new(false),
+            next: Default::default(),
+        }
+    }
+}
+
+impl<K: Ord, V> Node<K, V> {
+    /// Returns `true` if the key of the node is less than `k`. The tail sentinel has an infinite
+    /// key.
+    fn key_less(&self, k: &K) -> bool {
+        match self.key {
+            Some(ref key) => key < k,
+            None => false,
+        }
+    }
+}
+
+pub struct PriorityQueue<K, V> {
+    head: Atomic<Node<K, V>>,
+    tail: Atomic<Node<K, V>>,
+}
+
+impl<K, V> PriorityQueue<K, V>
+where
+    K: 'static + Ord + Clone,
+    V: 'static,
+{
+    pub fn new() -> Self {
+        let pin = Pin::fake();
+        let tail = Owned::new(Node::sentinel()).into_ptr(pin);
+        let head = Node::sentinel();
+        for i in 0..NUM_LEVELS {
+            head.next[i].store(tail, Relaxed);
+        }
+        Self {
+            head: Atomic::from_owned(Owned::new(head)),
+            tail: Atomic::from_ptr(tail),
+        }
+    }
+
+    /// Find the predecessors and successors of `k` on all levels. The predecessors on level 0 is
+    /// never in the deleted prefix. Return the last deleted node we saw on level 0, if any.
+    fn locate_preds<'scope>(
+        &self,
+        k: &K,
+        preds: &mut [Ptr<'scope, Node<K, V>>; NUM_LEVELS],
+        succs: &mut [Ptr<'scope, Node<K, V>>; NUM_LEVELS],
+        pin: Pin<'scope>,
+    ) -> Ptr<'scope, Node<K, V>> {
+        let mut del = Ptr::null();
+        let mut pred = self.head.load(SeqCst, pin);
+        let mut i = NUM_LEVELS;
+        while i > 0 {
+            i -= 1;
+            let mut cur = unsafe { pred.deref() }.next[i].load(SeqCst, pin);
+            // A marked pointer on level 0 means that `cur` is deleted.
+            let mut d = cur.tag() != 0;
+            cur = cur.with_tag(0);
+            loop {
+                let c = unsafe { cur.deref() };
+                let succ_deleted = c.next[0].load(SeqCst, pin).tag() != 0;
+                if !(c.key_less(k) || succ_deleted || (i == 0 && d)) {
+                    break;
+                }
+                if d && i == 0 {
+                    del = cur;
+                }
+                pred = cur;
+                let next = c.next[i].load(SeqCst, pin);
+                d = next.tag() != 0;
+                cur = next.with_tag(0);
+            }
+            preds[i] = pred;
+            succs[i] = cur;
+        }
+        del
+    }
+
+    /// Insert `value` with priority `key`.
+    pub fn insert<'scope>(&self, key: K, value: V, pin: Pin<'scope>) {
+        let height = random_level();
+        let mut preds = [Ptr::null(); NUM_LEVELS];
+        let mut succs = [Ptr::null(); NUM_LEVELS];
+        let new = Owned::new(Node::new(key, value)).into_ptr(pin);
+        let node = unsafe { new.deref() };
+        let key = node.key.as_ref().unwrap();
+
+        let mut del;
+        loop {
+            del = self.locate_preds(key, &mut preds, &mut succs, pin);
+            node.next[0].store(succs[0], SeqCst);
+            let pred = unsafe { preds[0].deref() };
+            if pred.next[0]
+                .compare_and_set(succs[0], new, SeqCst, pin)
+                .is_ok()
+            {
+                break;
+            }
+        }
+
+        let mut i = 1;
+        while i < height {
+            node.next[i].store(succs[i], SeqCst);
+            let succ = unsafe { succs[i].deref() };
+            // If the new node or the successor is being deleted, there is no point in linking
+            // it in on the higher levels. If the new node is deleted, a predecessor with a
+            // smaller key may have been inserted after it, and linking to it from there would
+            // make the levels go out of order.
+            if node.next[0].load(SeqCst, pin).tag() != 0 ||
+                succ.next[0].load(SeqCst, pin).tag() != 0 || del == succs[i] ||
+                self.is_deleted(new, pin)
+            {
+                break;
+            }
+            let pred = unsafe { preds[i].deref() };
+            if pred.next[i]
+                .compare_and_set(succs[i], new, SeqCst, pin)
+                .is_ok()
+            {
+                i += 1;
+            } else {
+                del = self.locate_preds(key, &mut preds, &mut succs, pin);
+                if succs[0] != new {
+                    // The node has been deleted.
+                    break;
+                }
+            }
+        }
+        node.inserting.store(false, SeqCst);
+    }
+
+    /// Returns `true` if `node` is in the deleted prefix.
+    fn is_deleted<'scope>(&self, node: Ptr<'scope, Node<K, V>>, pin: Pin<'scope>) -> bool {
+        let mut x = self.head.load(SeqCst, pin);
+        loop {
+            let next = unsafe { x.deref() }.next[0].load(SeqCst, pin);
+            if next.tag() == 0 {
+                return false;
+            }
+            if next.with_tag(0) == node {
+                return true;
+            }
+            x = next.with_tag(0);
+        }
+    }
+
+    /// Remove the element with the smallest key from the queue, and return its key and value.
+    pub fn delete_min<'scope>(&self, pin: Pin<'scope>) -> Option<(K, V)> {
+        let head_ptr = self.head.load(SeqCst, pin);
+        let head = unsafe { head_ptr.deref() };
+        let tail = self.tail.load(SeqCst, pin);
+        let obs_head = head.next[0].load(SeqCst, pin);
+        let mut new_head = Ptr::null();
+        let mut offset = 0;
+        let mut x = head_ptr;
+        // Walk the deleted prefix, and try to mark the first node after it.
+        loop {
+            let x_node = unsafe { x.deref() };
+            if x_node.next[0].load(SeqCst, pin).with_tag(0) == tail {
+                return None;
+            }
+            if new_head.is_null() && x_node.inserting.load(SeqCst) {
+                new_head = x;
+            }
+            let next = x_node.next[0].fetch_or(1, SeqCst, pin);
+            offset += 1;
+            x = next.with_tag(0);
+            if next.tag() == 0 {
+                // We marked the pointer, so `x` is ours.
+                break;
+            }
+        }
+        let ret = unsafe {
+            let x_node = x.deref();
+            let value = ::std::ptr::read(&x_node.value);
+            (x_node.key.clone().unwrap(), ManuallyDrop::into_inner(value))
+        };
+        if new_head.is_null() {
+            new_head = x;
+        }
+        if offset >= BOUND_OFFSET {
+            // Swing the head past the prefix. If we succeed, we are the only thread retiring the
+            // nodes from `obs_head` up until `new_head`.
+            if head.next[0]
+                .compare_and_set(obs_head, new_head.with_tag(1), SeqCst, pin)
+                .is_ok()
+            {
+                self.restructure(pin);
+                let mut cur = obs_head.with_tag(0);
+                while cur != new_head {
+                    let next = unsafe { cur.deref() }.next[0].load(SeqCst, pin).with_tag(0);
+                    unsafe {
+                        pin.add_garbage(cur.into_owned());
+                    }
+                    cur = next;
+                }
+            }
+        }
+        Some(ret)
+    }
+
+    /// Update the pointers of the head on levels above 0, so that they skip the deleted prefix.
+    fn restructure<'scope>(&self, pin: Pin<'scope>) {
+        let head = unsafe { self.head.load(SeqCst, pin).deref() };
+        let mut pred = head;
+        let mut i = NUM_LEVELS - 1;
+        while i > 0 {
+            let h = head.next[i].load(SeqCst, pin);
+            if unsafe { h.deref() }.next[0].load(SeqCst, pin).tag() == 0 {
+                i -= 1;
+                continue;
+            }
+            let mut cur = pred.next[i].load(SeqCst, pin);
+            while unsafe { cur.deref() }.next[0].load(SeqCst, pin).tag() != 0 {
+                pred = unsafe { cur.deref() };
+                cur = pred.next[i].load(SeqCst, pin);
+            }
+            if head.next[i]
+                .compare_and_set(h, pred.next[i].load(SeqCst, pin), SeqCst, pin)
+                .is_ok()
+            {
+                i -= 1;
+            }
+        }
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty<'scope>(&self, pin: Pin<'scope>) -> bool {
+        let tail = self.tail.load(SeqCst, pin);
+        let mut node = self.head.load(SeqCst, pin);
+        loop {
+            let next = unsafe { node.deref() }.next[0].load(SeqCst, pin);
+            if next.with_tag(0) == tail {
+                return true;
+            }
+            if next.tag() == 0 {
+                return false;
+            }
+            node = next.with_tag(0);
+        }
+    }
+}
+
+impl<K, V> Drop for PriorityQueue<K, V> {
+    fn drop(&mut self) {
+        unsafe {
+            let pin = Pin::fake();
+            let tail = self.tail.load(SeqCst, pin);
+            let mut node = self.head.load(SeqCst, pin).into_owned();
+            loop {
+                let next = node.next[0].load(SeqCst, pin);
+                ::std::mem::drop(node);
+                if next.with_tag(0) == tail {
+                    break;
+                }
+                node = next.with_tag(0).into_owned();
+                if next.tag() == 0 {
+                    // The node is not deleted, so we still own its value.
+                    ManuallyDrop::drop(&mut node.value);
+                }
+            }
+            ::std::mem::drop(tail.into_owned());
+        }
+    }
+}
+
+/// Return a random height for a new node, where each level is half as likely as the previous.
+fn random_level() -> usize {
+    thread_local! {
+        static SEED: Cell<u32> = Cell::new(0);
+    }
+    SEED.with(|seed| {
+        let mut x = seed.get();
+        if x == 0 {
+            x = (seed as *const _ as usize as u32) | 1;
+        }
+        // xorshift32
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        seed.set(x);
+        ::std::cmp::min(x.trailing_zeros() as usize + 1, NUM_LEVELS)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::pin;
+    use rand::{thread_rng, Rng};
+
+    use std::thread::spawn;
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn st_insert_delete_min() {
+        const N: usize = 1024;
+        let pq = PriorityQueue::new();
+        let mut keys = (0..N).collect::<Vec<_>>();
+        thread_rng().shuffle(&mut keys);
+        pin(|pin| for &k in &keys {
+            pq.insert(k, k * 2, pin);
+        });
+        for i in 0..N {
+            assert_eq!(pin(|pin| pq.delete_min(pin)), Some((i, i * 2)));
+        }
+        assert_eq!(pin(|pin| pq.delete_min(pin)), None);
+        assert!(pin(|pin| pq.is_empty(pin)));
+    }
+
+    #[test]
+    fn drop_values() {
+        use std::sync::atomic::AtomicUsize;
+        lazy_static! {
+            static ref COUNT: AtomicUsize = AtomicUsize::new(0);
+        }
+        struct MustDrop;
+        impl Drop for MustDrop {
+            fn drop(&mut self) {
+                COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        {
+            let pq = PriorityQueue::new();
+            pin(|pin| {
+                for i in 0..100 {
+                    pq.insert(i, MustDrop, pin);
+                }
+                for _ in 0..50 {
+                    pq.delete_min(pin);
+                }
+            });
+        }
+        assert_eq!(COUNT.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn is_unique_receiver() {
+        const N_THREADS: usize = 8;
+        const ELEMS: usize = 1024 * 64;
+
+        let pq = Arc::new(PriorityQueue::new());
+        let markers = Arc::new(
+            (0..ELEMS)
+                .map(|_| AtomicBool::new(false))
+                .collect::<Vec<_>>(),
+        );
+
+        let barrier = Arc::new(Barrier::new(N_THREADS));
+        // Half of the threads insert, while the other half delete.
+        let threads = (0..N_THREADS)
+            .map(|thread_id| {
+                let pq = pq.clone();
+                let markers = markers.clone();
+                let barrier = barrier.clone();
+                spawn(move || {
+                    if thread_id % 2 == 0 {
+                        let mut i = thread_id / 2;
+                        while i < ELEMS {
+                            pin(|pin| pq.insert(i, i, pin));
+                            i += N_THREADS / 2;
+                        }
+                    } else {
+                        for _ in 0..ELEMS / N_THREADS {
+                            if let Some((k, v)) = pin(|pin| pq.delete_min(pin)) {
+                                assert_eq!(k, v);
+                                assert!(!markers[k].swap(true, Ordering::SeqCst));
+                            }
+                        }
+                    }
+                    // `LocalState::drop` frees the threads marker right away, which is not safe
+                    // if other threads are still pinning. Wait for everyone before exiting.
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        // The rest of the elements must come out in order.
+        let mut last = None;
+        while let Some((k, _)) = pin(|pin| pq.delete_min(pin)) {
+            assert!(last < Some(k));
+            last = Some(k);
+            assert!(!markers[k].swap(true, Ordering::SeqCst));
+        }
+        for m in markers.iter() {
+            assert!(m.load(Ordering::SeqCst));
+        }
+    }
+}
@@ -9,12 +9,15 @@ use std::mem::ManuallyDrop;
 use super::Pin;
 
 use super::atomic::{Owned, Atomic, Ptr};
+use counter::StripedCounter;
 
 
 #[derive(Debug)]
 pub struct Queue<T> {
     head: Atomic<Node<T>>,
     tail: Atomic<Node<T>>,
+    /// Counts the elements in the queue, if the queue is made with `with_counter`.
+    counter: Option<StripedCounter>,
 }
 
 impl<T> Drop for Queue<T> {
@@ -82,12 +85,29 @@ where
         let q = Queue {
             head: Atomic::null(),
             tail: Atomic::null(),
+            counter: None,
         };
         q.head.store(ptr, Relaxed);
         q.tail.store(ptr, Relaxed);
         q
     }
 
+    /// Make a queue which counts its elements in a `StripedCounter` with `stripes` stripes, so
+    /// that `len` does not have to walk the queue. `stripes` should usually be the number of
+    /// threads using the queue.
+    pub fn with_counter(stripes: usize) -> Self {
+        let mut q = Self::new();
+        q.counter = Some(StripedCounter::new(stripes));
+        q
+    }
+
+    /// Add `n` to the element counter, if we have one.
+    fn count(&self, n: isize) {
+        if let Some(ref counter) = self.counter {
+            counter.add(n);
+        }
+    }
+
     pub fn push<'scope>(&self, t: T, _pin: Pin<'scope>) {
         let node = Owned::new(Node::new(t));
         let new_node = node.into_ptr(_pin);
@@ -109,12 +129,68 @@ where
                     // Update `queue.tail`. If we fail here it's OK, since another
                     // thread could have helped by moving the tail pointer.
                     let _ = self.tail.compare_and_set(tail, new_node, SeqCst, _pin);
+                    self.count(1);
                     break;
                 }
             }
         }
     }
 
+    /// Push all elements of `iter`. The nodes are linked together before we link the first of
+    /// them into the queue, so the whole batch takes one CAS, and the elements are next to each
+    /// other in the queue.
+    pub fn push_batch<'scope, I>(&self, iter: I, pin: Pin<'scope>)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut iter = iter.into_iter();
+        let first = match iter.next() {
+            Some(t) => Owned::new(Node::new(t)).into_ptr(pin),
+            None => return,
+        };
+        let mut last = first;
+        let mut count = 1;
+        for t in iter {
+            let node = Owned::new(Node::new(t)).into_ptr(pin);
+            unsafe { last.deref() }.next.store(node, Relaxed);
+            last = node;
+            count += 1;
+        }
+        loop {
+            let tail = self.tail.load(SeqCst, pin);
+            let t = unsafe { tail.deref() };
+            let next = t.next.load(SeqCst, pin);
+            if !next.is_null() {
+                let _ = self.tail.compare_and_set(tail, next, SeqCst, pin);
+                continue;
+            }
+            if t.next
+                .compare_and_set(Ptr::null(), first, SeqCst, pin)
+                .is_ok()
+            {
+                // Move the tail past the whole batch. If we fail, other threads will move it one
+                // node at a time.
+                let _ = self.tail.compare_and_set(tail, last, SeqCst, pin);
+                self.count(count);
+                return;
+            }
+        }
+    }
+
+    /// If the tail points to `node`, move it to `next`. The head may not move past the tail,
+    /// since the node the tail points to would then be freed, so this must be called before we
+    /// move the head from `node`.
+    fn help_tail<'scope>(
+        &self,
+        node: Ptr<'scope, Node<T>>,
+        next: Ptr<'scope, Node<T>>,
+        pin: Pin<'scope>,
+    ) {
+        if self.tail.load(SeqCst, pin) == node {
+            let _ = self.tail.compare_and_set(node, next, SeqCst, pin);
+        }
+    }
+
     pub fn pop<'scope>(&self, _pin: Pin<'scope>) -> Option<T> {
         'outer: loop {
             let head: Ptr<Node<T>> = self.head.load(SeqCst, _pin);
@@ -150,11 +226,13 @@ where
                     //
                     // This is where we leak memory: when we CAS out `head`, it is no longer reachable
                     // by the queue.
+                    self.help_tail(head, next, _pin);
                     let res = self.head.compare_and_set(head, next, SeqCst, _pin);
                     match res {
                         Ok(()) => {
                         let data = ::std::ptr::read(&node.data);
                         _pin.add_garbage(head.into_owned());
+                        self.count(-1);
                         return Some(ManuallyDrop::into_inner(data));
                     }
                         Err(e) => continue 'outer,
@@ -165,6 +243,48 @@ where
         }
     }
 
+    /// Pop up to `n` elements, with one CAS on the head.
+    pub fn pop_batch<'scope>(&self, n: usize, pin: Pin<'scope>) -> Vec<T> {
+        let mut ret = Vec::new();
+        if n == 0 {
+            return ret;
+        }
+        loop {
+            let head = self.head.load(SeqCst, pin);
+            // Find the node which will be the new sentinel.
+            let mut last = head;
+            let mut count = 0;
+            while count < n {
+                let next = unsafe { last.deref() }.next.load(SeqCst, pin);
+                if next.is_null() {
+                    break;
+                }
+                self.help_tail(last, next, pin);
+                last = next;
+                count += 1;
+            }
+            if count == 0 {
+                return ret;
+            }
+            if self.head.compare_and_set(head, last, SeqCst, pin).is_err() {
+                continue;
+            }
+            self.count(-(count as isize));
+            // Like in `pop`, the element of each node is in the node after it.
+            ret.reserve(count);
+            let mut node = head;
+            while node != last {
+                unsafe {
+                    let next = node.deref().next.load(SeqCst, pin);
+                    ret.push(ManuallyDrop::into_inner(::std::ptr::read(&next.deref().data)));
+                    pin.add_garbage(node.into_owned());
+                    node = next;
+                }
+            }
+            return ret;
+        }
+    }
+
     /// Pop the first element of the queue if `F(head)` evaluates
     /// to `true`.
     pub fn pop_if<'scope, F>(&self, f: F, _pin: Pin<'scope>) -> Option<T>
@@ -179,10 +299,12 @@ where
                 let data = unsafe { ::std::ptr::read(&node.data) };
                 if f(&*data) {
                     unsafe {
+                        self.help_tail(head, next, _pin);
                         let res = self.head.compare_and_set(head, next, SeqCst, _pin);
                         match res {
                             Ok(()) => {
                                 _pin.add_garbage(head.into_owned());
+                                self.count(-1);
                                 Some(ManuallyDrop::into_inner(data))
                             }
                             Err(e) => None,
@@ -196,11 +318,21 @@ where
         }
     }
 
-    /// Count the number of elements in the queue.
+    /// Return the number of elements in the queue. If the queue is made with `with_counter`, this
+    /// sums the counter, which is O(threads), but only approximate while other threads push and
+    /// pop. Otherwise, this is `len_exact`.
+    pub fn len<'scope>(&self, pin: Pin<'scope>) -> usize {
+        match self.counter {
+            Some(ref counter) => counter.get(),
+            None => self.len_exact(pin),
+        }
+    }
+
+    /// Count the number of elements in the queue, by walking it.
     /// This is typically not a operation we need,
     /// but it is practical to have for testing
     /// purposes
-    pub fn len<'scope>(&self, _pin: Pin<'scope>) -> usize {
+    pub fn len_exact<'scope>(&self, _pin: Pin<'scope>) -> usize {
         let mut len = 0;
         let mut node = unsafe { self.head.load(Acquire, _pin).deref() };
         while let Some(next) = unsafe { node.next.load(Relaxed, _pin).as_ref() } {
@@ -216,6 +348,103 @@ where
         let h = unsafe { head.deref() };
         h.next.load(Acquire, _pin).is_null()
     }
+
+    /// Returns a reference to the first element of the queue, without removing it. The element
+    /// may be popped while we hold the reference, but its node is not freed before `pin` ends.
+    ///
+    /// `pop` moves the element out of the node, and the thread which popped it may drop it while
+    /// we are reading it. That is only safe if `T` has no destructor, so we require `T: Copy`.
+    pub fn peek<'scope>(&'scope self, pin: Pin<'scope>) -> Option<&'scope T>
+    where
+        T: Copy,
+    {
+        let head = unsafe { self.head.load(Acquire, pin).deref() };
+        unsafe { head.next.load(Acquire, pin).as_ref() }.map(|node| &*node.data)
+    }
+
+    /// Returns an iterator over the elements of the queue, from the front to the back. The
+    /// iterator is not a consistent snapshot: the elements are returned in order, and each of
+    /// them was in the queue at some point while we iterated. As with `peek`, we require
+    /// `T: Copy`.
+    ///
+    /// Note that no garbage is freed while `pin` is active, so long iterations hold back
+    /// reclamation for all threads.
+    pub fn iter<'scope>(&'scope self, pin: Pin<'scope>) -> Iter<'scope, T>
+    where
+        T: Copy,
+    {
+        Iter {
+            node: unsafe { self.head.load(Acquire, pin).deref() },
+            pin,
+        }
+    }
+
+    /// Returns an iterator which pops elements until the queue is empty.
+    pub fn drain<'scope>(&'scope self, pin: Pin<'scope>) -> Drain<'scope, T> {
+        Drain { queue: self, pin }
+    }
+}
+
+/// An iterator over the elements of a `Queue`. See `Queue::iter`.
+pub struct Iter<'scope, T: 'scope> {
+    /// The last node we visited. Its element is already returned, or it is the sentinel.
+    node: &'scope Node<T>,
+    pin: Pin<'scope>,
+}
+
+impl<'scope, T> Iterator for Iter<'scope, T> {
+    type Item = &'scope T;
+
+    fn next(&mut self) -> Option<&'scope T> {
+        match unsafe { self.node.next.load(Acquire, self.pin).as_ref() } {
+            Some(next) => {
+                self.node = next;
+                Some(&*next.data)
+            }
+            None => None,
+        }
+    }
+}
+
+/// An iterator which pops elements from a `Queue`. See `Queue::drain`.
+pub struct Drain<'scope, T: 'scope> {
+    queue: &'scope Queue<T>,
+    pin: Pin<'scope>,
+}
+
+impl<'scope, T> Iterator for Drain<'scope, T>
+where
+    T: 'static + ::std::fmt::Debug,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.queue.pop(self.pin)
+    }
+}
+
+impl<T> ::std::iter::FromIterator<T> for Queue<T>
+where
+    T: 'static + ::std::fmt::Debug,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut q = Queue::new();
+        q.extend(iter);
+        q
+    }
+}
+
+impl<T> Extend<T> for Queue<T>
+where
+    T: 'static + ::std::fmt::Debug,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        // We have `&mut self`, so no other thread can use the queue.
+        let pin = Pin::fake();
+        for t in iter {
+            self.push(t, pin);
+        }
+    }
 }
 
 
@@ -288,6 +517,222 @@ mod test {
         });
     }
 
+    #[test]
+    fn st_queue_peek() {
+        pin(|pin| {
+            let q: Queue<u32> = Queue::new();
+            assert_eq!(q.peek(pin), None);
+            q.push(1, pin);
+            q.push(2, pin);
+            assert_eq!(q.peek(pin), Some(&1));
+            assert_eq!(q.pop(pin), Some(1));
+            assert_eq!(q.peek(pin), Some(&2));
+        });
+    }
+
+    #[test]
+    fn st_queue_iter() {
+        pin(|pin| {
+            let q: Queue<u32> = Queue::new();
+            for i in 0..10 {
+                q.push(i, pin);
+            }
+            assert_eq!(q.iter(pin).cloned().collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+            assert_eq!(q.len(pin), 10);
+        });
+    }
+
+    #[test]
+    fn st_queue_drain() {
+        pin(|pin| {
+            let q: Queue<u32> = Queue::new();
+            for i in 0..100 {
+                q.push(i, pin);
+            }
+            assert_eq!(q.drain(pin).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+            assert!(q.is_empty(pin));
+        });
+    }
+
+    #[test]
+    fn from_iter_extend() {
+        let mut q: Queue<u32> = (0..10).collect();
+        q.extend(10..20);
+        pin(|pin| for i in 0..20 {
+            assert_eq!(q.pop(pin), Some(i));
+        });
+    }
+
+    #[test]
+    fn st_queue_batch() {
+        pin(|pin| {
+            let q: Queue<u32> = Queue::new();
+            q.push_batch(0..100, pin);
+            q.push_batch(None, pin);
+            assert_eq!(q.pop_batch(30, pin), (0..30).collect::<Vec<_>>());
+            assert_eq!(q.pop(pin), Some(30));
+            assert_eq!(q.pop_batch(1000, pin), (31..100).collect::<Vec<_>>());
+            assert!(q.pop_batch(5, pin).is_empty());
+            assert!(q.is_empty(pin));
+        });
+    }
+
+    #[test]
+    fn batch_stress_test() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 16;
+        const BATCH: usize = 16;
+
+        let q: Arc<Queue<usize>> = Arc::new(Queue::new());
+        let popped = Arc::new(AtomicUsize::new(0));
+        let markers = Arc::new(
+            (0..N_THREADS * N)
+                .map(|_| AtomicBool::new(false))
+                .collect::<Vec<_>>(),
+        );
+        let barrier = Arc::new(Barrier::new(2 * N_THREADS));
+
+        let pushers = (0..N_THREADS).map(|thread_id| {
+            let q = q.clone();
+            let barrier = barrier.clone();
+            spawn(move || {
+                for i in 0..N / BATCH {
+                    let start = thread_id * N + i * BATCH;
+                    pin(|pin| q.push_batch(start..start + BATCH, pin));
+                }
+                barrier.wait();
+            })
+        });
+        let poppers = (0..N_THREADS).map(|_| {
+            let q = q.clone();
+            let popped = popped.clone();
+            let markers = markers.clone();
+            let barrier = barrier.clone();
+            spawn(move || {
+                while popped.load(Ordering::SeqCst) < N_THREADS * N {
+                    let v = pin(|pin| q.pop_batch(BATCH / 2, pin));
+                    popped.fetch_add(v.len(), Ordering::SeqCst);
+                    for i in v {
+                        assert!(!markers[i].swap(true, Ordering::SeqCst));
+                    }
+                }
+                barrier.wait();
+            })
+        });
+        let threads = pushers.chain(poppers).collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        assert!(markers.iter().all(|m| m.load(Ordering::SeqCst)));
+        assert!(pin(|pin| q.is_empty(pin)));
+    }
+
+    #[test]
+    fn len_counter() {
+        pin(|pin| {
+            let q: Queue<u32> = Queue::with_counter(4);
+            assert_eq!(q.len(pin), 0);
+            for i in 0..100 {
+                q.push(i, pin);
+            }
+            q.push_batch(0..10, pin);
+            assert_eq!(q.pop_batch(30, pin).len(), 30);
+            assert_eq!(q.pop(pin), Some(30));
+            assert_eq!(q.pop_if(|_| true, pin), Some(31));
+            assert_eq!(q.len(pin), 78);
+            assert_eq!(q.len_exact(pin), 78);
+        });
+    }
+
+    #[test]
+    fn len_counter_concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 16;
+
+        let q: Arc<Queue<usize>> = Arc::new(Queue::with_counter(N_THREADS));
+        let barrier = Arc::new(Barrier::new(N_THREADS));
+        let threads = (0..N_THREADS)
+            .map(|_| {
+                let q = q.clone();
+                let barrier = barrier.clone();
+                spawn(move || {
+                    for i in 0..N {
+                        pin(|pin| q.push(i, pin));
+                        if i % 2 == 0 {
+                            pin(|pin| q.pop(pin));
+                        }
+                    }
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        pin(|pin| {
+            assert_eq!(q.len(pin), N_THREADS * N / 2);
+            assert_eq!(q.len_exact(pin), N_THREADS * N / 2);
+        });
+    }
+
+    #[test]
+    fn iter_concurrent_push() {
+        const N: usize = 1024 * 64;
+
+        let q: Arc<Queue<usize>> = Arc::new(Queue::new());
+        let done = Arc::new(AtomicBool::new(false));
+        // A thread frees its local garbage when it exits, so the threads may not exit while we
+        // are iterating.
+        let barrier = Arc::new(Barrier::new(3));
+
+        let pusher = {
+            let q = q.clone();
+            let barrier = barrier.clone();
+            spawn(move || {
+                for i in 0..N {
+                    pin(|pin| q.push(i, pin));
+                }
+                barrier.wait();
+            })
+        };
+        let popper = {
+            let q = q.clone();
+            let done = done.clone();
+            let barrier = barrier.clone();
+            spawn(move || {
+                let mut popped = 0;
+                while popped < N {
+                    if pin(|pin| q.pop(pin)).is_some() {
+                        popped += 1;
+                    }
+                }
+                done.store(true, Ordering::SeqCst);
+                barrier.wait();
+            })
+        };
+
+        // Elements are pushed in order, so whatever we see must be increasing.
+        while !done.load(Ordering::SeqCst) {
+            pin(|pin| {
+                let front = q.peek(pin).cloned();
+                let mut last = None;
+                for &i in q.iter(pin) {
+                    assert!(i < N);
+                    assert!(last < Some(i));
+                    last = Some(i);
+                }
+                if let (Some(front), Some(last)) = (front, last) {
+                    assert!(front <= last);
+                }
+            });
+        }
+        barrier.wait();
+        assert!(pusher.join().is_ok());
+        assert!(popper.join().is_ok());
+        assert!(pin(|pin| q.is_empty(pin)));
+    }
+
     #[derive(Debug)]
     struct NoDrop;
     impl Drop for NoDrop {
@@ -366,7 +811,7 @@ mod test {
 
     use std::thread::spawn;
     use std::sync::atomic::AtomicBool;
-    use std::sync::Arc;
+    use std::sync::{Arc, Barrier};
 
     #[test]
     fn is_unique_receiver() {
@@ -0,0 +1,289 @@
+/// A segmented MPMC queue, in the style of crossbeam's `SegQueue`.
+///
+/// The queue is a linked list of segments, where each segment holds `SEG_SIZE` elements.
+/// Pushing and popping mostly bumps an index in the tail or head segment, and a segment is only
+/// retired when all of its elements are popped. This way we retire one segment for every
+/// `SEG_SIZE` elements, instead of one node per element like `queue::Queue` does.
+
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{Relaxed, Acquire, Release, SeqCst};
+
+use super::Pin;
+use super::atomic::{Owned, Atomic};
+
+/// The number of elements in one segment.
+const SEG_SIZE: usize = 32;
+
+struct Segment<T> {
+    /// The index of the next element to pop.
+    low: AtomicUsize,
+    data: [UnsafeCell<ManuallyDrop<T>>; SEG_SIZE],
+    /// `ready[i]` is set when `data[i]` is written.
+    ready: [AtomicBool; SEG_SIZE],
+    /// The index of the next element to push. This may grow past `SEG_SIZE`, when threads race
+    /// for the last slot.
+    high: AtomicUsize,
+    next: Atomic<Segment<T>>,
+}
+
+impl<T> Segment<T> {
+    fn new() -> Self {
+        Self {
+            low: AtomicUsize::new(0),
+            data: unsafe { ::std::mem::uninitialized() },
+            ready: Default::default(),
+            high: AtomicUsize::new(0),
+            next: Atomic::null(),
+        }
+    }
+}
+
+pub struct SegQueue<T> {
+    head: Atomic<Segment<T>>,
+    tail: Atomic<Segment<T>>,
+}
+
+unsafe impl<T: Send> Send for SegQueue<T> {}
+unsafe impl<T: Send> Sync for SegQueue<T> {}
+
+impl<T> SegQueue<T>
+where
+    T: 'static,
+{
+    pub fn new() -> Self {
+        let pin = Pin::fake();
+        let seg = Owned::new(Segment::new()).into_ptr(pin);
+        Self {
+            head: Atomic::from_ptr(seg),
+            tail: Atomic::from_ptr(seg),
+        }
+    }
+
+    pub fn push<'scope>(&self, t: T, pin: Pin<'scope>) {
+        loop {
+            let tail_ptr = self.tail.load(Acquire, pin);
+            let tail = unsafe { tail_ptr.deref() };
+            if tail.high.load(Relaxed) >= SEG_SIZE {
+                // The segment is full, and the thread which got the last slot will soon append
+                // a new one.
+                continue;
+            }
+            let i = tail.high.fetch_add(1, Relaxed);
+            if i < SEG_SIZE {
+                unsafe {
+                    *tail.data[i].get() = ManuallyDrop::new(t);
+                }
+                tail.ready[i].store(true, Release);
+                if i + 1 == SEG_SIZE {
+                    let new = Owned::new(Segment::new()).into_ptr(pin);
+                    tail.next.store(new, Release);
+                    // A popper may already have moved `self.tail` past us, so we must not store
+                    // unconditionally, or `self.tail` could move back to a retired segment.
+                    let _ = self.tail.compare_and_set(tail_ptr, new, SeqCst, pin);
+                }
+                return;
+            }
+        }
+    }
+
+    pub fn pop<'scope>(&self, pin: Pin<'scope>) -> Option<T> {
+        loop {
+            let head_ptr = self.head.load(Acquire, pin);
+            let head = unsafe { head_ptr.deref() };
+            loop {
+                let low = head.low.load(Relaxed);
+                if low >= cmp::min(head.high.load(Relaxed), SEG_SIZE) {
+                    break;
+                }
+                if head.low
+                    .compare_exchange_weak(low, low + 1, Relaxed, Relaxed)
+                    .is_err()
+                {
+                    continue;
+                }
+                // The pusher may not have written the element yet.
+                while !head.ready[low].load(Acquire) {}
+                let ret = unsafe {
+                    ManuallyDrop::into_inner(::std::ptr::read(head.data[low].get()))
+                };
+                if low + 1 == SEG_SIZE {
+                    // We popped the last element of the segment, so we are the one to retire it.
+                    let next = loop {
+                        let next = head.next.load(Acquire, pin);
+                        if !next.is_null() {
+                            break next;
+                        }
+                    };
+                    // The pusher that appended `next` may not have moved `self.tail` yet. Help it,
+                    // so that no thread can load `head` from `self.tail` after we retire it.
+                    let _ = self.tail.compare_and_set(head_ptr, next, SeqCst, pin);
+                    self.head.store(next, Release);
+                    unsafe {
+                        pin.add_garbage(head_ptr.into_owned());
+                    }
+                }
+                return Some(ret);
+            }
+            if head.next.load(Relaxed, pin).is_null() {
+                return None;
+            }
+        }
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty<'scope>(&self, pin: Pin<'scope>) -> bool {
+        let head = unsafe { self.head.load(Acquire, pin).deref() };
+        head.low.load(Relaxed) >= cmp::min(head.high.load(Relaxed), SEG_SIZE) &&
+            head.next.load(Relaxed, pin).is_null()
+    }
+}
+
+impl<T> Drop for SegQueue<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let pin = Pin::fake();
+            let mut ptr = self.head.load(Relaxed, pin);
+            while !ptr.is_null() {
+                let seg = ptr.into_owned();
+                let low = seg.low.load(Relaxed);
+                let high = cmp::min(seg.high.load(Relaxed), SEG_SIZE);
+                for i in low..high {
+                    ManuallyDrop::drop(&mut *(*seg).data[i].get());
+                }
+                ptr = seg.next.load(Relaxed, pin);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::pin;
+
+    use std::thread::spawn;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn st_queue_push_pop_many() {
+        let q = SegQueue::new();
+        pin(|pin| {
+            for i in 0..1000 {
+                q.push(i, pin);
+            }
+            for i in 0..1000 {
+                assert_eq!(q.pop(pin), Some(i));
+            }
+            assert_eq!(q.pop(pin), None);
+            assert!(q.is_empty(pin));
+        });
+    }
+
+    #[test]
+    fn do_drop() {
+        lazy_static! {
+            static ref COUNT: AtomicUsize = AtomicUsize::new(0);
+        }
+        struct MustDrop;
+        impl Drop for MustDrop {
+            fn drop(&mut self) {
+                COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        {
+            let q = SegQueue::new();
+            pin(|pin| {
+                for _ in 0..100 {
+                    q.push(MustDrop, pin);
+                }
+                for _ in 0..50 {
+                    q.pop(pin);
+                }
+            });
+        }
+        assert_eq!(COUNT.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn stress_test() {
+        const N_THREADS: usize = 16;
+        const N: usize = 1024 * 32;
+
+        let source = Arc::new(SegQueue::new());
+        let sink = Arc::new(SegQueue::new());
+
+        pin(|pin| for n in 0..N {
+            source.push(n, pin);
+        });
+
+        let threads = (0..N_THREADS)
+            .map(|_thread_id| {
+                let source = source.clone();
+                let sink = sink.clone();
+                spawn(move || while let Some(i) = pin(|pin| source.pop(pin)) {
+                    pin(|pin| sink.push(i, pin));
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        let mut v = Vec::with_capacity(N);
+        while let Some(i) = pin(|pin| sink.pop(pin)) {
+            v.push(i);
+        }
+        v.sort();
+        for (i, n) in v.into_iter().enumerate() {
+            assert_eq!(i, n);
+        }
+    }
+
+    #[test]
+    fn mpmc_cross_segments() {
+        const N_THREADS: usize = 8;
+        // Every pusher fills, and every popper empties, several segments.
+        const N: usize = SEG_SIZE * 64;
+
+        let q = Arc::new(SegQueue::new());
+        let pushers = (0..N_THREADS)
+            .map(|thread_id| {
+                let q = q.clone();
+                spawn(move || for n in 0..N {
+                    pin(|pin| q.push(thread_id * N + n, pin));
+                })
+            })
+            .collect::<Vec<_>>();
+        let poppers = (0..N_THREADS)
+            .map(|_thread_id| {
+                let q = q.clone();
+                spawn(move || {
+                    let mut v = Vec::with_capacity(N);
+                    while v.len() < N {
+                        if let Some(i) = pin(|pin| q.pop(pin)) {
+                            v.push(i);
+                        }
+                    }
+                    v
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in pushers.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        let mut v = Vec::with_capacity(N * N_THREADS);
+        for t in poppers.into_iter() {
+            v.extend(t.join().unwrap());
+        }
+        pin(|pin| assert!(q.is_empty(pin)));
+        v.sort();
+        for (i, n) in v.into_iter().enumerate() {
+            assert_eq!(i, n);
+        }
+    }
+}
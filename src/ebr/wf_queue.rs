@@ -0,0 +1,425 @@
+/// A wait-free MPMC queue, as described by Kogan and Petrank in "Wait-Free Queues With Multiple
+/// Enqueuers and Dequeuers".
+///
+/// Each thread announces its operation in `state` as an `OpDesc`, together with a phase number
+/// which is larger than the phase of every operation announced before it. Before a thread does
+/// its own operation it helps all pending operations with a phase no larger than its own, so an
+/// operation finishes after at most `num_threads` other operations.
+///
+/// The descriptors are immutable, and a thread changes the state of an operation by CASing a new
+/// descriptor into `state`. The thread whose CAS succeeds retires the old descriptor, so the
+/// descriptors are retired just as often as the nodes are.
+///
+/// Each thread passes its `tid` to `push` and `pop`. The `tid`s must be in `0..num_threads`, and
+/// no two threads may use the same `tid` at the same time.
+
+use std::mem::ManuallyDrop;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+
+use super::Pin;
+use super::atomic::{Owned, Atomic, Ptr};
+
+/// The `deq_tid` of a node no dequeuer has claimed.
+const NO_TID: usize = ::std::usize::MAX;
+
+struct Node<T> {
+    /// The value is read by the dequeuer, so we never drop it with the node.
+    data: ManuallyDrop<T>,
+    next: Atomic<Node<T>>,
+    /// The thread which enqueued the node.
+    enq_tid: usize,
+    /// The thread whose dequeue removes the node from the head, or `NO_TID`.
+    deq_tid: AtomicUsize,
+}
+
+impl<T> Node<T> {
+    fn new(data: T, enq_tid: usize) -> Self {
+        Self {
+            data: ManuallyDrop::new(data),
+            next: Atomic::null(),
+            enq_tid,
+            deq_tid: AtomicUsize::new(NO_TID),
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            data: unsafe { ::std::mem::uninitialized() },
+            next: Atomic::null(),
+            enq_tid: NO_TID,
+            deq_tid: AtomicUsize::new(NO_TID),
+        }
+    }
+}
+
+struct OpDesc<T> {
+    phase: usize,
+    pending: bool,
+    enqueue: bool,
+    /// For an enqueue this is the node to insert. For a dequeue this is the sentinel node at the
+    /// time the dequeue took effect, so the value is in the node after it. A finished dequeue
+    /// with a null node found the queue empty.
+    node: Atomic<Node<T>>,
+}
+
+impl<T> OpDesc<T> {
+    fn new(phase: usize, pending: bool, enqueue: bool, node: Ptr<Node<T>>) -> Self {
+        Self {
+            phase,
+            pending,
+            enqueue,
+            node: Atomic::from_ptr(node),
+        }
+    }
+
+    /// Returns `true` if this is a pending operation of the given kind, which should be helped
+    /// by an operation with phase `phase`.
+    fn is_pending(&self, phase: usize, enqueue: bool) -> bool {
+        self.pending && self.enqueue == enqueue && self.phase <= phase
+    }
+}
+
+pub struct WaitFreeQueue<T> {
+    head: Atomic<Node<T>>,
+    tail: Atomic<Node<T>>,
+    state: Vec<Atomic<OpDesc<T>>>,
+}
+
+unsafe impl<T: Send> Send for WaitFreeQueue<T> {}
+unsafe impl<T: Send> Sync for WaitFreeQueue<T> {}
+
+impl<T> WaitFreeQueue<T>
+where
+    T: 'static,
+{
+    /// Make a new queue, which can be used by `num_threads` threads at the same time.
+    pub fn new(num_threads: usize) -> Self {
+        let pin = Pin::fake();
+        let sentinel = Owned::new(Node::empty()).into_ptr(pin);
+        Self {
+            head: Atomic::from_ptr(sentinel),
+            tail: Atomic::from_ptr(sentinel),
+            state: (0..num_threads)
+                .map(|_| Atomic::new(OpDesc::new(0, false, true, Ptr::null())))
+                .collect(),
+        }
+    }
+
+    pub fn push<'scope>(&self, t: T, tid: usize, pin: Pin<'scope>) {
+        let phase = self.max_phase(pin) + 1;
+        let node = Owned::new(Node::new(t, tid)).into_ptr(pin);
+        self.announce(tid, OpDesc::new(phase, true, true, node), pin);
+        self.help(phase, pin);
+        self.help_finish_enq(pin);
+    }
+
+    pub fn pop<'scope>(&self, tid: usize, pin: Pin<'scope>) -> Option<T> {
+        let phase = self.max_phase(pin) + 1;
+        self.announce(tid, OpDesc::new(phase, true, false, Ptr::null()), pin);
+        self.help(phase, pin);
+        self.help_finish_deq(pin);
+        let desc = unsafe { self.state[tid].load(SeqCst, pin).deref() };
+        let node = desc.node.load(SeqCst, pin);
+        if node.is_null() {
+            return None;
+        }
+        // `node` was the sentinel when our dequeue took effect, so our value is in the node after
+        // it. No other thread reads the value, and since we are pinned, the node is not freed.
+        unsafe {
+            let next = node.deref().next.load(SeqCst, pin).deref();
+            Some(ManuallyDrop::into_inner(::std::ptr::read(&next.data)))
+        }
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty<'scope>(&self, pin: Pin<'scope>) -> bool {
+        let head = unsafe { self.head.load(SeqCst, pin).deref() };
+        head.next.load(SeqCst, pin).is_null()
+    }
+
+    /// Replace the descriptor of `tid` with `desc`. Only `tid` itself may do this, and only when
+    /// its previous operation is finished.
+    fn announce<'scope>(&self, tid: usize, desc: OpDesc<T>, pin: Pin<'scope>) {
+        let new = Owned::new(desc).into_ptr(pin);
+        let old = self.state[tid].swap(new, SeqCst, pin);
+        unsafe {
+            pin.add_garbage(old.into_owned());
+        }
+    }
+
+    /// CAS the descriptor of `tid` from `cur` to `new`, and retire `cur` if we succeed.
+    fn replace_desc<'scope>(
+        &self,
+        tid: usize,
+        cur: Ptr<'scope, OpDesc<T>>,
+        new: OpDesc<T>,
+        pin: Pin<'scope>,
+    ) -> bool {
+        match self.state[tid].compare_and_set_owned(cur, Owned::new(new), SeqCst, pin) {
+            Ok(_) => {
+                unsafe {
+                    pin.add_garbage(cur.into_owned());
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn max_phase<'scope>(&self, pin: Pin<'scope>) -> usize {
+        self.state
+            .iter()
+            .map(|s| unsafe { s.load(SeqCst, pin).deref().phase })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn is_still_pending<'scope>(
+        &self,
+        tid: usize,
+        phase: usize,
+        enqueue: bool,
+        pin: Pin<'scope>,
+    ) -> bool {
+        let desc = unsafe { self.state[tid].load(SeqCst, pin).deref() };
+        desc.is_pending(phase, enqueue)
+    }
+
+    /// Help all pending operations with a phase no larger than `phase`.
+    fn help<'scope>(&self, phase: usize, pin: Pin<'scope>) {
+        for tid in 0..self.state.len() {
+            let desc = unsafe { self.state[tid].load(SeqCst, pin).deref() };
+            if desc.pending && desc.phase <= phase {
+                if desc.enqueue {
+                    self.help_enq(tid, phase, pin);
+                } else {
+                    self.help_deq(tid, phase, pin);
+                }
+            }
+        }
+    }
+
+    fn help_enq<'scope>(&self, tid: usize, phase: usize, pin: Pin<'scope>) {
+        while self.is_still_pending(tid, phase, true, pin) {
+            let last = self.tail.load(SeqCst, pin);
+            let next = unsafe { last.deref() }.next.load(SeqCst, pin);
+            if last != self.tail.load(SeqCst, pin) {
+                continue;
+            }
+            if !next.is_null() {
+                // Some enqueue is halfway done. Finish it, so that we can link our node.
+                self.help_finish_enq(pin);
+                continue;
+            }
+            // We read the descriptor again, so that we link the node of the operation we
+            // checked, and not the node of some later operation of `tid`.
+            let desc = unsafe { self.state[tid].load(SeqCst, pin).deref() };
+            if desc.is_pending(phase, true) {
+                let node = desc.node.load(SeqCst, pin);
+                let last = unsafe { last.deref() };
+                if last.next
+                    .compare_and_set(Ptr::null(), node, SeqCst, pin)
+                    .is_ok()
+                {
+                    self.help_finish_enq(pin);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Finish the enqueue whose node is linked after `tail`: mark its descriptor as done, and
+    /// move the tail.
+    fn help_finish_enq<'scope>(&self, pin: Pin<'scope>) {
+        let last = self.tail.load(SeqCst, pin);
+        let next = unsafe { last.deref() }.next.load(SeqCst, pin);
+        let tid = match unsafe { next.as_ref() } {
+            Some(node) => node.enq_tid,
+            None => return,
+        };
+        let cur = self.state[tid].load(SeqCst, pin);
+        let desc = unsafe { cur.deref() };
+        if last == self.tail.load(SeqCst, pin) && desc.node.load(SeqCst, pin) == next {
+            let new = OpDesc::new(desc.phase, false, true, next);
+            self.replace_desc(tid, cur, new, pin);
+        }
+        let _ = self.tail.compare_and_set(last, next, SeqCst, pin);
+    }
+
+    fn help_deq<'scope>(&self, tid: usize, phase: usize, pin: Pin<'scope>) {
+        while self.is_still_pending(tid, phase, false, pin) {
+            let first = self.head.load(SeqCst, pin);
+            let last = self.tail.load(SeqCst, pin);
+            let next = unsafe { first.deref() }.next.load(SeqCst, pin);
+            if first != self.head.load(SeqCst, pin) {
+                continue;
+            }
+            if first == last {
+                if !next.is_null() {
+                    // Some enqueue is halfway done. Finish it, so that we can move past it.
+                    self.help_finish_enq(pin);
+                    continue;
+                }
+                // The queue is empty, so the dequeue finishes without a node.
+                let cur = self.state[tid].load(SeqCst, pin);
+                let desc = unsafe { cur.deref() };
+                if last == self.tail.load(SeqCst, pin) && desc.is_pending(phase, false) {
+                    let new = OpDesc::new(desc.phase, false, false, Ptr::null());
+                    self.replace_desc(tid, cur, new, pin);
+                }
+            } else {
+                let cur = self.state[tid].load(SeqCst, pin);
+                let desc = unsafe { cur.deref() };
+                if !desc.is_pending(phase, false) {
+                    break;
+                }
+                if first == self.head.load(SeqCst, pin) && desc.node.load(SeqCst, pin) != first {
+                    let new = OpDesc::new(desc.phase, true, false, first);
+                    if !self.replace_desc(tid, cur, new, pin) {
+                        continue;
+                    }
+                }
+                let first = unsafe { first.deref() };
+                let _ = first
+                    .deq_tid
+                    .compare_exchange(NO_TID, tid, SeqCst, Relaxed);
+                self.help_finish_deq(pin);
+            }
+        }
+    }
+
+    /// Finish the dequeue which has claimed the sentinel: mark its descriptor as done, and move
+    /// the head.
+    fn help_finish_deq<'scope>(&self, pin: Pin<'scope>) {
+        let first = self.head.load(SeqCst, pin);
+        let f = unsafe { first.deref() };
+        let next = f.next.load(SeqCst, pin);
+        let tid = f.deq_tid.load(SeqCst);
+        if tid == NO_TID {
+            return;
+        }
+        let cur = self.state[tid].load(SeqCst, pin);
+        let desc = unsafe { cur.deref() };
+        if first == self.head.load(SeqCst, pin) && !next.is_null() {
+            let new = OpDesc::new(desc.phase, false, false, desc.node.load(SeqCst, pin));
+            self.replace_desc(tid, cur, new, pin);
+            if self.head.compare_and_set(first, next, SeqCst, pin).is_ok() {
+                unsafe {
+                    pin.add_garbage(first.into_owned());
+                }
+            }
+        }
+    }
+}
+
+impl<T> Drop for WaitFreeQueue<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let pin = Pin::fake();
+            // The value of the sentinel is either popped or uninitialized.
+            let sentinel = self.head.load(Relaxed, pin).into_owned();
+            let mut ptr = sentinel.next.load(Relaxed, pin);
+            ::std::mem::drop(sentinel);
+            while !ptr.is_null() {
+                let mut node = ptr.into_owned();
+                ManuallyDrop::drop(&mut (*node).data);
+                ptr = node.next.load(Relaxed, pin);
+            }
+            for desc in self.state.iter() {
+                ::std::mem::drop(desc.load(Relaxed, pin).into_owned());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::pin;
+
+    use std::thread::spawn;
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn st_queue_push_pop_many() {
+        let q = WaitFreeQueue::new(1);
+        pin(|pin| {
+            for i in 0..1000 {
+                q.push(i, 0, pin);
+            }
+            for i in 0..1000 {
+                assert_eq!(q.pop(0, pin), Some(i));
+            }
+            assert_eq!(q.pop(0, pin), None);
+            assert!(q.is_empty(pin));
+        });
+    }
+
+    #[test]
+    fn do_drop() {
+        lazy_static! {
+            static ref COUNT: AtomicUsize = AtomicUsize::new(0);
+        }
+        struct MustDrop;
+        impl Drop for MustDrop {
+            fn drop(&mut self) {
+                COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        {
+            let q = WaitFreeQueue::new(1);
+            pin(|pin| {
+                for _ in 0..100 {
+                    q.push(MustDrop, 0, pin);
+                }
+                for _ in 0..50 {
+                    q.pop(0, pin);
+                }
+            });
+        }
+        assert_eq!(COUNT.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn stress_test() {
+        const N_THREADS: usize = 8;
+        const N: usize = 1024 * 32;
+
+        let source = Arc::new(WaitFreeQueue::new(N_THREADS));
+        let sink = Arc::new(WaitFreeQueue::new(N_THREADS));
+        let barrier = Arc::new(Barrier::new(N_THREADS));
+
+        pin(|pin| for n in 0..N {
+            source.push(n, 0, pin);
+        });
+
+        let threads = (0..N_THREADS)
+            .map(|tid| {
+                let source = source.clone();
+                let sink = sink.clone();
+                let barrier = barrier.clone();
+                spawn(move || {
+                    while let Some(i) = pin(|pin| source.pop(tid, pin)) {
+                        pin(|pin| sink.push(i, tid, pin));
+                    }
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        let mut v = Vec::with_capacity(N);
+        while let Some(i) = pin(|pin| sink.pop(0, pin)) {
+            v.push(i);
+        }
+        v.sort();
+        assert_eq!(v.len(), N);
+        for (i, n) in v.into_iter().enumerate() {
+            assert_eq!(i, n);
+        }
+    }
+}
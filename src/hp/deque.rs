@@ -0,0 +1,370 @@
+/// A Chase-Lev work-stealing deque.
+///
+/// The owning thread pushes and pops at the bottom of the deque through a `Worker`, while other
+/// threads steal from the top through a `Stealer`. The elements are stored in a circular buffer,
+/// which is replaced by one twice as large when it is full. The old buffer may still be read by
+/// stealers, so it is retired with `HazardPtr::free`, and stealers register the buffer they read
+/// from as hazardous.
+///
+/// The memory orderings follow "Correct and Efficient Work-Stealing for Weak Memory Models" by
+/// Lê et al.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::mem::{ManuallyDrop, drop};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicIsize, fence};
+use std::sync::atomic::Ordering::{Relaxed, Acquire, Release, SeqCst};
+
+use super::atomic::{Owned, Atomic};
+
+/// The capacity of the buffer a new deque starts out with.
+const MIN_CAP: usize = 16;
+
+/// A circular buffer of `T`s. Dropping the buffer frees the memory, but does not drop any
+/// elements; this is the responsibility of the deque.
+#[derive(Debug)]
+struct Buffer<T> {
+    ptr: *mut T,
+    cap: usize,
+}
+
+impl<T> Buffer<T> {
+    /// Make a new buffer with room for `cap` elements. `cap` must be a power of two.
+    fn new(cap: usize) -> Self {
+        debug_assert_eq!(cap, cap.next_power_of_two());
+        let mut v = Vec::with_capacity(cap);
+        let ptr = v.as_mut_ptr();
+        ::std::mem::forget(v);
+        Buffer { ptr, cap }
+    }
+
+    /// Return a pointer to the slot for index `i`.
+    unsafe fn at(&self, i: isize) -> *mut T {
+        self.ptr.offset(i & (self.cap - 1) as isize)
+    }
+
+    unsafe fn write(&self, i: isize, t: T) {
+        ::std::ptr::write(self.at(i), t)
+    }
+
+    /// Read the element at index `i`. The element is not removed from the buffer, so the caller
+    /// must make sure that only one copy of it is ever used.
+    unsafe fn read(&self, i: isize) -> ManuallyDrop<T> {
+        ManuallyDrop::new(::std::ptr::read(self.at(i)))
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Vec::from_raw_parts(self.ptr, 0, self.cap));
+        }
+    }
+}
+
+/// The data shared between the `Worker` and its `Stealer`s.
+#[derive(Debug)]
+struct Inner<T> {
+    bottom: AtomicIsize,
+    top: AtomicIsize,
+    buffer: Atomic<Buffer<T>>,
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        // We are the last reference to the deque, so no other thread can be reading from it.
+        unsafe {
+            let buffer = self.buffer.load(Relaxed).into_owned();
+            let b = self.bottom.load(Relaxed);
+            let mut t = self.top.load(Relaxed);
+            while t < b {
+                ManuallyDrop::into_inner(buffer.read(t));
+                t += 1;
+            }
+        }
+    }
+}
+
+/// The result of a `steal` operation.
+#[derive(Debug, PartialEq)]
+pub enum Steal<T> {
+    /// The deque was empty.
+    Empty,
+    /// We stole an element.
+    Data(T),
+    /// We lost a race with another thread, and should try again.
+    Retry,
+}
+
+/// The owning end of a deque. Only one thread can push and pop at a time.
+#[derive(Debug)]
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    _marker: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T: Send> Send for Worker<T> {}
+
+/// The stealing end of a deque. This can be cloned and shared among any number of threads.
+#[derive(Debug)]
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Stealer<T> {}
+unsafe impl<T: Send> Sync for Stealer<T> {}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Stealer { inner: self.inner.clone() }
+    }
+}
+
+/// Make a new deque, and return its `Worker` and `Stealer`.
+pub fn new<T>() -> (Worker<T>, Stealer<T>)
+where
+    T: 'static,
+{
+    let inner = Arc::new(Inner {
+        bottom: AtomicIsize::new(0),
+        top: AtomicIsize::new(0),
+        buffer: Atomic::from_owned(Owned::new(Buffer::new(MIN_CAP))),
+    });
+    let worker = Worker {
+        inner: inner.clone(),
+        _marker: PhantomData,
+    };
+    (worker, Stealer { inner })
+}
+
+impl<T> Worker<T>
+where
+    T: 'static,
+{
+    /// Push `t` to the bottom of the deque.
+    pub fn push(&self, t: T) {
+        let inner = &*self.inner;
+        let b = inner.bottom.load(Relaxed);
+        let top = inner.top.load(Acquire);
+        // Only the owner replaces the buffer, so we do not need to register it as hazardous.
+        let mut buffer = unsafe { inner.buffer.load(Relaxed).deref() };
+        if b - top >= buffer.cap as isize {
+            self.resize(b, top, buffer.cap * 2);
+            buffer = unsafe { inner.buffer.load(Relaxed).deref() };
+        }
+        unsafe {
+            buffer.write(b, t);
+        }
+        fence(Release);
+        inner.bottom.store(b + 1, Relaxed);
+    }
+
+    /// Pop an element from the bottom of the deque.
+    pub fn pop(&self) -> Option<T> {
+        let inner = &*self.inner;
+        let b = inner.bottom.load(Relaxed) - 1;
+        let buffer = unsafe { inner.buffer.load(Relaxed).deref() };
+        inner.bottom.store(b, Relaxed);
+        fence(SeqCst);
+        let t = inner.top.load(Relaxed);
+        if t > b {
+            // The deque was empty.
+            inner.bottom.store(b + 1, Relaxed);
+            return None;
+        }
+        let data = unsafe { buffer.read(b) };
+        if t == b {
+            // This is the last element, so we race with the stealers for it.
+            let won = inner.top.compare_exchange(t, t + 1, SeqCst, Relaxed).is_ok();
+            inner.bottom.store(b + 1, Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        Some(ManuallyDrop::into_inner(data))
+    }
+
+    /// Returns `true` if the deque is empty.
+    pub fn is_empty(&self) -> bool {
+        let b = self.inner.bottom.load(Relaxed);
+        let t = self.inner.top.load(Relaxed);
+        b <= t
+    }
+
+    /// Replace the buffer with a new one with room for `cap` elements, and retire the old one.
+    fn resize(&self, b: isize, t: isize, cap: usize) {
+        let inner = &*self.inner;
+        let old = inner.buffer.load(Relaxed);
+        let new = Buffer::new(cap);
+        unsafe {
+            let old_buffer = old.deref();
+            let mut i = t;
+            while i < b {
+                ::std::ptr::copy_nonoverlapping(old_buffer.at(i), new.at(i), 1);
+                i += 1;
+            }
+        }
+        inner.buffer.store(Owned::new(new).into_ptr(), Release);
+        // Stealers may still be reading from the old buffer, so we cannot free it right away.
+        // Note that the elements are moved, so dropping the buffer does not drop them.
+        unsafe {
+            old.hazard().free();
+        }
+    }
+}
+
+impl<T> Stealer<T>
+where
+    T: 'static,
+{
+    /// Try to steal an element from the top of the deque.
+    pub fn steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+        let t = inner.top.load(Acquire);
+        fence(SeqCst);
+        let b = inner.bottom.load(Acquire);
+        if t >= b {
+            return Steal::Empty;
+        }
+        let buffer_ptr = inner.buffer.load(Acquire);
+        let buffer_hp = buffer_ptr.hazard();
+        // validate:
+        {
+            if inner.buffer.load(Acquire) != buffer_ptr {
+                drop(buffer_hp);
+                return Steal::Retry;
+            }
+        }
+        let buffer = unsafe { buffer_ptr.deref() };
+        let data = unsafe { buffer.read(t) };
+        let res = inner.top.compare_exchange(t, t + 1, SeqCst, Relaxed);
+        drop(buffer_hp);
+        match res {
+            Ok(_) => Steal::Data(ManuallyDrop::into_inner(data)),
+            // Someone else took the element; `data` is theirs, so we must not drop it.
+            Err(_) => Steal::Retry,
+        }
+    }
+
+    /// Returns `true` if the deque is empty.
+    pub fn is_empty(&self) -> bool {
+        let t = self.inner.top.load(Acquire);
+        let b = self.inner.bottom.load(Acquire);
+        b <= t
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread::spawn;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn st_push_pop() {
+        let (w, _s) = new();
+        for i in 0..100 {
+            w.push(i);
+        }
+        for i in (0..100).rev() {
+            assert_eq!(w.pop(), Some(i));
+        }
+        assert_eq!(w.pop(), None);
+    }
+
+    #[test]
+    fn st_steal() {
+        let (w, s) = new();
+        for i in 0..100 {
+            w.push(i);
+        }
+        for i in 0..100 {
+            assert_eq!(s.steal(), Steal::Data(i));
+        }
+        assert_eq!(s.steal(), Steal::Empty);
+        assert!(w.is_empty());
+    }
+
+    #[test]
+    fn drop_elements() {
+        use std::sync::atomic::AtomicUsize;
+        lazy_static! {
+            static ref COUNT: AtomicUsize = AtomicUsize::new(0);
+        }
+        struct MustDrop;
+        impl Drop for MustDrop {
+            fn drop(&mut self) {
+                COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        {
+            let (w, _s) = new();
+            for _ in 0..100 {
+                w.push(MustDrop);
+            }
+        }
+        assert_eq!(COUNT.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn is_unique_receiver() {
+        const N_THREADS: usize = 8;
+        const ELEMS: usize = 1024 * 256;
+
+        let (w, s) = new::<usize>();
+        let markers = ::std::sync::Arc::new(
+            (0..ELEMS)
+                .map(|_| AtomicBool::new(false))
+                .collect::<Vec<_>>(),
+        );
+        let done = ::std::sync::Arc::new(AtomicBool::new(false));
+
+        let threads = (0..N_THREADS)
+            .map(|_| {
+                let s = s.clone();
+                let markers = markers.clone();
+                let done = done.clone();
+                spawn(move || loop {
+                    match s.steal() {
+                        Steal::Data(i) => {
+                            let ret = markers[i].swap(true, Ordering::SeqCst);
+                            assert_eq!(ret, false);
+                        }
+                        Steal::Retry => {}
+                        Steal::Empty => {
+                            if done.load(Ordering::SeqCst) {
+                                return;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Push everything, and pop some of it ourselves. The buffer is resized many times while
+        // the other threads are stealing.
+        for i in 0..ELEMS {
+            w.push(i);
+            if i % 3 == 0 {
+                if let Some(i) = w.pop() {
+                    let ret = markers[i].swap(true, Ordering::SeqCst);
+                    assert_eq!(ret, false);
+                }
+            }
+        }
+        while let Some(i) = w.pop() {
+            let ret = markers[i].swap(true, Ordering::SeqCst);
+            assert_eq!(ret, false);
+        }
+        done.store(true, Ordering::SeqCst);
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        for m in markers.iter() {
+            assert!(m.load(Ordering::SeqCst));
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use std::sync::atomic::Ordering::SeqCst;
 use std::mem::{drop, ManuallyDrop};
+use std::borrow::Borrow;
+use std::ops::Deref;
 
 use super::atomic::{Owned, Atomic, Ptr, HazardPtr};
 
@@ -169,6 +171,126 @@ where
             }
         }
     }
+
+    /// Return the first element for which `f` returns `true`. The node of the element is
+    /// registered as hazardous as long as the returned `Guard` lives, so it may be removed, but it
+    /// is not freed.
+    ///
+    /// Removing moves the element out of the node, and the thread which removed it may drop it
+    /// while we are reading it. That is only safe if `T` has no destructor, so we require
+    /// `T: Copy`.
+    pub fn find<F>(&self, mut f: F) -> Option<Guard<T>>
+    where
+        T: Copy,
+        F: FnMut(&T) -> bool,
+    {
+        let mut cursor = self.cursor();
+        loop {
+            let found = match cursor.get() {
+                Some(t) => f(t),
+                None => return None,
+            };
+            if found {
+                // The node is already protected by the cursor, so we do not need to validate.
+                return Some(Guard { hp: cursor.curr.hazard() });
+            }
+            cursor.next();
+        }
+    }
+
+    /// Return the first element which borrows as `key`. This is useful for key/value records,
+    /// where `T: Borrow<K>` extracts the key. As with `find`, we require `T: Copy`.
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<Guard<T>>
+    where
+        T: Copy + Borrow<Q>,
+        Q: PartialEq,
+    {
+        self.find(|t| t.borrow() == key)
+    }
+
+    /// Remove and return the first element for which `f` returns `true`.
+    pub fn remove_if<F>(&self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut cursor = self.cursor();
+        loop {
+            let found = match cursor.get() {
+                Some(t) => f(t),
+                None => return None,
+            };
+            if found {
+                if let Some(t) = cursor.remove_current() {
+                    return Some(t);
+                }
+                // Some other thread removed it first.
+            }
+            cursor.next();
+        }
+    }
+
+    /// Replace the first element for which `f` returns `Some(new)` with `new`, and return the old
+    /// element. The element is replaced by swinging the pointer to its node to a new node, so
+    /// concurrent readers see either the old or the new element, never a partial write.
+    pub fn update<F>(&self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> Option<T>,
+    {
+        let mut cursor = self.cursor();
+        loop {
+            let new = match cursor.get() {
+                Some(t) => f(t),
+                None => return None,
+            };
+            if let Some(new) = new {
+                if let Ok(old) = cursor.replace_current(new) {
+                    return Some(old);
+                }
+                // Some other thread removed it first.
+            }
+            cursor.next();
+        }
+    }
+
+    /// Remove all elements for which `f` returns `false`, in one pass over the list.
+    ///
+    /// If the cursor loses its position because of concurrent removals, we restart from the head,
+    /// so `f` may be called more than once for an element.
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut cursor = self.cursor();
+        loop {
+            let keep = match cursor.get() {
+                Some(t) => f(t),
+                None => return,
+            };
+            if keep || cursor.remove_current().is_none() {
+                cursor.next();
+            }
+        }
+    }
+
+    /// Return a cursor pointing to the first element of the list.
+    pub fn cursor(&self) -> Cursor<T> {
+        'outer: loop {
+            let head = self.head.load(SeqCst);
+            let head_hp = head.hazard();
+            {
+                if self.head.load(SeqCst) != head {
+                    continue 'outer;
+                }
+            }
+            return Cursor {
+                list: self,
+                prev: &self.head,
+                prev_hp: None,
+                curr: head,
+                curr_hp: head_hp,
+            };
+        }
+    }
 }
 
 impl<T> List<T>
@@ -438,6 +560,244 @@ impl<'a, T> Iterator for Iter<'a, T> {
     }
 }
 
+/// A reference to an element in a `List`. The node of the element is registered as hazardous as
+/// long as the guard lives. See `List::find`.
+pub struct Guard<T> {
+    hp: HazardPtr<Node<T>>,
+}
+
+impl<T> Deref for Guard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.hp.data
+    }
+}
+
+/// A cursor into a `List`.
+///
+/// The cursor points to an element in the list, and remembers the `next` pointer that points to
+/// it, so that we can insert and remove elements at the cursor without walking the list from the
+/// head again. Both the current node and the node before it are kept registered as hazardous.
+/// When the cursor is past the last element, `get` returns `None`.
+pub struct Cursor<'a, T: 'static> {
+    list: &'a List<T>,
+    prev: &'a Atomic<Node<T>>,
+    /// The hazard for the node owning `prev`, or `None` if `prev` is the head of the list.
+    prev_hp: Option<HazardPtr<Node<T>>>,
+    curr: Ptr<'a, Node<T>>,
+    curr_hp: HazardPtr<Node<T>>,
+}
+
+impl<'a, T> Cursor<'a, T>
+where
+    T: 'static,
+{
+    /// Move the cursor back to the first element of the list.
+    fn reset(&mut self) {
+        'outer: loop {
+            let head = self.list.head.load(SeqCst);
+            let head_hp = head.hazard();
+            {
+                if self.list.head.load(SeqCst) != head {
+                    continue 'outer;
+                }
+            }
+            self.prev = &self.list.head;
+            self.prev_hp = None;
+            self.curr = head;
+            self.curr_hp = head_hp;
+            return;
+        }
+    }
+
+    /// Return a reference to the current element, if any.
+    pub fn get(&self) -> Option<&T> {
+        unsafe { self.curr.as_ref() }.map(|node| &*node.data)
+    }
+
+    /// Move the cursor to the next element in the list. Returns `false` if we have walked past
+    /// the end of the list.
+    ///
+    /// If the current element is removed by another thread, we continue from its predecessor
+    /// instead. If that is also removed, we have lost our position, and restart from the head.
+    pub fn next(&mut self) -> bool {
+        'outer: loop {
+            let node = match unsafe { self.curr.as_ref() } {
+                Some(node) => node,
+                None => return false,
+            };
+            let next = node.next.load(SeqCst);
+            if next.tag() == 0 {
+                let next_hp = next.hazard();
+                {
+                    if node.next.load(SeqCst) != next {
+                        continue 'outer;
+                    }
+                }
+                self.prev = &node.next;
+                self.prev_hp = Some(::std::mem::replace(&mut self.curr_hp, next_hp));
+                self.curr = next;
+                return !next.is_null();
+            }
+            // `curr` is being removed. Once it is unlinked, `prev` points to its successor.
+            let fresh = self.prev.load(SeqCst);
+            if fresh.tag() != 0 {
+                self.reset();
+                return !self.curr.is_null();
+            }
+            if fresh != self.curr {
+                let fresh_hp = fresh.hazard();
+                {
+                    if self.prev.load(SeqCst) != fresh {
+                        continue 'outer;
+                    }
+                }
+                self.curr = fresh;
+                self.curr_hp = fresh_hp;
+                return !fresh.is_null();
+            }
+        }
+    }
+
+    /// Insert `data` right after the current element. The cursor is not moved, so the next call
+    /// to `next` moves to the inserted element.
+    ///
+    /// Returns `Err(data)` if the cursor is past the end of the list, or if the current element
+    /// was removed.
+    pub fn insert_after(&mut self, data: T) -> Result<(), T> {
+        let node = match unsafe { self.curr.as_ref() } {
+            Some(node) => node,
+            None => return Err(data),
+        };
+        let mut new = Owned::new(Node::new(data));
+        loop {
+            // We do not need a HP for `next`, since we never dereference it.
+            let next = node.next.load(SeqCst);
+            if next.tag() != 0 {
+                // `data` is `ManuallyDrop`, so dropping the node does not drop it.
+                let data = unsafe { ::std::ptr::read(&(*new).data) };
+                return Err(ManuallyDrop::into_inner(data));
+            }
+            new.next.store(next, SeqCst);
+            match node.next.compare_and_set_owned(next, new, SeqCst) {
+                Ok(_) => return Ok(()),
+                Err(e) => new = e.new,
+            }
+        }
+    }
+
+    /// Remove the current element from the list, and return it. The cursor is moved to the next
+    /// element.
+    ///
+    /// Returns `None` if the cursor is past the end of the list, or if some other thread removed
+    /// the element first.
+    pub fn remove_current(&mut self) -> Option<T> {
+        loop {
+            let node = match unsafe { self.curr.as_ref() } {
+                Some(node) => node,
+                None => return None,
+            };
+            // Mark the node, so that no other thread inserts after it, or removes its successor.
+            let next = node.next.load(SeqCst);
+            if next.tag() != 0 {
+                return None;
+            }
+            if node.next
+                .compare_and_set(next, next.with_tag(1), SeqCst)
+                .is_err()
+            {
+                continue;
+            }
+            // Now `next` cannot be removed before `curr` is, so we do not need to validate the HP.
+            let next_hp = next.hazard();
+            if self.prev.compare_and_set(self.curr, next, SeqCst).is_ok() {
+                unsafe {
+                    // Now `curr` is not reachable from the list.
+                    let data = ::std::ptr::read(&node.data);
+                    self.curr = next;
+                    ::std::mem::replace(&mut self.curr_hp, next_hp).free();
+                    return Some(ManuallyDrop::into_inner(data));
+                }
+            }
+            // Either a node was inserted in front of us, or the previous node is being removed.
+            // Unmark, and find our predecessor again.
+            drop(next_hp);
+            let _ = node.next.compare_and_set(next.with_tag(1), next, SeqCst);
+            if !self.relocate() {
+                return None;
+            }
+        }
+    }
+
+    /// Replace the current element with `data`, and return the old element. The cursor is moved
+    /// to the new element.
+    ///
+    /// Returns `Err(data)` if the cursor is past the end of the list, or if some other thread
+    /// removed the element first.
+    pub fn replace_current(&mut self, data: T) -> Result<T, T> {
+        let new = Owned::new(Node::new(data)).into_ptr();
+        loop {
+            let node = match unsafe { self.curr.as_ref() } {
+                Some(node) => node,
+                None => break,
+            };
+            // Mark the node, so that no other thread inserts after it, or removes its successor.
+            let next = node.next.load(SeqCst);
+            if next.tag() != 0 {
+                break;
+            }
+            if node.next
+                .compare_and_set(next, next.with_tag(1), SeqCst)
+                .is_err()
+            {
+                continue;
+            }
+            unsafe { new.deref() }.next.store(next, SeqCst);
+            // Once `new` is in the list, another thread may remove and free it, so we register it
+            // before it is reachable.
+            let new_hp = new.hazard();
+            if self.prev.compare_and_set(self.curr, new, SeqCst).is_ok() {
+                unsafe {
+                    // Now `curr` is not reachable from the list.
+                    let data = ::std::ptr::read(&node.data);
+                    self.curr = new;
+                    ::std::mem::replace(&mut self.curr_hp, new_hp).free();
+                    return Ok(ManuallyDrop::into_inner(data));
+                }
+            }
+            // As in `remove_current`: unmark, and find our predecessor again.
+            drop(new_hp);
+            let _ = node.next.compare_and_set(next.with_tag(1), next, SeqCst);
+            if !self.relocate() {
+                break;
+            }
+        }
+        // `new` was never in the list, so we can take the data back and free the node right away.
+        // `data` is `ManuallyDrop`, so dropping the node does not drop it.
+        unsafe {
+            let new = new.into_owned();
+            let data = ::std::ptr::read(&(*new).data);
+            Err(ManuallyDrop::into_inner(data))
+        }
+    }
+
+    /// Walk from the head to the current node again, after we failed to unlink it because `prev`
+    /// changed. Returns `false` if the node is no longer in the list.
+    fn relocate(&mut self) -> bool {
+        let target = self.curr;
+        // `target` is already protected by `curr_hp`, so we do not need to validate.
+        let _target_hp = target.hazard();
+        self.reset();
+        while self.curr != target {
+            if !self.next() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
 impl<T> Drop for List<T> {
     fn drop(&mut self) {
         unsafe {
@@ -524,6 +884,176 @@ mod test {
         assert!(list.is_empty());
     }
 
+    #[test]
+    fn cursor() {
+        let list = List::new();
+        const N: usize = 32;
+        for i in 0..N {
+            list.insert(i);
+        }
+        {
+            // Remove the even elements, and insert `i + 100` after the odd ones.
+            let mut cursor = list.cursor();
+            while let Some(i) = cursor.get().cloned() {
+                if i % 2 == 0 {
+                    assert_eq!(cursor.remove_current(), Some(i));
+                } else {
+                    assert!(cursor.insert_after(i + 100).is_ok());
+                    assert!(cursor.next());
+                    assert_eq!(cursor.get(), Some(&(i + 100)));
+                    cursor.next();
+                }
+            }
+            assert!(!cursor.next());
+            assert_eq!(cursor.remove_current(), None);
+            assert_eq!(cursor.insert_after(0), Err(0));
+        }
+        let v = list.iter().cloned().collect::<Vec<_>>();
+        let expected = (0..N)
+            .rev()
+            .filter(|i| i % 2 == 1)
+            .flat_map(|i| vec![i, i + 100])
+            .collect::<Vec<_>>();
+        assert_eq!(v, expected);
+    }
+
+    #[test]
+    fn cursor_remove_concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 16;
+
+        let list: Arc<List<usize>> = Arc::new(List::new());
+        for i in 0..N {
+            list.insert(i);
+        }
+
+        let threads = (0..N_THREADS)
+            .map(|thread_id| {
+                let list = list.clone();
+                spawn(move || {
+                    let mut removed = 0;
+                    let mut cursor = list.cursor();
+                    while let Some(i) = cursor.get().cloned() {
+                        if i % N_THREADS == thread_id {
+                            if cursor.remove_current().is_some() {
+                                removed += 1;
+                            }
+                        } else {
+                            cursor.next();
+                        }
+                    }
+                    removed
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert_eq!(t.join().unwrap(), N / N_THREADS);
+        }
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn predicates() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Record {
+            key: usize,
+            value: usize,
+        }
+        impl ::std::borrow::Borrow<usize> for Record {
+            fn borrow(&self) -> &usize {
+                &self.key
+            }
+        }
+
+        let list = List::new();
+        const N: usize = 32;
+        for i in 0..N {
+            list.insert(Record { key: i, value: i * 10 });
+        }
+        assert_eq!(list.get(&5).map(|r| r.value), Some(50));
+        assert!(list.get(&N).is_none());
+        assert_eq!(list.find(|r| r.value == 70).map(|r| r.key), Some(7));
+        assert!(list.find(|r| r.value == 71).is_none());
+
+        assert_eq!(list.remove_if(|r| r.key == 3).map(|r| r.value), Some(30));
+        assert_eq!(list.remove_if(|r| r.key == 3), None);
+
+        list.retain(|r| r.key % 4 != 0);
+        let mut keys = list.iter().map(|r| r.key).collect::<Vec<_>>();
+        keys.sort();
+        let expected = (0..N).filter(|i| i % 4 != 0 && *i != 3).collect::<Vec<_>>();
+        assert_eq!(keys, expected);
+
+        let old = list.update(|r| if r.key == 5 {
+            Some(Record { key: 5, value: 51 })
+        } else {
+            None
+        });
+        assert_eq!(old, Some(Record { key: 5, value: 50 }));
+        assert_eq!(list.get(&5).map(|r| r.value), Some(51));
+        assert_eq!(list.update(|r| if r.key == 3 { Some(*r) } else { None }), None);
+        assert_eq!(list.iter().count(), expected.len());
+    }
+
+    #[test]
+    fn update_concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 256;
+        const ROUNDS: usize = 16;
+
+        let list: Arc<List<(usize, usize)>> = Arc::new(List::new());
+        for i in 0..N {
+            list.insert((i, 0));
+        }
+
+        let threads = (0..N_THREADS)
+            .map(|_| {
+                let list = list.clone();
+                spawn(move || for _ in 0..ROUNDS {
+                    for i in 0..N {
+                        let old = list.update(|&(k, v)| {
+                            if k == i { Some((k, v + 1)) } else { None }
+                        });
+                        assert!(old.is_some());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        // No update was lost, and no element was duplicated.
+        let mut v = list.iter().cloned().collect::<Vec<_>>();
+        v.sort();
+        let expected = (0..N).map(|i| (i, N_THREADS * ROUNDS)).collect::<Vec<_>>();
+        assert_eq!(v, expected);
+    }
+
+    #[test]
+    fn retain_concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 16;
+
+        let list: Arc<List<usize>> = Arc::new(List::new());
+        for i in 0..N {
+            list.insert(i);
+        }
+
+        let threads = (0..N_THREADS)
+            .map(|thread_id| {
+                let list = list.clone();
+                spawn(move || list.retain(|i| i % N_THREADS != thread_id))
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        assert!(list.is_empty());
+    }
+
     #[test]
     fn stress_test() {
         const N_THREADS: usize = 4;
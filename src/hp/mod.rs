@@ -5,6 +5,10 @@
 pub mod atomic;
 pub mod queue;
 pub mod list;
+pub mod deque;
+pub mod priority_queue;
+pub mod seg_queue;
+pub mod wf_queue;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{channel, Sender};
@@ -192,6 +196,23 @@ lazy_static! {
     static ref HAZARD_QUEUE: queue::Queue<Garbage> = {
         queue::Queue::new()
     };
+    /// The number of objects in `HAZARD_QUEUE`.
+    static ref HAZARD_QUEUE_LEN: AtomicUsize = {
+        AtomicUsize::new(0)
+    };
+}
+
+/// Returns the number of objects that are retired but not yet freed, since a thread had a hazard
+/// pointer to them when they were retired. With `hp-wait` we wait for the hazard pointers to go
+/// away instead, so this is always 0.
+#[cfg(not(feature = "hp-wait"))]
+pub fn pending_garbage() -> usize {
+    HAZARD_QUEUE_LEN.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "hp-wait")]
+pub fn pending_garbage() -> usize {
+    0
 }
 
 #[cfg(not(feature = "hp-wait"))]
@@ -199,6 +220,7 @@ fn defer_hp<T>(hp: atomic::HazardPtr<T>)
 where
     T: 'static,
 {
+    HAZARD_QUEUE_LEN.fetch_add(1, Ordering::Relaxed);
     unsafe {
         HAZARD_QUEUE.push(Garbage::new(hp.into_owned()));
     }
@@ -228,6 +250,7 @@ fn free_from_queue() {
                     // used
                     HAZARD_QUEUE.push(garbage);
                 } else {
+                    HAZARD_QUEUE_LEN.fetch_sub(1, Ordering::Relaxed);
                     drop(garbage);
                 }
             } else {
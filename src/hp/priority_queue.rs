@@ -0,0 +1,514 @@
+/// A lock-free priority queue, as described by Lindén and Jonsson in "A Skiplist-Based
+/// Concurrent Priority Queue with Minimal Memory Contention".
+///
+/// The queue is a skiplist, where `delete_min` logically deletes the first node by marking the
+/// `next[0]` pointer of its predecessor. This way the deleted nodes always form a prefix of the
+/// list. When a thread sees that the prefix is longer than `BOUND_OFFSET`, it swings the head
+/// past the whole prefix, and frees all of the nodes at once.
+///
+/// We only have `NUM_HP` hazard pointers, so we cannot protect the predecessors and successors on
+/// all levels at the same time, like the paper does. Instead we search for one level at a time,
+/// and only hold hazard pointers to the nodes we are currently looking at. Since a node in the
+/// prefix can be freed while we are reading from it, the thread freeing the prefix first sets
+/// `recycled` on each node, in list order. If the node we came from is not recycled after we have
+/// registered the next node as hazardous, the next node is not freed. This only holds if the
+/// prefix is freed in order, so only one thread may free nodes at a time.
+
+use std::cell::Cell;
+use std::mem::ManuallyDrop;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+
+use super::atomic::{Owned, Atomic, Ptr, HazardPtr};
+
+/// The maximum height of a node.
+const NUM_LEVELS: usize = 16;
+
+/// The number of deleted nodes we accept in the prefix, before we try to remove them.
+const BOUND_OFFSET: usize = 32;
+
+pub struct Node<K, V> {
+    /// The key of the node. This is `None` for the head and tail sentinels.
+    key: Option<K>,
+    /// The value is moved out when the node is deleted, so we never drop it with the node.
+    value: ManuallyDrop<V>,
+    /// `true` while the node is being linked into the higher levels. Nodes after an inserting
+    /// node in the prefix are not freed, since the inserting thread may still link to them.
+    inserting: AtomicBool,
+    /// `true` when the node is about to be freed.
+    recycled: AtomicBool,
+    next: [Atomic<Node<K, V>>; NUM_LEVELS],
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Self {
+        Self {
+            key: Some(key),
+            value: ManuallyDrop::new(value),
+            inserting: AtomicBool::new(true),
+            recycled: AtomicBool::new(false),
+            next: Default::default(),
+        }
+    }
+
+    fn sentinel() -> Self {
+        Self {
+            key: None,
+            value: unsafe { ::std::mem::uninitialized() },
+            inserting: AtomicBool::new(false),
+            recycled: AtomicBool::new(false),
+            next: Default::default(),
+        }
+    }
+
+    /// Load `next[i]` and register it as hazardous. The returned pointer keeps its tag. Return
+    /// `None` if `self` is recycled, since the pointer we read may then be freed.
+    fn protect_next<'scope>(&self, i: usize) -> Option<(Ptr<'scope, Self>, HazardPtr<Self>)> {
+        loop {
+            let next = self.next[i].load(SeqCst);
+            let next_hp = next.hazard();
+            if self.next[i].load(SeqCst) != next {
+                drop(next_hp);
+                continue;
+            }
+            if self.recycled.load(SeqCst) {
+                return None;
+            }
+            return Some((next, next_hp));
+        }
+    }
+}
+
+impl<K: Ord, V> Node<K, V> {
+    /// Returns `true` if the key of the node is less than `k`. The tail sentinel has an infinite
+    /// key.
+    fn key_less(&self, k: &K) -> bool {
+        match self.key {
+            Some(ref key) => key < k,
+            None => false,
+        }
+    }
+}
+
+/// The predecessor and successor of a key on one level. `pred` is not protected if it is the
+/// head, which is never freed.
+struct Position<'scope, K: 'scope, V: 'scope> {
+    pred: Ptr<'scope, Node<K, V>>,
+    _pred_hp: Option<HazardPtr<Node<K, V>>>,
+    succ: Ptr<'scope, Node<K, V>>,
+    _succ_hp: HazardPtr<Node<K, V>>,
+}
+
+pub struct PriorityQueue<K, V> {
+    head: Atomic<Node<K, V>>,
+    tail: Atomic<Node<K, V>>,
+    /// `true` while a thread is freeing a part of the prefix.
+    recycling: AtomicBool,
+}
+
+impl<K, V> PriorityQueue<K, V>
+where
+    K: 'static + Ord + Clone,
+    V: 'static,
+{
+    pub fn new() -> Self {
+        let tail = Owned::new(Node::sentinel()).into_ptr();
+        let head = Node::sentinel();
+        for i in 0..NUM_LEVELS {
+            head.next[i].store(tail, Relaxed);
+        }
+        Self {
+            head: Atomic::from_owned(Owned::new(head)),
+            tail: Atomic::from_ptr(tail),
+            recycling: AtomicBool::new(false),
+        }
+    }
+
+    /// Find the predecessor and successor of `k` on `level`. The predecessor on level 0 is never
+    /// in the deleted prefix.
+    fn locate<'scope>(&self, k: &K, level: usize) -> Position<'scope, K, V> {
+        'restart: loop {
+            let mut pred: Ptr<Node<K, V>> = self.head.load(SeqCst);
+            let mut pred_hp = None;
+            let mut i = NUM_LEVELS;
+            while i > level {
+                i -= 1;
+                let (next, mut cur_hp) = match unsafe { pred.deref() }.protect_next(i) {
+                    Some(p) => p,
+                    None => continue 'restart,
+                };
+                // A marked pointer on level 0 means that `cur` is deleted.
+                let mut d = next.tag() != 0;
+                let mut cur = next.with_tag(0);
+                loop {
+                    let c = unsafe { cur.deref() };
+                    let succ_deleted = c.next[0].load(SeqCst).tag() != 0;
+                    if !(c.key_less(k) || succ_deleted || (i == 0 && d)) {
+                        break;
+                    }
+                    let (next, next_hp) = match c.protect_next(i) {
+                        Some(p) => p,
+                        None => continue 'restart,
+                    };
+                    pred = cur;
+                    pred_hp = Some(cur_hp);
+                    d = next.tag() != 0;
+                    cur = next.with_tag(0);
+                    cur_hp = next_hp;
+                }
+                if i == level {
+                    return Position {
+                        pred,
+                        _pred_hp: pred_hp,
+                        succ: cur,
+                        _succ_hp: cur_hp,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Insert `value` with priority `key`.
+    pub fn insert(&self, key: K, value: V) {
+        let height = random_level();
+        // `new` is not freed before we clear `inserting`, so we do not need to protect it.
+        let new = Owned::new(Node::new(key, value)).into_ptr();
+        let node = unsafe { new.deref() };
+        let key = node.key.as_ref().unwrap();
+
+        loop {
+            let pos = self.locate(key, 0);
+            node.next[0].store(pos.succ, SeqCst);
+            let pred = unsafe { pos.pred.deref() };
+            if pred.next[0].compare_and_set(pos.succ, new, SeqCst).is_ok() {
+                break;
+            }
+        }
+
+        let mut i = 1;
+        while i < height {
+            let pos = self.locate(key, i);
+            node.next[i].store(pos.succ, SeqCst);
+            let succ = unsafe { pos.succ.deref() };
+            // If the new node or the successor is being deleted, there is no point in linking
+            // it in on the higher levels. A deleted successor may also come before the new node
+            // on level 0, and a deleted new node may come before its predecessor, and then the
+            // levels would go out of order.
+            if node.next[0].load(SeqCst).tag() != 0 || succ.next[0].load(SeqCst).tag() != 0 ||
+                self.is_deleted(pos.succ) || self.is_deleted(new)
+            {
+                break;
+            }
+            let pred = unsafe { pos.pred.deref() };
+            if pred.next[i].compare_and_set(pos.succ, new, SeqCst).is_ok() {
+                i += 1;
+            }
+        }
+        node.inserting.store(false, SeqCst);
+    }
+
+    /// Returns `true` if `node` is in the deleted prefix.
+    fn is_deleted(&self, node: Ptr<Node<K, V>>) -> bool {
+        'restart: loop {
+            let mut x = self.head.load(SeqCst);
+            let mut _x_hp = None;
+            loop {
+                let (next, next_hp) = match unsafe { x.deref() }.protect_next(0) {
+                    Some(p) => p,
+                    None => continue 'restart,
+                };
+                if next.tag() == 0 {
+                    return false;
+                }
+                if next.with_tag(0) == node {
+                    return true;
+                }
+                x = next.with_tag(0);
+                _x_hp = Some(next_hp);
+            }
+        }
+    }
+
+    /// Remove the element with the smallest key from the queue, and return its key and value.
+    pub fn delete_min(&self) -> Option<(K, V)> {
+        let head_ptr = self.head.load(SeqCst);
+        let head = unsafe { head_ptr.deref() };
+        let tail = self.tail.load(SeqCst);
+        'restart: loop {
+            // `obs_head` must not be freed and reused while we look at it, or the CAS on the
+            // head below could succeed when it should not.
+            let (obs_head, obs_hp) = head.protect_next(0).unwrap();
+            let mut new_head = Ptr::null();
+            let mut offset = 0;
+            let mut x = head_ptr;
+            let mut x_hp;
+            // Walk the deleted prefix, and try to mark the first node after it.
+            loop {
+                let x_node = unsafe { x.deref() };
+                if x_node.next[0].load(SeqCst).with_tag(0) == tail {
+                    return None;
+                }
+                if new_head.is_null() && x_node.inserting.load(SeqCst) {
+                    new_head = x;
+                }
+                let (next, next_hp) = match x_node.protect_next(0) {
+                    Some(p) => p,
+                    None => continue 'restart,
+                };
+                if next.tag() == 0 {
+                    // `next` is protected before we mark it, so it is safe to read from after
+                    // we have won it. Unlike the paper we cannot use `fetch_or` here, since it
+                    // could mark a node we have not protected.
+                    if x_node.next[0]
+                        .compare_and_set(next, next.with_tag(1), SeqCst)
+                        .is_err()
+                    {
+                        // Someone else marked it, or inserted after `x`. Try again.
+                        continue;
+                    }
+                }
+                offset += 1;
+                x = next.with_tag(0);
+                x_hp = next_hp;
+                if next.tag() == 0 {
+                    // We marked the pointer, so `x` is ours.
+                    break;
+                }
+            }
+            let ret = unsafe {
+                let x_node = x.deref();
+                let value = ::std::ptr::read(&x_node.value);
+                (x_node.key.clone().unwrap(), ManuallyDrop::into_inner(value))
+            };
+            drop(x_hp);
+            if new_head.is_null() {
+                new_head = x;
+            }
+            // Only one thread frees nodes at a time; if someone else is at it, we leave the
+            // prefix for a later `delete_min`.
+            if offset >= BOUND_OFFSET && !self.recycling.swap(true, SeqCst) {
+                // Swing the head past the prefix. If we succeed, we are the only thread freeing
+                // the nodes from `obs_head` up until `new_head`.
+                if head.next[0]
+                    .compare_and_set(obs_head, new_head.with_tag(1), SeqCst)
+                    .is_ok()
+                {
+                    drop(obs_hp);
+                    self.restructure();
+                    let mut cur = obs_head.with_tag(0);
+                    while cur != new_head {
+                        let c = unsafe { cur.deref() };
+                        let next = c.next[0].load(SeqCst).with_tag(0);
+                        c.recycled.store(true, SeqCst);
+                        unsafe {
+                            cur.hazard().free();
+                        }
+                        cur = next;
+                    }
+                }
+                self.recycling.store(false, SeqCst);
+            }
+            return Some(ret);
+        }
+    }
+
+    /// Update the pointers of the head on levels above 0, so that they skip the deleted prefix.
+    fn restructure(&self) {
+        let head_ptr = self.head.load(SeqCst);
+        let head = unsafe { head_ptr.deref() };
+        'restart: loop {
+            let mut pred = head_ptr;
+            let mut _pred_hp = None;
+            let mut i = NUM_LEVELS - 1;
+            while i > 0 {
+                let (h, h_hp) = head.protect_next(i).unwrap();
+                if h_hp.next[0].load(SeqCst).tag() == 0 {
+                    i -= 1;
+                    continue;
+                }
+                let (mut cur, mut cur_hp) = match unsafe { pred.deref() }.protect_next(i) {
+                    Some(p) => p,
+                    None => continue 'restart,
+                };
+                while cur_hp.next[0].load(SeqCst).tag() != 0 {
+                    let (next, next_hp) = match cur_hp.protect_next(i) {
+                        Some(p) => p,
+                        None => continue 'restart,
+                    };
+                    pred = cur;
+                    _pred_hp = Some(cur_hp);
+                    cur = next;
+                    cur_hp = next_hp;
+                }
+                // `cur` is protected, so we link to it instead of reloading `pred.next[i]`.
+                if head.next[i].compare_and_set(h, cur, SeqCst).is_ok() {
+                    i -= 1;
+                }
+            }
+            return;
+        }
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        let tail = self.tail.load(SeqCst);
+        'restart: loop {
+            let mut node = self.head.load(SeqCst);
+            let mut _node_hp = None;
+            loop {
+                let (next, next_hp) = match unsafe { node.deref() }.protect_next(0) {
+                    Some(p) => p,
+                    None => continue 'restart,
+                };
+                if next.with_tag(0) == tail {
+                    return true;
+                }
+                if next.tag() == 0 {
+                    return false;
+                }
+                node = next.with_tag(0);
+                _node_hp = Some(next_hp);
+            }
+        }
+    }
+}
+
+impl<K, V> Drop for PriorityQueue<K, V> {
+    fn drop(&mut self) {
+        unsafe {
+            let tail = self.tail.load(SeqCst);
+            let mut node = self.head.load(SeqCst).into_owned();
+            loop {
+                let next = node.next[0].load(SeqCst);
+                ::std::mem::drop(node);
+                if next.with_tag(0) == tail {
+                    break;
+                }
+                node = next.with_tag(0).into_owned();
+                if next.tag() == 0 {
+                    // The node is not deleted, so we still own its value.
+                    ManuallyDrop::drop(&mut node.value);
+                }
+            }
+            ::std::mem::drop(tail.into_owned());
+        }
+    }
+}
+
+/// Return a random height for a new node, where each level is half as likely as the previous.
+fn random_level() -> usize {
+    thread_local! {
+        static SEED: Cell<u32> = Cell::new(0);
+    }
+    SEED.with(|seed| {
+        let mut x = seed.get();
+        if x == 0 {
+            x = (seed as *const _ as usize as u32) | 1;
+        }
+        // xorshift32
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        seed.set(x);
+        ::std::cmp::min(x.trailing_zeros() as usize + 1, NUM_LEVELS)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{thread_rng, Rng};
+
+    use std::thread::spawn;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn st_insert_delete_min() {
+        const N: usize = 1024;
+        let pq = PriorityQueue::new();
+        let mut keys = (0..N).collect::<Vec<_>>();
+        thread_rng().shuffle(&mut keys);
+        for &k in &keys {
+            pq.insert(k, k * 2);
+        }
+        for i in 0..N {
+            assert_eq!(pq.delete_min(), Some((i, i * 2)));
+        }
+        assert_eq!(pq.delete_min(), None);
+        assert!(pq.is_empty());
+    }
+
+    #[test]
+    fn drop_values() {
+        use std::sync::atomic::AtomicUsize;
+        lazy_static! {
+            static ref COUNT: AtomicUsize = AtomicUsize::new(0);
+        }
+        struct MustDrop;
+        impl Drop for MustDrop {
+            fn drop(&mut self) {
+                COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        {
+            let pq = PriorityQueue::new();
+            for i in 0..100 {
+                pq.insert(i, MustDrop);
+            }
+            for _ in 0..50 {
+                pq.delete_min();
+            }
+        }
+        assert_eq!(COUNT.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn is_unique_receiver() {
+        const N_THREADS: usize = 8;
+        const ELEMS: usize = 1024 * 64;
+
+        let pq = Arc::new(PriorityQueue::new());
+        let markers = Arc::new(
+            (0..ELEMS)
+                .map(|_| AtomicBool::new(false))
+                .collect::<Vec<_>>(),
+        );
+
+        // Half of the threads insert, while the other half delete.
+        let threads = (0..N_THREADS)
+            .map(|thread_id| {
+                let pq = pq.clone();
+                let markers = markers.clone();
+                spawn(move || if thread_id % 2 == 0 {
+                    let mut i = thread_id / 2;
+                    while i < ELEMS {
+                        pq.insert(i, i);
+                        i += N_THREADS / 2;
+                    }
+                } else {
+                    for _ in 0..ELEMS / N_THREADS {
+                        if let Some((k, v)) = pq.delete_min() {
+                            assert_eq!(k, v);
+                            assert!(!markers[k].swap(true, Ordering::SeqCst));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        // The rest of the elements must come out in order.
+        let mut last = None;
+        while let Some((k, _)) = pq.delete_min() {
+            assert!(last < Some(k));
+            last = Some(k);
+            assert!(!markers[k].swap(true, Ordering::SeqCst));
+        }
+        for m in markers.iter() {
+            assert!(m.load(Ordering::SeqCst));
+        }
+    }
+}
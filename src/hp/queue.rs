@@ -2,22 +2,30 @@
 #[allow(dead_code)]
 /// A Michael-Scott Queue.
 
-use std::sync::atomic::Ordering::{SeqCst};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
 use std::default::Default;
 use std::mem::{ManuallyDrop, drop};
+use std::ops::Deref;
 
-use super::atomic::{Owned, Atomic, Ptr};
+use super::atomic::{Owned, Atomic, Ptr, HazardPtr};
+use counter::StripedCounter;
 
 #[derive(Debug)]
 pub struct Queue<T> {
     head: Atomic<Node<T>>,
     tail: Atomic<Node<T>>,
+    /// Counts the elements in the queue, if the queue is made with `with_counter`.
+    counter: Option<StripedCounter>,
 }
 
 #[derive(Debug)]
 pub struct Node<T> {
     pub data: ManuallyDrop<T>,
     next: Atomic<Node<T>>,
+    /// The position of the node in the queue, counted from the first sentinel. This is set
+    /// before the node is linked in, so that iterators can tell if the head has moved past a node.
+    index: AtomicUsize,
 }
 
 impl<T> Node<T> {
@@ -25,6 +33,7 @@ impl<T> Node<T> {
         Self {
             data: ManuallyDrop::new(data),
             next: Default::default(),
+            index: AtomicUsize::new(0),
         }
     }
 
@@ -32,6 +41,7 @@ impl<T> Node<T> {
         Self {
             data: unsafe { ::std::mem::uninitialized() },
             next: Default::default(),
+            index: AtomicUsize::new(0),
         }
     }
 }
@@ -46,12 +56,29 @@ where
         let q = Queue {
             head: Atomic::null(),
             tail: Atomic::null(),
+            counter: None,
         };
         q.head.store(ptr, SeqCst);
         q.tail.store(ptr, SeqCst);
         q
     }
 
+    /// Make a queue which counts its elements in a `StripedCounter` with `stripes` stripes, so
+    /// that `len` does not have to walk the queue. `stripes` should usually be the number of
+    /// threads using the queue.
+    pub fn with_counter(stripes: usize) -> Self {
+        let mut q = Self::new();
+        q.counter = Some(StripedCounter::new(stripes));
+        q
+    }
+
+    /// Add `n` to the element counter, if we have one.
+    fn count(&self, n: isize) {
+        if let Some(ref counter) = self.counter {
+            counter.add(n);
+        }
+    }
+
     pub fn push(&self, t: T) {
         let node = Owned::new(Node::new(t));
         let new_node = node.into_ptr();
@@ -73,6 +100,10 @@ where
                 // on queue to the real tail we've seen, which is `next`.
                 let _ = self.tail.compare_and_set(tail, next, SeqCst);
             } else {
+                unsafe { new_node.deref() }.index.store(
+                    t.index.load(Relaxed) + 1,
+                    Relaxed,
+                );
                 let succ = t.next
                     .compare_and_set(Ptr::null(), new_node, SeqCst)
                     .is_ok();
@@ -82,12 +113,80 @@ where
                     // thread could have helped by moving the tail pointer.
                     let _ = self.tail.compare_and_set(tail, new_node, SeqCst);
                     drop(tail_hp);
+                    self.count(1);
                     return;
                 }
             }
         }
     }
 
+    /// Push all elements of `iter`. The nodes are linked together before we link the first of
+    /// them into the queue, so the whole batch takes one CAS and one hazard pointer, and the
+    /// elements are next to each other in the queue.
+    pub fn push_batch<I>(&self, iter: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut iter = iter.into_iter();
+        let first = match iter.next() {
+            Some(t) => Owned::new(Node::new(t)).into_ptr(),
+            None => return,
+        };
+        let mut last = first;
+        let mut count = 1;
+        for t in iter {
+            let node = Owned::new(Node::new(t)).into_ptr();
+            unsafe { last.deref() }.next.store(node, Relaxed);
+            last = node;
+            count += 1;
+        }
+        loop {
+            let tail: Ptr<Node<T>> = self.tail.load(SeqCst);
+            let tail_hp = tail.hazard();
+            if self.tail.load(SeqCst) != tail {
+                continue;
+            }
+            let t = unsafe { tail.deref() };
+            let next = t.next.load(SeqCst);
+            if !next.is_null() {
+                let _ = self.tail.compare_and_set(tail, next, SeqCst);
+                continue;
+            }
+            // The indices depend on where the batch is linked in, so we set them every time.
+            let mut index = t.index.load(Relaxed);
+            let mut node = first;
+            loop {
+                index += 1;
+                let n = unsafe { node.deref() };
+                n.index.store(index, Relaxed);
+                if node == last {
+                    break;
+                }
+                node = n.next.load(Relaxed);
+            }
+            if t.next
+                .compare_and_set(Ptr::null(), first, SeqCst)
+                .is_ok()
+            {
+                // Move the tail past the whole batch. If we fail, other threads will move it one
+                // node at a time.
+                let _ = self.tail.compare_and_set(tail, last, SeqCst);
+                drop(tail_hp);
+                self.count(count);
+                return;
+            }
+        }
+    }
+
+    /// If the tail points to `node`, move it to `next`. The head may not move past the tail,
+    /// since the node the tail points to would then be freed, so this must be called before we
+    /// move the head from `node`.
+    fn help_tail(&self, node: Ptr<Node<T>>, next: Ptr<Node<T>>) {
+        if self.tail.load(SeqCst) == node {
+            let _ = self.tail.compare_and_set(node, next, SeqCst);
+        }
+    }
+
     pub fn pop(&self) -> Option<T> {
         self.pop_hp_fn(|hp| unsafe { hp.free() })
     }
@@ -129,11 +228,13 @@ where
                     // node the new sentinel node, but return the data of `node`,
                     // instead of `head`. In other words, the data we return
                     // belongs on the node that is the new sentinel node.
+                    self.help_tail(head, next);
                     let res = self.head.compare_and_set(head, next, SeqCst);
                     match res {
                         Ok(()) => {
                             let ret = Some(ManuallyDrop::into_inner(::std::ptr::read(&node.data)));
                             drop(next_hp);
+                            self.count(-1);
                             // While someone is using the head pointer, keep it here.
                             f(head_hp);
                             return ret;
@@ -146,11 +247,79 @@ where
         }
     }
 
-    /// Count the number of elements in the queue.
+    /// Pop up to `n` elements, with one CAS on the head.
+    pub fn pop_batch(&self, n: usize) -> Vec<T> {
+        let mut ret = Vec::new();
+        if n == 0 {
+            return ret;
+        }
+        'outer: loop {
+            let (head, head_hp) = self.protect_head();
+            // Find the node which will be the new sentinel. We only register the node we are at
+            // and the node after it, but no node after the head is freed before the head has
+            // moved, so we check that it has not.
+            let mut last = head;
+            let mut last_hp = None;
+            let mut count = 0;
+            while count < n {
+                let next = unsafe { last.deref() }.next.load(SeqCst);
+                if next.is_null() {
+                    break;
+                }
+                let next_hp = next.hazard();
+                if self.head.load(SeqCst) != head {
+                    continue 'outer;
+                }
+                self.help_tail(last, next);
+                last = next;
+                last_hp = Some(next_hp);
+                count += 1;
+            }
+            if count == 0 {
+                return ret;
+            }
+            if self.head.compare_and_set(head, last, SeqCst).is_err() {
+                continue;
+            }
+            // The nodes from `head` up to `last` are now ours to free. `last` is the new sentinel,
+            // so we keep it registered until we have read its element. Like in `pop`, the element
+            // of each node is in the node after it.
+            self.count(-(count as isize));
+            ret.reserve(count);
+            unsafe {
+                let mut node = head;
+                let mut node_hp = head_hp;
+                loop {
+                    let next = node.deref().next.load(SeqCst);
+                    ret.push(ManuallyDrop::into_inner(::std::ptr::read(&next.deref().data)));
+                    node_hp.free();
+                    if next == last {
+                        break;
+                    }
+                    node = next;
+                    node_hp = node.hazard();
+                }
+            }
+            drop(last_hp);
+            return ret;
+        }
+    }
+
+    /// Return the number of elements in the queue. If the queue is made with `with_counter`, this
+    /// sums the counter, which is O(threads), but only approximate while other threads push and
+    /// pop. Otherwise, this is `len_exact`.
+    pub fn len(&self) -> usize {
+        match self.counter {
+            Some(ref counter) => counter.get(),
+            None => self.len_exact(),
+        }
+    }
+
+    /// Count the number of elements in the queue, by walking it.
     /// This is typically not a operation we need,
     /// but it is practical to have for testing
     /// purposes.
-    pub fn len(&self) -> usize {
+    pub fn len_exact(&self) -> usize {
         let mut len = 0;
         let mut node = unsafe { self.head.load(SeqCst).deref() };
         while let Some(next) = unsafe { node.next.load(SeqCst).as_ref() } {
@@ -166,6 +335,153 @@ where
         let h = unsafe { head.deref() };
         h.next.load(SeqCst).is_null()
     }
+
+    /// Load the head, and register it as hazardous.
+    fn protect_head<'scope>(&'scope self) -> (Ptr<'scope, Node<T>>, HazardPtr<Node<T>>) {
+        loop {
+            let head = self.head.load(SeqCst);
+            let head_hp = head.hazard();
+            if self.head.load(SeqCst) == head {
+                return (head, head_hp);
+            }
+        }
+    }
+
+    /// Returns a reference to the first element of the queue, without removing it. The node of
+    /// the element is registered as hazardous as long as the returned `Guard` lives, so it may be
+    /// popped, but it is not freed.
+    ///
+    /// `pop` moves the element out of the node, and the thread which popped it may drop it while
+    /// we are reading it. That is only safe if `T` has no destructor, so we require `T: Copy`.
+    pub fn peek(&self) -> Option<Guard<T>>
+    where
+        T: Copy,
+    {
+        loop {
+            let (head, _head_hp) = self.protect_head();
+            let next = unsafe { head.deref() }.next.load(SeqCst);
+            if next.is_null() {
+                return None;
+            }
+            let next_hp = next.hazard();
+            // `next` is not freed before the head has moved past it.
+            if self.head.load(SeqCst) == head {
+                return Some(Guard { hp: next_hp });
+            }
+        }
+    }
+
+    /// Returns an iterator over the elements of the queue, from the front to the back. The
+    /// iterator is not a consistent snapshot: the elements are returned in order, and each of
+    /// them was in the queue at some point while we iterated. If the head moves past the
+    /// iterator, it skips ahead to the head. As with `peek`, we require `T: Copy`.
+    pub fn iter(&self) -> Iter<T>
+    where
+        T: Copy,
+    {
+        let (node, hp) = self.protect_head();
+        Iter {
+            queue: self,
+            node,
+            _hp: hp,
+        }
+    }
+
+    /// Returns an iterator which pops elements until the queue is empty.
+    pub fn drain(&self) -> Drain<T> {
+        Drain { queue: self }
+    }
+}
+
+/// A reference to an element in a `Queue`. See `Queue::peek`.
+pub struct Guard<T> {
+    hp: HazardPtr<Node<T>>,
+}
+
+impl<T> Deref for Guard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.hp.data
+    }
+}
+
+/// An iterator over the elements of a `Queue`. See `Queue::iter`.
+pub struct Iter<'a, T: 'a> {
+    queue: &'a Queue<T>,
+    /// The last node we visited. Its element is already returned, or it is a sentinel.
+    node: Ptr<'a, Node<T>>,
+    _hp: HazardPtr<Node<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T>
+where
+    T: 'static + Copy,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            let node = unsafe { self.node.deref() };
+            let next = node.next.load(SeqCst);
+            if next.is_null() {
+                return None;
+            }
+            let next_hp = next.hazard();
+            // `next` is not freed before the head has moved past it, so we check that the head is
+            // not yet past `next`. If it is, the elements up to the head are popped, and we
+            // continue from the head instead.
+            let (head, head_hp) = self.queue.protect_head();
+            let head_index = unsafe { head.deref() }.index.load(Relaxed);
+            if head_index > node.index.load(Relaxed) + 1 {
+                self.node = head;
+                self._hp = head_hp;
+                continue;
+            }
+            drop(head_hp);
+            self.node = next;
+            self._hp = next_hp;
+            return Some(*unsafe { next.deref() }.data);
+        }
+    }
+}
+
+/// An iterator which pops elements from a `Queue`. See `Queue::drain`.
+pub struct Drain<'a, T: 'a> {
+    queue: &'a Queue<T>,
+}
+
+impl<'a, T> Iterator for Drain<'a, T>
+where
+    T: 'static,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.queue.pop()
+    }
+}
+
+impl<T> ::std::iter::FromIterator<T> for Queue<T>
+where
+    T: 'static,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut q = Queue::new();
+        q.extend(iter);
+        q
+    }
+}
+
+impl<T> Extend<T> for Queue<T>
+where
+    T: 'static,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for t in iter {
+            self.push(t);
+        }
+    }
 }
 
 impl<T> Drop for Queue<T> {
@@ -241,6 +557,183 @@ mod test {
         assert_eq!(q.len(), 10);
     }
 
+    #[test]
+    fn st_queue_peek() {
+        let q: Queue<u32> = Queue::new();
+        assert!(q.peek().is_none());
+        q.push(1);
+        q.push(2);
+        assert_eq!(*q.peek().unwrap(), 1);
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(*q.peek().unwrap(), 2);
+    }
+
+    #[test]
+    fn st_queue_iter() {
+        let q: Queue<u32> = Queue::new();
+        for i in 0..10 {
+            q.push(i);
+        }
+        assert_eq!(q.iter().collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+        assert_eq!(q.len(), 10);
+    }
+
+    #[test]
+    fn st_queue_drain() {
+        let q: Queue<u32> = Queue::new();
+        for i in 0..100 {
+            q.push(i);
+        }
+        assert_eq!(q.drain().collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn from_iter_extend() {
+        let mut q: Queue<u32> = (0..10).collect();
+        q.extend(10..20);
+        for i in 0..20 {
+            assert_eq!(q.pop(), Some(i));
+        }
+    }
+
+    #[test]
+    fn st_queue_batch() {
+        let q: Queue<u32> = Queue::new();
+        q.push_batch(0..100);
+        q.push_batch(None);
+        assert_eq!(q.pop_batch(30), (0..30).collect::<Vec<_>>());
+        assert_eq!(q.pop(), Some(30));
+        assert_eq!(q.pop_batch(1000), (31..100).collect::<Vec<_>>());
+        assert!(q.pop_batch(5).is_empty());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn batch_stress_test() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 16;
+        const BATCH: usize = 16;
+
+        let q: Arc<Queue<usize>> = Arc::new(Queue::new());
+        let popped = Arc::new(AtomicUsize::new(0));
+        let markers = Arc::new(
+            (0..N_THREADS * N)
+                .map(|_| AtomicBool::new(false))
+                .collect::<Vec<_>>(),
+        );
+
+        let pushers = (0..N_THREADS).map(|thread_id| {
+            let q = q.clone();
+            spawn(move || for i in 0..N / BATCH {
+                let start = thread_id * N + i * BATCH;
+                q.push_batch(start..start + BATCH);
+            })
+        });
+        let poppers = (0..N_THREADS).map(|_| {
+            let q = q.clone();
+            let popped = popped.clone();
+            let markers = markers.clone();
+            spawn(move || while popped.load(Ordering::SeqCst) < N_THREADS * N {
+                let v = q.pop_batch(BATCH / 2);
+                popped.fetch_add(v.len(), Ordering::SeqCst);
+                for i in v {
+                    assert!(!markers[i].swap(true, Ordering::SeqCst));
+                }
+            })
+        });
+        let threads = pushers.chain(poppers).collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        assert!(markers.iter().all(|m| m.load(Ordering::SeqCst)));
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn len_counter() {
+        let q: Queue<u32> = Queue::with_counter(4);
+        assert_eq!(q.len(), 0);
+        for i in 0..100 {
+            q.push(i);
+        }
+        q.push_batch(0..10);
+        assert_eq!(q.pop_batch(30).len(), 30);
+        assert_eq!(q.pop(), Some(30));
+        assert_eq!(q.len(), 79);
+        assert_eq!(q.len_exact(), 79);
+    }
+
+    #[test]
+    fn len_counter_concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 16;
+
+        let q: Arc<Queue<usize>> = Arc::new(Queue::with_counter(N_THREADS));
+        let threads = (0..N_THREADS)
+            .map(|_| {
+                let q = q.clone();
+                spawn(move || for i in 0..N {
+                    q.push(i);
+                    if i % 2 == 0 {
+                        q.pop();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        assert_eq!(q.len(), N_THREADS * N / 2);
+        assert_eq!(q.len_exact(), N_THREADS * N / 2);
+    }
+
+    #[test]
+    fn iter_concurrent_push() {
+        const N: usize = 1024 * 64;
+
+        let q: Arc<Queue<usize>> = Arc::new(Queue::new());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let pusher = {
+            let q = q.clone();
+            spawn(move || for i in 0..N {
+                q.push(i);
+            })
+        };
+        let popper = {
+            let q = q.clone();
+            let done = done.clone();
+            spawn(move || {
+                let mut popped = 0;
+                while popped < N {
+                    if q.pop().is_some() {
+                        popped += 1;
+                    }
+                }
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+
+        // Elements are pushed in order, so whatever we see must be increasing.
+        while !done.load(Ordering::SeqCst) {
+            let front = q.peek().map(|g| *g);
+            let mut last = None;
+            for i in q.iter() {
+                assert!(i < N);
+                assert!(last < Some(i));
+                last = Some(i);
+            }
+            if let (Some(front), Some(last)) = (front, last) {
+                assert!(front <= last);
+            }
+        }
+        assert!(pusher.join().is_ok());
+        assert!(popper.join().is_ok());
+        assert!(q.is_empty());
+    }
+
     #[derive(Debug)]
     struct NoDrop;
     impl Drop for NoDrop {
@@ -0,0 +1,295 @@
+/// A segmented MPMC queue, in the style of crossbeam's `SegQueue`.
+///
+/// The queue is a linked list of segments, where each segment holds `SEG_SIZE` elements.
+/// Pushing and popping mostly bumps an index in the tail or head segment, and a segment is only
+/// freed when all of its elements are popped. This way we free one segment for every `SEG_SIZE`
+/// elements, instead of one node per element like `queue::Queue` does.
+
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{Relaxed, Acquire, Release, SeqCst};
+
+use super::atomic::{Owned, Atomic, Ptr};
+
+/// The number of elements in one segment.
+const SEG_SIZE: usize = 32;
+
+struct Segment<T> {
+    /// The index of the next element to pop.
+    low: AtomicUsize,
+    data: [UnsafeCell<ManuallyDrop<T>>; SEG_SIZE],
+    /// `ready[i]` is set when `data[i]` is written.
+    ready: [AtomicBool; SEG_SIZE],
+    /// The index of the next element to push. This may grow past `SEG_SIZE`, when threads race
+    /// for the last slot.
+    high: AtomicUsize,
+    next: Atomic<Segment<T>>,
+}
+
+impl<T> Segment<T> {
+    fn new() -> Self {
+        Self {
+            low: AtomicUsize::new(0),
+            data: unsafe { ::std::mem::uninitialized() },
+            ready: Default::default(),
+            high: AtomicUsize::new(0),
+            next: Atomic::null(),
+        }
+    }
+}
+
+pub struct SegQueue<T> {
+    head: Atomic<Segment<T>>,
+    tail: Atomic<Segment<T>>,
+}
+
+unsafe impl<T: Send> Send for SegQueue<T> {}
+unsafe impl<T: Send> Sync for SegQueue<T> {}
+
+impl<T> SegQueue<T>
+where
+    T: 'static,
+{
+    pub fn new() -> Self {
+        let seg = Owned::new(Segment::new()).into_ptr();
+        Self {
+            head: Atomic::from_ptr(seg),
+            tail: Atomic::from_ptr(seg),
+        }
+    }
+
+    pub fn push(&self, t: T) {
+        loop {
+            let tail_ptr: Ptr<Segment<T>> = self.tail.load(Acquire);
+            let tail_hp = tail_ptr.hazard();
+            if self.tail.load(Acquire) != tail_ptr {
+                continue;
+            }
+            let tail = &*tail_hp;
+            if tail.high.load(Relaxed) >= SEG_SIZE {
+                // The segment is full, and the thread which got the last slot will soon append
+                // a new one.
+                continue;
+            }
+            let i = tail.high.fetch_add(1, Relaxed);
+            if i < SEG_SIZE {
+                unsafe {
+                    *tail.data[i].get() = ManuallyDrop::new(t);
+                }
+                tail.ready[i].store(true, Release);
+                if i + 1 == SEG_SIZE {
+                    let new = Owned::new(Segment::new()).into_ptr();
+                    tail.next.store(new, Release);
+                    // A popper may already have moved `self.tail` past us, so we must not store
+                    // unconditionally, or `self.tail` could move back to a retired segment.
+                    let _ = self.tail.compare_and_set(tail_ptr, new, SeqCst);
+                }
+                return;
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let head_ptr: Ptr<Segment<T>> = self.head.load(Acquire);
+            let head_hp = head_ptr.hazard();
+            if self.head.load(Acquire) != head_ptr {
+                continue;
+            }
+            let head = unsafe { head_ptr.deref() };
+            loop {
+                let low = head.low.load(Relaxed);
+                if low >= cmp::min(head.high.load(Relaxed), SEG_SIZE) {
+                    break;
+                }
+                if head.low
+                    .compare_exchange_weak(low, low + 1, Relaxed, Relaxed)
+                    .is_err()
+                {
+                    continue;
+                }
+                // The pusher may not have written the element yet.
+                while !head.ready[low].load(Acquire) {}
+                let ret = unsafe {
+                    ManuallyDrop::into_inner(::std::ptr::read(head.data[low].get()))
+                };
+                if low + 1 == SEG_SIZE {
+                    // We popped the last element of the segment, so we are the one to free it.
+                    let next = loop {
+                        let next = head.next.load(Acquire);
+                        if !next.is_null() {
+                            break next;
+                        }
+                    };
+                    // The pusher that appended `next` may not have moved `self.tail` yet. Help it,
+                    // so that no thread can load `head` from `self.tail` after we free it.
+                    let _ = self.tail.compare_and_set(head_ptr, next, SeqCst);
+                    self.head.store(next, Release);
+                    unsafe {
+                        head_hp.free();
+                    }
+                }
+                return Some(ret);
+            }
+            if head.next.load(Relaxed).is_null() {
+                return None;
+            }
+        }
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        loop {
+            let head_ptr: Ptr<Segment<T>> = self.head.load(Acquire);
+            let head = head_ptr.hazard();
+            if self.head.load(Acquire) != head_ptr {
+                continue;
+            }
+            return head.low.load(Relaxed) >= cmp::min(head.high.load(Relaxed), SEG_SIZE) &&
+                head.next.load(Relaxed).is_null();
+        }
+    }
+}
+
+impl<T> Drop for SegQueue<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let mut ptr = self.head.load(Relaxed);
+            while !ptr.is_null() {
+                let seg = ptr.into_owned();
+                let low = seg.low.load(Relaxed);
+                let high = cmp::min(seg.high.load(Relaxed), SEG_SIZE);
+                for i in low..high {
+                    ManuallyDrop::drop(&mut *(*seg).data[i].get());
+                }
+                ptr = seg.next.load(Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread::spawn;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn st_queue_push_pop_many() {
+        let q = SegQueue::new();
+        for i in 0..1000 {
+            q.push(i);
+        }
+        for i in 0..1000 {
+            assert_eq!(q.pop(), Some(i));
+        }
+        assert_eq!(q.pop(), None);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn do_drop() {
+        lazy_static! {
+            static ref COUNT: AtomicUsize = AtomicUsize::new(0);
+        }
+        struct MustDrop;
+        impl Drop for MustDrop {
+            fn drop(&mut self) {
+                COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        {
+            let q = SegQueue::new();
+            for _ in 0..100 {
+                q.push(MustDrop);
+            }
+            for _ in 0..50 {
+                q.pop();
+            }
+        }
+        assert_eq!(COUNT.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn stress_test() {
+        const N_THREADS: usize = 16;
+        const N: usize = 1024 * 32;
+
+        let source = Arc::new(SegQueue::new());
+        let sink = Arc::new(SegQueue::new());
+
+        for n in 0..N {
+            source.push(n);
+        }
+
+        let threads = (0..N_THREADS)
+            .map(|_thread_id| {
+                let source = source.clone();
+                let sink = sink.clone();
+                spawn(move || while let Some(i) = source.pop() {
+                    sink.push(i);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        let mut v = Vec::with_capacity(N);
+        while let Some(i) = sink.pop() {
+            v.push(i);
+        }
+        v.sort();
+        for (i, n) in v.into_iter().enumerate() {
+            assert_eq!(i, n);
+        }
+    }
+
+    #[test]
+    fn mpmc_cross_segments() {
+        const N_THREADS: usize = 8;
+        // Every pusher fills, and every popper empties, several segments.
+        const N: usize = SEG_SIZE * 64;
+
+        let q = Arc::new(SegQueue::new());
+        let pushers = (0..N_THREADS)
+            .map(|thread_id| {
+                let q = q.clone();
+                spawn(move || for n in 0..N {
+                    q.push(thread_id * N + n);
+                })
+            })
+            .collect::<Vec<_>>();
+        let poppers = (0..N_THREADS)
+            .map(|_thread_id| {
+                let q = q.clone();
+                spawn(move || {
+                    let mut v = Vec::with_capacity(N);
+                    while v.len() < N {
+                        if let Some(i) = q.pop() {
+                            v.push(i);
+                        }
+                    }
+                    v
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in pushers.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        let mut v = Vec::with_capacity(N * N_THREADS);
+        for t in poppers.into_iter() {
+            v.extend(t.join().unwrap());
+        }
+        assert!(q.is_empty());
+        v.sort();
+        for (i, n) in v.into_iter().enumerate() {
+            assert_eq!(i, n);
+        }
+    }
+}
@@ -0,0 +1,471 @@
+/// A wait-free MPMC queue, as described by Kogan and Petrank in "Wait-Free Queues With Multiple
+/// Enqueuers and Dequeuers".
+///
+/// Each thread announces its operation in `state` as an `OpDesc`, together with a phase number
+/// which is larger than the phase of every operation announced before it. Before a thread does
+/// its own operation it helps all pending operations with a phase no larger than its own, so an
+/// operation finishes after at most `num_threads` other operations.
+///
+/// The descriptors are immutable, and a thread changes the state of an operation by CASing a new
+/// descriptor into `state`. The thread whose CAS succeeds frees the old descriptor, so the
+/// descriptors are freed just as often as the nodes are.
+///
+/// Every pointer we dereference is registered as hazardous, and validated by re-reading the
+/// pointer it came from. Note that a validation may fail infinitely often, so with hazard pointers
+/// the queue is only lock-free. At most three hazard pointers are held at once, so that `free`
+/// still has room for its own.
+///
+/// The thread which dequeues a node reads its value from the node after it, after the head has
+/// moved past both of them. Therefore a node is freed when both the thread which dequeued it, and
+/// the thread which dequeued the node before it, are done with it.
+///
+/// Each thread passes its `tid` to `push` and `pop`. The `tid`s must be in `0..num_threads`, and
+/// no two threads may use the same `tid` at the same time.
+
+use std::mem::{ManuallyDrop, drop};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+
+use super::atomic::{Owned, Atomic, Ptr, HazardPtr};
+
+/// The `deq_tid` of a node no dequeuer has claimed.
+const NO_TID: usize = ::std::usize::MAX;
+
+struct Node<T> {
+    /// The value is read by the dequeuer, so we never drop it with the node.
+    data: ManuallyDrop<T>,
+    next: Atomic<Node<T>>,
+    /// The thread which enqueued the node.
+    enq_tid: usize,
+    /// The thread whose dequeue removes the node from the head, or `NO_TID`.
+    deq_tid: AtomicUsize,
+    /// The number of threads which are done with the node. It is freed when this reaches 2.
+    released: AtomicUsize,
+}
+
+impl<T> Node<T> {
+    fn new(data: T, enq_tid: usize) -> Self {
+        Self {
+            data: ManuallyDrop::new(data),
+            next: Atomic::null(),
+            enq_tid,
+            deq_tid: AtomicUsize::new(NO_TID),
+            released: AtomicUsize::new(0),
+        }
+    }
+
+    /// The first sentinel has no value, so nobody will read it.
+    fn empty() -> Self {
+        Self {
+            data: unsafe { ::std::mem::uninitialized() },
+            next: Atomic::null(),
+            enq_tid: NO_TID,
+            deq_tid: AtomicUsize::new(NO_TID),
+            released: AtomicUsize::new(1),
+        }
+    }
+}
+
+struct OpDesc<T> {
+    phase: usize,
+    pending: bool,
+    enqueue: bool,
+    /// For an enqueue this is the node to insert. For a dequeue this is the sentinel node at the
+    /// time the dequeue took effect, so the value is in the node after it. A finished dequeue
+    /// with a null node found the queue empty.
+    node: Atomic<Node<T>>,
+}
+
+impl<T> OpDesc<T> {
+    fn new(phase: usize, pending: bool, enqueue: bool, node: Ptr<Node<T>>) -> Self {
+        Self {
+            phase,
+            pending,
+            enqueue,
+            node: Atomic::from_ptr(node),
+        }
+    }
+
+    /// Returns `true` if this is a pending operation of the given kind, which should be helped
+    /// by an operation with phase `phase`.
+    fn is_pending(&self, phase: usize, enqueue: bool) -> bool {
+        self.pending && self.enqueue == enqueue && self.phase <= phase
+    }
+}
+
+/// Load `atomic` and register the pointer as hazardous.
+fn protect<'scope, T>(atomic: &Atomic<T>) -> (Ptr<'scope, T>, HazardPtr<T>) {
+    loop {
+        let ptr = atomic.load(SeqCst);
+        let hp = ptr.hazard();
+        if atomic.load(SeqCst) == ptr {
+            return (ptr, hp);
+        }
+    }
+}
+
+pub struct WaitFreeQueue<T> {
+    head: Atomic<Node<T>>,
+    tail: Atomic<Node<T>>,
+    state: Vec<Atomic<OpDesc<T>>>,
+}
+
+unsafe impl<T: Send> Send for WaitFreeQueue<T> {}
+unsafe impl<T: Send> Sync for WaitFreeQueue<T> {}
+
+impl<T> WaitFreeQueue<T>
+where
+    T: 'static,
+{
+    /// Make a new queue, which can be used by `num_threads` threads at the same time.
+    pub fn new(num_threads: usize) -> Self {
+        let sentinel = Owned::new(Node::empty()).into_ptr();
+        Self {
+            head: Atomic::from_ptr(sentinel),
+            tail: Atomic::from_ptr(sentinel),
+            state: (0..num_threads)
+                .map(|_| Atomic::new(OpDesc::new(0, false, true, Ptr::null())))
+                .collect(),
+        }
+    }
+
+    pub fn push(&self, t: T, tid: usize) {
+        let phase = self.max_phase() + 1;
+        let node = Owned::new(Node::new(t, tid)).into_ptr();
+        self.announce(tid, OpDesc::new(phase, true, true, node));
+        self.help(phase);
+        self.help_finish_enq();
+    }
+
+    pub fn pop(&self, tid: usize) -> Option<T> {
+        let phase = self.max_phase() + 1;
+        self.announce(tid, OpDesc::new(phase, true, false, Ptr::null()));
+        self.help(phase);
+        // This moves the head past our node, if no other thread has done it yet.
+        self.help_finish_deq();
+        let node = {
+            let (desc, _desc_hp) = protect(&self.state[tid]);
+            unsafe { desc.deref() }.node.load(SeqCst)
+        };
+        if node.is_null() {
+            return None;
+        }
+        // `node` was the sentinel when our dequeue took effect, so our value is in the node after
+        // it. Neither node is freed before we release it.
+        unsafe {
+            let next = node.deref().next.load(SeqCst);
+            let ret = ManuallyDrop::into_inner(::std::ptr::read(&next.deref().data));
+            self.release(next);
+            self.release(node);
+            Some(ret)
+        }
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        let (head, _head_hp) = protect(&self.head);
+        unsafe { head.deref() }.next.load(SeqCst).is_null()
+    }
+
+    /// Replace the descriptor of `tid` with `desc`. Only `tid` itself may do this, and only when
+    /// its previous operation is finished.
+    fn announce(&self, tid: usize, desc: OpDesc<T>) {
+        let new = Owned::new(desc).into_ptr();
+        let old = self.state[tid].swap(new, SeqCst);
+        unsafe {
+            old.hazard().free();
+        }
+    }
+
+    /// CAS the descriptor of `tid` from `cur` to `new`. If we succeed we return the hazard
+    /// pointer of `cur`, which the caller should free when it has dropped its other hazard
+    /// pointers.
+    fn replace_desc(
+        &self,
+        tid: usize,
+        cur: Ptr<OpDesc<T>>,
+        cur_hp: HazardPtr<OpDesc<T>>,
+        new: OpDesc<T>,
+    ) -> Option<HazardPtr<OpDesc<T>>> {
+        match self.state[tid].compare_and_set_owned(cur, Owned::new(new), SeqCst) {
+            Ok(_) => Some(cur_hp),
+            Err(_) => None,
+        }
+    }
+
+    /// Mark that we are done with `node`, and free it if the other thread is done too.
+    unsafe fn release(&self, node: Ptr<Node<T>>) {
+        if node.deref().released.fetch_add(1, SeqCst) == 1 {
+            node.hazard().free();
+        }
+    }
+
+    fn max_phase(&self) -> usize {
+        self.state
+            .iter()
+            .map(|s| {
+                let (desc, _desc_hp) = protect(s);
+                unsafe { desc.deref() }.phase
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn is_still_pending(&self, tid: usize, phase: usize, enqueue: bool) -> bool {
+        let (desc, _desc_hp) = protect(&self.state[tid]);
+        unsafe { desc.deref() }.is_pending(phase, enqueue)
+    }
+
+    /// Help all pending operations with a phase no larger than `phase`.
+    fn help(&self, phase: usize) {
+        for tid in 0..self.state.len() {
+            let (pending, enqueue) = {
+                let (desc, _desc_hp) = protect(&self.state[tid]);
+                let desc = unsafe { desc.deref() };
+                (desc.pending && desc.phase <= phase, desc.enqueue)
+            };
+            if pending {
+                if enqueue {
+                    self.help_enq(tid, phase);
+                } else {
+                    self.help_deq(tid, phase);
+                }
+            }
+        }
+    }
+
+    fn help_enq(&self, tid: usize, phase: usize) {
+        while self.is_still_pending(tid, phase, true) {
+            let (last, last_hp) = protect(&self.tail);
+            let next = unsafe { last.deref() }.next.load(SeqCst);
+            if !next.is_null() {
+                // Some enqueue is halfway done. Finish it, so that we can link our node.
+                drop(last_hp);
+                self.help_finish_enq();
+                continue;
+            }
+            // We read the descriptor again, so that we link the node of the operation we
+            // checked, and not the node of some later operation of `tid`.
+            let (desc, desc_hp) = protect(&self.state[tid]);
+            let d = unsafe { desc.deref() };
+            if !d.is_pending(phase, true) {
+                continue;
+            }
+            // The node of a pending enqueue is not yet dequeued, so it is not freed before we
+            // have registered it, as long as the descriptor is still there.
+            let node = d.node.load(SeqCst);
+            let node_hp = node.hazard();
+            if self.state[tid].load(SeqCst) != desc {
+                continue;
+            }
+            let linked = unsafe { last.deref() }
+                .next
+                .compare_and_set(Ptr::null(), node, SeqCst)
+                .is_ok();
+            drop(node_hp);
+            drop(desc_hp);
+            drop(last_hp);
+            if linked {
+                self.help_finish_enq();
+                return;
+            }
+        }
+    }
+
+    /// Finish the enqueue whose node is linked after `tail`: mark its descriptor as done, and
+    /// move the tail.
+    fn help_finish_enq(&self) {
+        let (last, last_hp) = protect(&self.tail);
+        let next = unsafe { last.deref() }.next.load(SeqCst);
+        if next.is_null() {
+            return;
+        }
+        // While `last` is the tail, `next` is not dequeued.
+        let next_hp = next.hazard();
+        if self.tail.load(SeqCst) != last {
+            return;
+        }
+        let tid = unsafe { next.deref() }.enq_tid;
+        let (cur, cur_hp) = protect(&self.state[tid]);
+        let desc = unsafe { cur.deref() };
+        let mut old = None;
+        if self.tail.load(SeqCst) == last && desc.node.load(SeqCst) == next {
+            let new = OpDesc::new(desc.phase, false, true, next);
+            old = self.replace_desc(tid, cur, cur_hp, new);
+        }
+        let _ = self.tail.compare_and_set(last, next, SeqCst);
+        drop(next_hp);
+        drop(last_hp);
+        if let Some(old) = old {
+            unsafe { old.free() };
+        }
+    }
+
+    fn help_deq(&self, tid: usize, phase: usize) {
+        while self.is_still_pending(tid, phase, false) {
+            let (first, first_hp) = protect(&self.head);
+            let last = self.tail.load(SeqCst);
+            let next = unsafe { first.deref() }.next.load(SeqCst);
+            if first == last {
+                if !next.is_null() {
+                    // Some enqueue is halfway done. Finish it, so that we can move past it.
+                    drop(first_hp);
+                    self.help_finish_enq();
+                    continue;
+                }
+                // The queue is empty, so the dequeue finishes without a node.
+                let (cur, cur_hp) = protect(&self.state[tid]);
+                let desc = unsafe { cur.deref() };
+                if last == self.tail.load(SeqCst) && desc.is_pending(phase, false) {
+                    let new = OpDesc::new(desc.phase, false, false, Ptr::null());
+                    if let Some(old) = self.replace_desc(tid, cur, cur_hp, new) {
+                        drop(first_hp);
+                        unsafe { old.free() };
+                    }
+                }
+            } else {
+                let (cur, cur_hp) = protect(&self.state[tid]);
+                let desc = unsafe { cur.deref() };
+                if !desc.is_pending(phase, false) {
+                    break;
+                }
+                if first == self.head.load(SeqCst) && desc.node.load(SeqCst) != first {
+                    let new = OpDesc::new(desc.phase, true, false, first);
+                    match self.replace_desc(tid, cur, cur_hp, new) {
+                        Some(old) => unsafe { old.free() },
+                        None => continue,
+                    }
+                }
+                let _ = unsafe { first.deref() }
+                    .deq_tid
+                    .compare_exchange(NO_TID, tid, SeqCst, Relaxed);
+                drop(first_hp);
+                self.help_finish_deq();
+            }
+        }
+    }
+
+    /// Finish the dequeue which has claimed the sentinel: mark its descriptor as done, and move
+    /// the head.
+    fn help_finish_deq(&self) {
+        let (first, first_hp) = protect(&self.head);
+        let f = unsafe { first.deref() };
+        let next = f.next.load(SeqCst);
+        let tid = f.deq_tid.load(SeqCst);
+        if tid == NO_TID {
+            return;
+        }
+        let (cur, cur_hp) = protect(&self.state[tid]);
+        let desc = unsafe { cur.deref() };
+        if first == self.head.load(SeqCst) && !next.is_null() {
+            let new = OpDesc::new(desc.phase, false, false, desc.node.load(SeqCst));
+            let old = self.replace_desc(tid, cur, cur_hp, new);
+            // The dequeuer releases `first` when it is done, so we do not free it here.
+            let _ = self.head.compare_and_set(first, next, SeqCst);
+            drop(first_hp);
+            if let Some(old) = old {
+                unsafe { old.free() };
+            }
+        }
+    }
+}
+
+impl<T> Drop for WaitFreeQueue<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // The value of the sentinel is either popped or uninitialized.
+            let sentinel = self.head.load(Relaxed).into_owned();
+            let mut ptr = sentinel.next.load(Relaxed);
+            drop(sentinel);
+            while !ptr.is_null() {
+                let mut node = ptr.into_owned();
+                ManuallyDrop::drop(&mut (*node).data);
+                ptr = node.next.load(Relaxed);
+            }
+            for desc in self.state.iter() {
+                drop(desc.load(Relaxed).into_owned());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread::spawn;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn st_queue_push_pop_many() {
+        let q = WaitFreeQueue::new(1);
+        for i in 0..1000 {
+            q.push(i, 0);
+        }
+        for i in 0..1000 {
+            assert_eq!(q.pop(0), Some(i));
+        }
+        assert_eq!(q.pop(0), None);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn do_drop() {
+        lazy_static! {
+            static ref COUNT: AtomicUsize = AtomicUsize::new(0);
+        }
+        struct MustDrop;
+        impl Drop for MustDrop {
+            fn drop(&mut self) {
+                COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        {
+            let q = WaitFreeQueue::new(1);
+            for _ in 0..100 {
+                q.push(MustDrop, 0);
+            }
+            for _ in 0..50 {
+                q.pop(0);
+            }
+        }
+        assert_eq!(COUNT.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn stress_test() {
+        const N_THREADS: usize = 8;
+        const N: usize = 1024 * 32;
+
+        let source = Arc::new(WaitFreeQueue::new(N_THREADS));
+        let sink = Arc::new(WaitFreeQueue::new(N_THREADS));
+
+        for n in 0..N {
+            source.push(n, 0);
+        }
+
+        let threads = (0..N_THREADS)
+            .map(|tid| {
+                let source = source.clone();
+                let sink = sink.clone();
+                spawn(move || while let Some(i) = source.pop(tid) {
+                    sink.push(i, tid);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        let mut v = Vec::with_capacity(N);
+        while let Some(i) = sink.pop(0) {
+            v.push(i);
+        }
+        v.sort();
+        assert_eq!(v.len(), N);
+        for (i, n) in v.into_iter().enumerate() {
+            assert_eq!(i, n);
+        }
+    }
+}
@@ -1,6 +1,7 @@
-#![feature(test)]
+#![feature(test, asm, repr_align, attr_literals, thread_local_state)]
 // TODO: remove this
 #![feature(const_fn, const_atomic_usize_new)]
+#![cfg_attr(target_pointer_width = "32", feature(integer_atomics))]
 
 // #![feature(alloc_system, global_allocator, allocator_api)]
 // extern crate alloc_system;
@@ -16,6 +17,10 @@ extern crate lazy_static;
 #[cfg(test)]
 extern crate rand;
 
+pub mod alloc;
+pub mod atomic;
 pub mod nothing;
 pub mod ebr;
 pub mod hp;
+pub mod counter;
+pub mod pool;
@@ -0,0 +1,200 @@
+/// A bounded MPMC queue backed by a fixed array, as described by Dmitry Vyukov.
+///
+/// Each slot has a sequence number which tells whether the slot is ready to be written to or read
+/// from in the current lap around the buffer. Since slots are recycled in place, the queue never
+/// allocates after construction, and so there is nothing to reclaim. This makes it a useful
+/// baseline when we want to separate the cost of allocating nodes from the cost of reclaiming
+/// them.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, Acquire, Release};
+
+/// One slot in the buffer.
+struct Slot<T> {
+    /// If `sequence == pos`, the slot is empty and ready to be written for position `pos`.  If
+    /// `sequence == pos + 1`, the slot holds the data written at position `pos`.
+    sequence: AtomicUsize,
+    data: UnsafeCell<Option<T>>,
+}
+
+pub struct ArrayQueue<T> {
+    buffer: Box<[Slot<T>]>,
+    mask: usize,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for ArrayQueue<T> {}
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    /// Make a new queue which can hold at least `capacity` elements. The capacity is rounded up
+    /// to the next power of two.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = ::std::cmp::max(capacity, 2).next_power_of_two();
+        let buffer = (0..capacity)
+            .map(|i| {
+                Slot {
+                    sequence: AtomicUsize::new(i),
+                    data: UnsafeCell::new(None),
+                }
+            })
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        Self {
+            buffer,
+            mask: capacity - 1,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Return the number of slots in the queue.
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Push `t` onto the queue. If the queue is full we give `t` back.
+    pub fn push(&self, t: T) -> Result<(), T> {
+        let mut pos = self.tail.load(Relaxed);
+        loop {
+            let slot = &self.buffer[pos & self.mask];
+            let seq = slot.sequence.load(Acquire);
+            let diff = seq as isize - pos as isize;
+            if diff == 0 {
+                // The slot is free for this lap. Try to claim it.
+                match self.tail.compare_exchange_weak(pos, pos + 1, Relaxed, Relaxed) {
+                    Ok(_) => {
+                        unsafe {
+                            *slot.data.get() = Some(t);
+                        }
+                        slot.sequence.store(pos + 1, Release);
+                        return Ok(());
+                    }
+                    Err(new_pos) => pos = new_pos,
+                }
+            } else if diff < 0 {
+                // The slot still holds data from the previous lap, so the queue is full.
+                return Err(t);
+            } else {
+                // Some other thread has claimed `pos`. Reload and try again.
+                pos = self.tail.load(Relaxed);
+            }
+        }
+    }
+
+    /// Pop the first element of the queue, if any.
+    pub fn pop(&self) -> Option<T> {
+        let mut pos = self.head.load(Relaxed);
+        loop {
+            let slot = &self.buffer[pos & self.mask];
+            let seq = slot.sequence.load(Acquire);
+            let diff = seq as isize - (pos + 1) as isize;
+            if diff == 0 {
+                match self.head.compare_exchange_weak(pos, pos + 1, Relaxed, Relaxed) {
+                    Ok(_) => {
+                        let data = unsafe { (*slot.data.get()).take() };
+                        // Mark the slot as free for the next lap.
+                        slot.sequence.store(pos + self.mask + 1, Release);
+                        return data;
+                    }
+                    Err(new_pos) => pos = new_pos,
+                }
+            } else if diff < 0 {
+                // Nothing is written to the slot yet, so the queue is empty.
+                return None;
+            } else {
+                pos = self.head.load(Relaxed);
+            }
+        }
+    }
+
+    /// Count the number of elements in the queue. This is only a snapshot, and may be outdated
+    /// if other threads are operating on the queue.
+    pub fn len(&self) -> usize {
+        loop {
+            let tail = self.tail.load(Acquire);
+            let head = self.head.load(Acquire);
+            if self.tail.load(Acquire) == tail {
+                return tail.wrapping_sub(head);
+            }
+        }
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread::spawn;
+    use std::sync::Arc;
+
+    #[test]
+    fn st_queue_push_pop_many() {
+        let q: ArrayQueue<u32> = ArrayQueue::new(128);
+        for i in 0..100 {
+            assert!(q.push(i).is_ok());
+        }
+        assert_eq!(q.len(), 100);
+        for i in 0..100 {
+            assert_eq!(q.pop(), Some(i));
+        }
+        assert_eq!(q.pop(), None);
+    }
+
+    #[test]
+    fn full() {
+        let q: ArrayQueue<u32> = ArrayQueue::new(4);
+        for i in 0..4 {
+            assert!(q.push(i).is_ok());
+        }
+        assert_eq!(q.push(4), Err(4));
+        assert_eq!(q.pop(), Some(0));
+        assert!(q.push(4).is_ok());
+        for i in 1..5 {
+            assert_eq!(q.pop(), Some(i));
+        }
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn stress_test() {
+        const N_THREADS: usize = 16;
+        const N: usize = 1024 * 256;
+
+        let source = Arc::new(ArrayQueue::new(N));
+        let sink = Arc::new(ArrayQueue::new(N));
+
+        for n in 0..N {
+            assert!(source.push(n).is_ok());
+        }
+
+        let threads = (0..N_THREADS)
+            .map(|_thread_id| {
+                let source = source.clone();
+                let sink = sink.clone();
+                spawn(move || while let Some(i) = source.pop() {
+                    assert!(sink.push(i).is_ok());
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        let mut v = Vec::with_capacity(N);
+        while let Some(i) = sink.pop() {
+            v.push(i);
+        }
+        v.sort();
+        for (i, n) in v.into_iter().enumerate() {
+            assert_eq!(i, n);
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::sync::atomic::Ordering::{SeqCst};
 use super::atomic::{Owned, Atomic, Ptr};
 
 use std::mem::ManuallyDrop;
+use std::borrow::Borrow;
 
 #[derive(Debug)]
 pub struct Node<T> {
@@ -100,19 +101,26 @@ impl<T> List<T> {
             }
         }
     }
-}
 
-impl<T: PartialEq> List<T> {
-    /// Return `true` if the list contains the given value.
-    pub fn contains(&self, value: &T) -> bool {
+    /// Return the first element for which `f` returns `true`. Nodes are never freed, but the
+    /// element may be removed while we hold the reference.
+    ///
+    /// Removing moves the element out of the node, and the thread which removed it may drop it
+    /// while we are reading it. That is only safe if `T` has no destructor, so we require
+    /// `T: Copy`.
+    pub fn find<F>(&self, mut f: F) -> Option<&T>
+    where
+        T: Copy,
+        F: FnMut(&T) -> bool,
+    {
         'outer: loop {
             let mut node_ptr = self.head.load(SeqCst);
-            let mut node;
+            let mut node: &Node<T>;
 
             while !node_ptr.is_null() {
                 node = unsafe { node_ptr.deref() };
-                if *node.data == *value {
-                    return true;
+                if f(&node.data) {
+                    return Some(&node.data);
                 }
                 node_ptr = node.next.load(SeqCst);
                 if node_ptr.tag() != 0 {
@@ -120,16 +128,29 @@ impl<T: PartialEq> List<T> {
                     continue 'outer;
                 }
             }
-            return false
+            return None;
         }
     }
 
-    /// Remove the first node in the list where `node.data == key`
+    /// Return the first element which borrows as `key`. This is useful for key/value records,
+    /// where `T: Borrow<K>` extracts the key. As with `find`, we require `T: Copy`.
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+    where
+        T: Copy + Borrow<Q>,
+        Q: PartialEq,
+    {
+        self.find(|t| t.borrow() == key)
+    }
+
+    /// Remove and return the first element for which `f` returns `true`.
     ///
     /// Note that this method causes the list to not be lock-free, since
     /// threads wanting to insert a node after this or remove the next node
     /// will be stuck forever if a thread tags the current node and then dies.
-    pub fn remove(&self, value: &T) -> Option<T> {
+    pub fn remove_if<F>(&self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
         // Rust does not have tail-call optimization guarantees, so we have to use a loop here, in
         // order not to blow the stack.
         'outer: loop {
@@ -144,7 +165,7 @@ impl<T: PartialEq> List<T> {
             loop {
                 current_node = unsafe { current_ptr.deref() };
 
-                if *current_node.data == *value {
+                if f(&current_node.data) {
                     // Now we want to remove the current node from the list.  We first need to mark
                     // this node as 'to-be-deleted', by tagging its next pointer. When doing this,
                     // we avoid that other threads are inserting something after the current node,
@@ -188,11 +209,157 @@ impl<T: PartialEq> List<T> {
                     }
 
                     if current_ptr.is_null() {
-                        // we've reached the end of the list, without finding our value.
+                        // we've reached the end of the list, without finding a match.
                         return None;
                     }
                 }
             }
         }
     }
+
+    /// Replace the first element for which `f` returns `Some(new)` with `new`, and return the old
+    /// element. The element is replaced by swinging the pointer to its node to a new node, so
+    /// concurrent readers see either the old or the new element, never a partial write.
+    ///
+    /// If we fail to swing the pointer because of concurrent operations, we drop `new` and restart
+    /// from the head, so `f` may be called more than once for an element.
+    pub fn update<F>(&self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> Option<T>,
+    {
+        'outer: loop {
+            let mut current_atomic_ptr = &self.head;
+            let mut current_ptr = current_atomic_ptr.load(SeqCst);
+
+            while !current_ptr.is_null() {
+                let current_node: &Node<T> = unsafe { current_ptr.deref() };
+                let next_ptr = current_node.next.load(SeqCst);
+                if next_ptr.tag() != 0 {
+                    // Some other thread is removing this node.
+                    continue 'outer;
+                }
+                let new = match f(&current_node.data) {
+                    Some(new) => new,
+                    None => {
+                        current_atomic_ptr = &current_node.next;
+                        current_ptr = next_ptr;
+                        continue;
+                    }
+                };
+                // Mark the node, as in `remove_if`, so that the successor we give the new node
+                // stays its successor.
+                if current_node
+                    .next
+                    .compare_and_set(next_ptr, next_ptr.with_tag(1), SeqCst)
+                    .is_err()
+                {
+                    continue 'outer;
+                }
+                let mut new_node = Node::new(new);
+                new_node.next = Atomic::from_ptr(next_ptr);
+                let new_ptr = Owned::new(new_node).into_ptr();
+                match current_atomic_ptr.compare_and_set(current_ptr, new_ptr, SeqCst) {
+                    Ok(_) => unsafe {
+                        let data = ::std::ptr::read(&current_node.data);
+                        // leak node
+                        return Some(ManuallyDrop::into_inner(data));
+                    },
+                    Err(_) => {
+                        let _res = current_node.next.compare_and_set(
+                            next_ptr.with_tag(1),
+                            next_ptr,
+                            SeqCst,
+                        );
+                        // `new_ptr` was never in the list, so we can free it right away.
+                        unsafe {
+                            let new_node = new_ptr.into_owned();
+                            drop(ManuallyDrop::into_inner(::std::ptr::read(&(*new_node).data)));
+                        }
+                        continue 'outer;
+                    }
+                }
+            }
+            return None;
+        }
+    }
+
+    /// Remove all elements for which `f` returns `false`, in one pass over the list.
+    ///
+    /// If we fail to remove a node because of concurrent operations, we restart from the head, so
+    /// `f` may be called more than once for an element.
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        'outer: loop {
+            let mut current_atomic_ptr = &self.head;
+            let mut current_ptr = current_atomic_ptr.load(SeqCst);
+
+            while !current_ptr.is_null() {
+                let current_node: &Node<T> = unsafe { current_ptr.deref() };
+                let next_ptr = current_node.next.load(SeqCst);
+                if next_ptr.tag() != 0 {
+                    // Some other thread is removing this node.
+                    continue 'outer;
+                }
+                if f(&current_node.data) {
+                    current_atomic_ptr = &current_node.next;
+                    current_ptr = next_ptr;
+                    continue;
+                }
+                if current_node
+                    .next
+                    .compare_and_set(next_ptr, next_ptr.with_tag(1), SeqCst)
+                    .is_err()
+                {
+                    continue 'outer;
+                }
+                match current_atomic_ptr.compare_and_set(current_ptr, next_ptr, SeqCst) {
+                    Ok(_) => unsafe {
+                        let data = ::std::ptr::read(&current_node.data);
+                        // leak node
+                        drop(ManuallyDrop::into_inner(data));
+                        current_ptr = next_ptr;
+                    },
+                    Err(_) => {
+                        let _res = current_node.next.compare_and_set(
+                            next_ptr.with_tag(1),
+                            next_ptr,
+                            SeqCst,
+                        );
+                        continue 'outer;
+                    }
+                }
+            }
+            return;
+        }
+    }
+}
+
+impl<T: PartialEq> List<T> {
+    /// Return `true` if the list contains the given value.
+    pub fn contains(&self, value: &T) -> bool {
+        'outer: loop {
+            let mut node_ptr = self.head.load(SeqCst);
+            let mut node;
+
+            while !node_ptr.is_null() {
+                node = unsafe { node_ptr.deref() };
+                if *node.data == *value {
+                    return true;
+                }
+                node_ptr = node.next.load(SeqCst);
+                if node_ptr.tag() != 0 {
+                    // restart, as we're being (or has been) removed
+                    continue 'outer;
+                }
+            }
+            return false
+        }
+    }
+
+    /// Remove the first node in the list where `node.data == key`
+    pub fn remove(&self, value: &T) -> Option<T> {
+        self.remove_if(|t| *t == *value)
+    }
 }
@@ -7,3 +7,4 @@ pub mod atomic;
 
 pub mod queue;
 pub mod list;
+pub mod array_queue;
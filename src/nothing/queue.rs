@@ -113,6 +113,44 @@ impl<T> Queue<T> {
         }
     }
 
+    /// Push all elements of `iter`. The nodes are linked together before we link the first of
+    /// them into the queue, so the whole batch takes one CAS, and the elements are next to each
+    /// other in the queue.
+    pub fn push_batch<I>(&self, iter: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut iter = iter.into_iter();
+        let first = match iter.next() {
+            Some(t) => Owned::new(Node::new(t)).into_ptr(),
+            None => return,
+        };
+        let mut last = first;
+        for t in iter {
+            let node = Owned::new(Node::new(t)).into_ptr();
+            unsafe { last.deref() }.next.store(node, Relaxed);
+            last = node;
+        }
+        loop {
+            let tail = self.tail.load(Acquire);
+            let t = unsafe { tail.deref() };
+            let next = t.next.load(Acquire);
+            if !next.is_null() {
+                let _ = self.tail.compare_and_set(tail, next, Release);
+                continue;
+            }
+            if t.next
+                .compare_and_set(Ptr::null(), first, Release)
+                .is_ok()
+            {
+                // Move the tail past the whole batch. If we fail, other threads will move it one
+                // node at a time.
+                let _ = self.tail.compare_and_set(tail, last, Release);
+                return;
+            }
+        }
+    }
+
     pub fn pop(&self) -> Option<T> {
         let head: Ptr<Node<T>> = self.head.load(Acquire);
         let h: &Node<T> = unsafe { head.deref() };
@@ -136,6 +174,47 @@ impl<T> Queue<T> {
         }
     }
 
+    /// Pop up to `n` elements, with one CAS on the head.
+    pub fn pop_batch(&self, n: usize) -> Vec<T> {
+        let mut ret = Vec::new();
+        if n == 0 {
+            return ret;
+        }
+        loop {
+            let head = self.head.load(Acquire);
+            // Find the node which will be the new sentinel.
+            let mut last = head;
+            let mut count = 0;
+            while count < n {
+                let next = unsafe { last.deref() }.next.load(Acquire);
+                if next.is_null() {
+                    break;
+                }
+                last = next;
+                count += 1;
+            }
+            if count == 0 {
+                return ret;
+            }
+            if self.head.compare_and_set(head, last, Release).is_err() {
+                continue;
+            }
+            // Like in `pop`, the element of each node is in the node after it.
+            ret.reserve(count);
+            let mut node = head;
+            while node != last {
+                unsafe {
+                    let next = node.deref().next.load(Acquire);
+                    if let Some(t) = ::std::ptr::read(&next.deref().data) {
+                        ret.push(t);
+                    }
+                    node = next;
+                }
+            }
+            return ret;
+        }
+    }
+
     /// Count the number of elements in the queue.
     /// This is typically not a operation we need,
     /// but it is practical to have for testing
@@ -216,4 +295,16 @@ mod test {
         }
         assert_eq!(q.len(), 10);
     }
+
+    #[test]
+    fn st_queue_batch() {
+        let q: Queue<u32> = Queue::new();
+        q.push_batch(0..100);
+        q.push_batch(None);
+        assert_eq!(q.pop_batch(30), (0..30).collect::<Vec<_>>());
+        assert_eq!(q.pop(), Some(30));
+        assert_eq!(q.pop_batch(1000), (31..100).collect::<Vec<_>>());
+        assert!(q.pop_batch(5).is_empty());
+        assert!(q.is_empty());
+    }
 }
@@ -0,0 +1,205 @@
+/// Type-stable memory pools.
+///
+/// Instead of freeing memory, we put it in a lock-free pool, from where it is handed out again to
+/// the next allocation of the same size and alignment. Memory in a pool is never given back to
+/// the global allocator, so it stays valid to read for as long as the program runs. This avoids
+/// allocator calls on the hot path, at the cost of keeping the peak memory usage of every node
+/// type.
+///
+/// The pool only decides where memory goes when it is freed; it does not decide when it is safe
+/// to free it. With the `ebr-pool` and `hp-pool` features, `Pool` is the allocator of the scheme,
+/// so memory retired with `ebr::Pin::add_garbage` or `hp::HazardPtr::free` is still only put in
+/// the pool after the scheme has decided that no thread can access it.
+///
+/// There is one pool for each size and alignment, and not for each type, since we cannot have
+/// generic statics. In practice this is one pool for each node type.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+
+use alloc::{Alloc, Global};
+use nothing::atomic::{AtomicVersioned, Ptr};
+
+/// A free block of memory in a pool. The link to the next block is stored in the block itself,
+/// so we can only pool memory which has room for, and is aligned as, a `usize`.
+struct Block {
+    next: AtomicUsize,
+}
+
+/// A lock-free stack of free blocks of the same size and alignment.
+///
+/// When we pop a block, another thread may pop the same block, reuse it, and free it back to
+/// the pool before our CAS, which is the ABA problem. Since the head is versioned, our CAS fails
+/// if this happened. While we read `next` of the head, another thread may also write to the
+/// block, but since the memory is never freed the read is safe, and the CAS fails in that case.
+struct Stack {
+    head: AtomicVersioned<Block>,
+    len: AtomicUsize,
+}
+
+impl Stack {
+    fn new() -> Self {
+        Stack {
+            head: AtomicVersioned::null(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, block: usize) {
+        let new = Ptr::from_raw(block as *const Block);
+        let mut head = self.head.load(SeqCst);
+        loop {
+            unsafe { new.deref() }.next.store(head.ptr.as_raw() as usize, SeqCst);
+            match self.head.compare_and_set(head, new, SeqCst) {
+                Ok(_) => break,
+                Err(h) => head = h,
+            }
+        }
+        self.len.fetch_add(1, Relaxed);
+    }
+
+    fn pop(&self) -> Option<usize> {
+        let mut head = self.head.load(SeqCst);
+        loop {
+            if head.ptr.is_null() {
+                return None;
+            }
+            // If the block was reused, `next` may be anything, so we must not check it.
+            let next = Ptr::from_data(unsafe { head.ptr.deref() }.next.load(SeqCst));
+            match self.head.compare_and_set(head, next, SeqCst) {
+                Ok(_) => {
+                    self.len.fetch_sub(1, Relaxed);
+                    return Some(head.ptr.as_raw() as usize);
+                }
+                Err(h) => head = h,
+            }
+        }
+    }
+}
+
+lazy_static! {
+    /// All pools, keyed on the size and alignment of their blocks. The pools are leaked, since
+    /// the memory in them must never be freed.
+    static ref POOLS: Mutex<HashMap<(usize, usize), &'static Stack>> = {
+        Mutex::new(HashMap::new())
+    };
+}
+
+thread_local! {
+    /// A cache of `POOLS`, so that we only take the lock the first time a thread uses a pool.
+    static POOL_CACHE: RefCell<HashMap<(usize, usize), &'static Stack>> = {
+        RefCell::new(HashMap::new())
+    };
+}
+
+/// Returns the pool for `T`, or `None` if `T` is too small or not aligned enough to be pooled.
+fn stack<T>() -> Option<&'static Stack> {
+    let key = (mem::size_of::<T>(), mem::align_of::<T>());
+    if key.0 < mem::size_of::<Block>() || key.1 < mem::align_of::<Block>() {
+        return None;
+    }
+    let global = || {
+        *POOLS.lock().unwrap().entry(key).or_insert_with(|| unsafe {
+            &*Box::into_raw(Box::new(Stack::new()))
+        })
+    };
+    // The cache may already be destroyed if we are called from a thread local destructor, for
+    // instance when `ebr` frees the garbage of an exiting thread.
+    let cached = POOL_CACHE
+        .try_with(|c| *c.borrow_mut().entry(key).or_insert_with(&global))
+        .ok();
+    Some(cached.unwrap_or_else(global))
+}
+
+/// The allocator of the type-stable pools. Allocations take memory from the pool of their size
+/// and alignment if it is not empty, and freed memory goes back to the pool. Types that are
+/// smaller than a `usize` are not pooled, and go to `Global`.
+#[derive(Debug)]
+pub struct Pool;
+
+impl Pool {
+    /// Returns the number of free blocks in the pool of `T`.
+    pub fn len<T>() -> usize {
+        stack::<T>().map(|s| s.len.load(Relaxed)).unwrap_or(0)
+    }
+}
+
+impl Alloc for Pool {
+    fn alloc<T>() -> *mut T {
+        match stack::<T>().and_then(|s| s.pop()) {
+            Some(block) => block as *mut T,
+            None => Global::alloc(),
+        }
+    }
+
+    unsafe fn free<T>(ptr: *mut T) {
+        match stack::<T>() {
+            Some(s) => s.push(ptr as usize),
+            None => Global::free(ptr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::ptr;
+    use std::sync::{Arc, Barrier};
+    use std::thread::spawn;
+
+    /// A type of its own size, so that other tests do not use the same pool.
+    struct Big([usize; 13]);
+
+    #[test]
+    fn reuse() {
+        let a = Pool::alloc::<Big>();
+        unsafe { Pool::free(a) };
+        assert!(Pool::len::<Big>() >= 1);
+        // The pool is a stack, so we get the last freed block back.
+        let b = Pool::alloc::<Big>();
+        assert_eq!(a, b);
+        unsafe { Pool::free(b) };
+    }
+
+    #[test]
+    fn small_types_are_not_pooled() {
+        let a = Pool::alloc::<u8>();
+        unsafe { Pool::free(a) };
+        assert_eq!(Pool::len::<u8>(), 0);
+    }
+
+    #[test]
+    fn concurrent() {
+        const N_THREADS: usize = 4;
+        const N: usize = 1024 * 4;
+        struct Node([usize; 11]);
+
+        let barrier = Arc::new(Barrier::new(N_THREADS));
+        let threads = (0..N_THREADS)
+            .map(|i| {
+                let barrier = barrier.clone();
+                spawn(move || {
+                    barrier.wait();
+                    for j in 0..N {
+                        let n = Pool::alloc::<Node>();
+                        unsafe {
+                            ptr::write(n, Node([i * N + j; 11]));
+                        }
+                        // If two threads got the same block, one of them sees the other's write.
+                        assert!(unsafe { &*n }.0.iter().all(|&x| x == i * N + j));
+                        unsafe { Pool::free(n) };
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads.into_iter() {
+            assert!(t.join().is_ok());
+        }
+        assert!(Pool::len::<Node>() <= N_THREADS);
+    }
+}